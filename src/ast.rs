@@ -1,3 +1,5 @@
+use std::fmt;
+
 /// A programme is a sequence of operators.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Programme {
@@ -45,33 +47,474 @@ pub enum Operator {
     DeleteEmpty,
     /// `f` - flatten nested arrays by one level
     Flatten,
+    /// `e` - pair each element with its index
+    Enumerate,
+    /// `Z` - transpose rows and columns, padding short rows
+    Transpose,
+    /// `z` - pair up the elements of parallel arrays, stopping at the shortest
+    Zip,
     /// `d` - dedupe with counts
     DedupeWithCounts,
-    /// `D<selection>` - dedupe by selection with counts
-    DedupeSelectionWithCounts(Selection),
+    /// `D<selection>` or `D{<programme>}` - dedupe by key with counts
+    DedupeSelectionWithCounts(Key),
     /// `+` - sum numeric values
     Sum,
+    /// `%<statistic>` - reduce numeric values to a statistic, e.g. `%max`, `%p99`
+    Statistic(Statistic),
+    /// `%describe` - reduce numeric values to a row of summary statistics
+    Describe,
+    /// `\<scan>` - replace each number with a running computation, e.g. `\+`
+    Scan(Scan),
+    /// `w<n>` - sliding windows of n consecutive elements
+    Window(usize),
+    /// `v` - reverse the order of elements
+    Reverse,
+    /// `~` - shuffle elements into a random order
+    Shuffle,
+    /// `~<n>` or `~<fraction>` - keep a random sample of elements
+    Sample(Sample),
     /// `#` - count elements
     Count,
     /// `c` - columnate
     Columnate,
+    /// `h`, `h%`, `h+` or `h%+` - draw `[count, label]` rows as a bar chart,
+    /// with a column of percentages of the total and one of cumulative ones
+    Histogram { percent: bool, cumulative: bool },
+    /// `H` - draw a series of numbers as a sparkline
+    Sparkline,
     /// `p<selection>` - partition array at indices
     Partition(Selection),
     /// `o` - sort descending
     SortDescending,
     /// `O` - sort ascending
     SortAscending,
+    /// `o{<programme>}` - sort descending by the key the programme computes
+    SortDescendingBy(Programme),
+    /// `O{<programme>}` - sort ascending by the key the programme computes
+    SortAscendingBy(Programme),
     /// Selection - select elements by index, slice, or multi-select
     Selection(Selection),
     /// `/<regex>/` - filter keep matching elements
     /// `!/<regex>/` - filter remove matching elements (keep non-matching)
     Filter { pattern: String, negate: bool },
+    /// `F"<template>"` - render each element with a template of literal text
+    /// and `{...}` placeholders for its fields
+    Format(Vec<FormatPart>),
+    /// `={<expression>}` or `=<expression>` - replace each element with the
+    /// number computed from its fields
+    Arithmetic(Expr),
+    /// `k[<selection>]p`, `k[<selection>]/<bucket>` or `k[<selection>]f"<pattern>"`
+    /// - parse, truncate or format times, of each element or its selected fields
+    Time {
+        selection: Option<Selection>,
+        op: TimeOp,
+    },
+    /// `kd<from>,<to>` - replace each element with the seconds from the time
+    /// in one field to the time in another
+    Duration { from: i64, to: i64 },
+    /// `!{<command>}` - pipe each element through a shell command, running it
+    /// once per element, or with `!|{<command>}` once for all of them
+    Command { command: String, batched: bool },
+    /// Conjunction of filters. Never produced by the parser: the optimiser
+    /// merges adjacent filters into one so each element is matched in one pass.
+    /// Prints as the filters it was merged from.
+    FilterAll(Vec<FilterClause>),
     /// `m/<regex>/` - extract all regex matches from each element
     Match { pattern: String },
-    /// `g<selection>` - group by the value(s) at the selection
-    GroupBy(Selection),
+    /// `g<selection>` or `g{<programme>}` - group by key
+    GroupBy(Key),
+    /// `g<key>{<aggregate>, ...}` - group by key, reducing each group to one
+    /// row of the key followed by the aggregates
+    GroupAggregate {
+        key: Key,
+        aggregates: Vec<Aggregate>,
+    },
+    /// `*[?!]<input>:<selection>[=<selection>]` - join each row with the rows
+    /// of a named input whose key, at the second selection, equals its own
+    JoinInput {
+        input: String,
+        kind: JoinKind,
+        left: Selection,
+        right: Selection,
+    },
+    /// `*<op><input>[:<selection>[=<selection>]]` - a set operation between the
+    /// elements and the named input, by whole element or by the keys at the
+    /// selections
+    SetInput {
+        input: String,
+        operation: SetOperation,
+        key: Option<(Selection, Selection)>,
+    },
+    /// `*/<input>[:<selection>]` - keep elements containing any line of the
+    /// named input, or with a selection whose selected fields equal one.
+    /// `*!/` drops them instead, and `*~/` reads the lines as regexes.
+    PatternFilter {
+        input: String,
+        regex: bool,
+        negate: bool,
+        selection: Option<Selection>,
+    },
+    /// `*=<input>[:<selection>]["<default>"]` - replace each value, or the
+    /// selected fields, with its entry in a two-column dictionary input.
+    /// `*=!` drops unmapped values instead.
+    Map {
+        input: String,
+        selection: Option<Selection>,
+        unmapped: Unmapped,
+    },
     /// `;` - no-op separator
     NoOp,
+    /// `{<programme>}` - run a sub-programme on each element, then restore the depth
+    Block(Programme),
+    /// `(<programme>|<programme>...)` - run each branch on a copy of the value,
+    /// collecting the results into an array
+    Fork(Vec<Programme>),
+    /// `?[<selection>]/<regex>/{<then>}[{<else>}]` - apply `then` to each element
+    /// matching the regex (in the selected fields), and `else` to the rest
+    Conditional {
+        selection: Option<Selection>,
+        pattern: String,
+        then: Programme,
+        otherwise: Option<Programme>,
+    },
+    /// `&<name>` - invoke a macro; replaced by the macro body when expanded
+    Macro(String),
+}
+
+/// A single regex predicate of a merged filter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterClause {
+    pub pattern: String,
+    pub negate: bool,
+}
+
+/// The key of an element for grouping, deduping or sorting.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Key {
+    /// The value(s) at the selection
+    Selection(Selection),
+    /// The result of running the programme on a copy of the element
+    Programme(Programme),
+}
+
+/// Which rows a `*` join keeps.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JoinKind {
+    /// `*` - each row paired with each matching row of the input
+    Inner,
+    /// `*?` - as inner, but rows without a match are kept unpaired
+    Left,
+    /// `*!` - only the rows without a match, unpaired
+    Anti,
+}
+
+/// A set operation of `*`, spelt as in Python. The results are distinct.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SetOperation {
+    /// `*|` - elements of either
+    Union,
+    /// `*&` - elements also in the input
+    Intersection,
+    /// `*-` - elements not in the input
+    Difference,
+    /// `*^` - elements in one but not both
+    SymmetricDifference,
+}
+
+/// What `*=` does with a value missing from its dictionary.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Unmapped {
+    /// Leave it as it is
+    Keep,
+    /// `"<default>"` - replace it with the default
+    Default(String),
+    /// `*=!` - drop it, or with a selection the whole element
+    Drop,
+}
+
+/// A part of an `F` template.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatPart {
+    /// Literal text, with `{{` and `}}` for braces
+    Literal(String),
+    /// `{[<selection>][|<separator>][%<spec>]}` - the selected fields, each
+    /// formatted with the spec and joined with the separator (default a
+    /// space), or without a selection the whole element
+    Field {
+        selection: Option<Selection>,
+        separator: Option<String>,
+        spec: Option<FormatSpec>,
+    },
+}
+
+/// A printf-style spec of an `F` placeholder: `%[-+0][<width>][.<precision>]<conversion>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatSpec {
+    /// `-` - align left rather than right
+    pub left: bool,
+    /// `+` - give positive numbers a sign
+    pub sign: bool,
+    /// `0` - pad numbers with zeros rather than spaces
+    pub zero: bool,
+    pub width: Option<usize>,
+    /// Digits after the point, minimum digits of an integer, or maximum
+    /// characters of a string
+    pub precision: Option<usize>,
+    pub conversion: Conversion,
+}
+
+/// The conversion of a [`FormatSpec`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Conversion {
+    /// `s` - the value as it prints
+    String,
+    /// `d` - a number, truncated to an integer
+    Integer,
+    /// `f` - a number with a fixed number of decimals, 6 by default
+    Fixed,
+    /// `e` - a number in scientific notation
+    Exponent,
+    /// `x` - a number, truncated to an integer, in hexadecimal
+    Hex,
+}
+
+impl Conversion {
+    /// Every conversion, for parsing.
+    pub const ALL: [Conversion; 5] = [
+        Conversion::String,
+        Conversion::Integer,
+        Conversion::Fixed,
+        Conversion::Exponent,
+        Conversion::Hex,
+    ];
+
+    /// The letter of the conversion.
+    pub fn letter(self) -> char {
+        match self {
+            Conversion::String => 's',
+            Conversion::Integer => 'd',
+            Conversion::Fixed => 'f',
+            Conversion::Exponent => 'e',
+            Conversion::Hex => 'x',
+        }
+    }
+}
+
+/// What a `k` operator does to a time.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimeOp {
+    /// `p` or `p"<pattern>"` - parse a time into seconds since the epoch,
+    /// recognising common formats or with a strftime pattern
+    Parse(Option<String>),
+    /// `/<bucket>` - round a time down to the start of its bucket
+    Truncate(Bucket),
+    /// `f[<offset>]"<pattern>"` - format a time with a strftime pattern, in
+    /// UTC or at a fixed offset in seconds east of it
+    Format {
+        offset: Option<i32>,
+        pattern: String,
+    },
+}
+
+/// A bucket of `k/`: `[<count>]<unit>`, e.g. `15m`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bucket {
+    pub count: u32,
+    pub unit: TimeUnit,
+}
+
+/// A unit of time of a [`Bucket`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeUnit {
+    /// `s`
+    Second,
+    /// `m`
+    Minute,
+    /// `h`
+    Hour,
+    /// `d`
+    Day,
+}
+
+impl TimeUnit {
+    /// Every unit, for parsing.
+    pub const ALL: [TimeUnit; 4] = [
+        TimeUnit::Second,
+        TimeUnit::Minute,
+        TimeUnit::Hour,
+        TimeUnit::Day,
+    ];
+
+    /// The letter of the unit.
+    pub fn letter(self) -> char {
+        match self {
+            TimeUnit::Second => 's',
+            TimeUnit::Minute => 'm',
+            TimeUnit::Hour => 'h',
+            TimeUnit::Day => 'd',
+        }
+    }
+
+    /// The length of the unit in seconds.
+    pub fn seconds(self) -> u32 {
+        match self {
+            TimeUnit::Second => 1,
+            TimeUnit::Minute => 60,
+            TimeUnit::Hour => 60 * 60,
+            TimeUnit::Day => 24 * 60 * 60,
+        }
+    }
+}
+
+/// An arithmetic expression of `=`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// A non-negative number literal
+    Number(f64),
+    /// `$<index>` - a field of the element (0-based, negative counts from the
+    /// end), or `$` for the whole element
+    Field(Option<i64>),
+    /// `-<expr>`
+    Negate(Box<Expr>),
+    /// `<expr> <op> <expr>`
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    /// `<function>(<expr>, ...)`
+    Call(Function, Vec<Expr>),
+}
+
+/// A binary operator of an arithmetic expression.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    /// `+`
+    Add,
+    /// `-`
+    Subtract,
+    /// `*`
+    Multiply,
+    /// `/`
+    Divide,
+    /// `%` - remainder, with the sign of the dividend
+    Remainder,
+    /// `**` - power, binding tighter than negation: `-2 ** 2` is -4
+    Power,
+}
+
+/// A function of an arithmetic expression.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    /// `abs(x)`
+    Abs,
+    /// `ceil(x)`
+    Ceil,
+    /// `floor(x)`
+    Floor,
+    /// `round(x)` or `round(x, digits)` - halves round away from zero
+    Round,
+    /// `sqrt(x)`
+    Sqrt,
+    /// `exp(x)`
+    Exp,
+    /// `log(x)` - natural logarithm
+    Log,
+    /// `log2(x)`
+    Log2,
+    /// `log10(x)`
+    Log10,
+}
+
+impl Function {
+    /// Every function, for parsing.
+    pub const ALL: [Function; 9] = [
+        Function::Abs,
+        Function::Ceil,
+        Function::Floor,
+        Function::Round,
+        Function::Sqrt,
+        Function::Exp,
+        Function::Log,
+        Function::Log2,
+        Function::Log10,
+    ];
+
+    /// The name the function is called by.
+    pub fn name(self) -> &'static str {
+        match self {
+            Function::Abs => "abs",
+            Function::Ceil => "ceil",
+            Function::Floor => "floor",
+            Function::Round => "round",
+            Function::Sqrt => "sqrt",
+            Function::Exp => "exp",
+            Function::Log => "log",
+            Function::Log2 => "log2",
+            Function::Log10 => "log10",
+        }
+    }
+
+    /// The number of arguments the function takes, as a range.
+    pub fn arity(self) -> std::ops::RangeInclusive<usize> {
+        match self {
+            Function::Round => 1..=2,
+            _ => 1..=1,
+        }
+    }
+}
+
+/// A per-group reduction of `g<key>{...}`. The field is an index into each
+/// element; without one the whole element is used.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Aggregate {
+    /// `#` - number of elements in the group
+    Count,
+    /// `+[<index>]` - sum of the field
+    Sum(Option<i64>),
+    /// `min[<index>]` - smallest numeric value of the field
+    Min(Option<i64>),
+    /// `max[<index>]` - largest numeric value of the field
+    Max(Option<i64>),
+    /// `mean[<index>]` - mean of the numeric values of the field
+    Mean(Option<i64>),
+}
+
+/// A statistic computed by a `%` reducer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statistic {
+    /// `%min`
+    Min,
+    /// `%max`
+    Max,
+    /// `%mean`
+    Mean,
+    /// `%median`
+    Median,
+    /// `%p<n>` - the nth percentile, from 0 to 100
+    Percentile(f64),
+    /// `%stddev` - population standard deviation
+    StdDev,
+    /// `%var` - population variance
+    Variance,
+}
+
+/// A running computation of a `\` operator.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Scan {
+    /// `\+` - cumulative sum
+    Sum,
+    /// `\-` - difference from the previous number
+    Delta,
+    /// `\/` - change relative to the previous number
+    Rate,
+    /// `\m<n>` - mean of the last n numbers
+    MovingAverage(usize),
+}
+
+/// How much a `~` operator samples.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sample {
+    /// `~<n>` - exactly n elements, or all of them if there are fewer
+    Count(usize),
+    /// `~<fraction>` - each element with this probability, e.g. `~0.01`
+    Fraction(f64),
 }
 
 /// A selection is a comma-separated list of select items.
@@ -101,3 +544,1230 @@ pub struct Slice {
     pub end: Option<i64>,
     pub step: Option<i64>,
 }
+
+impl fmt::Display for Programme {
+    /// Canonical source for the programme, which parses back to the same programme.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut prev: Option<&Operator> = None;
+        for op in &self.operators {
+            if prev.is_some_and(|prev| needs_separator(prev, op)) {
+                write!(f, ";")?;
+            }
+            write!(f, "{}", op)?;
+            prev = Some(op);
+        }
+        Ok(())
+    }
+}
+
+impl Programme {
+    /// Render the programme with one operator group per line, indented by depth.
+    ///
+    /// A group is a run of operators at the same depth: `@` and `^` start a
+    /// new group, and `;` separators become line breaks.
+    pub fn pretty(&self) -> String {
+        let mut lines: Vec<String> = Vec::new();
+        let mut group: Vec<String> = Vec::new();
+        let mut navigation_only = true;
+        let mut depth = 0usize;
+        let flush = |group: &mut Vec<String>, lines: &mut Vec<String>, depth: usize| {
+            if !group.is_empty() {
+                lines.push(format!("{}{}", "  ".repeat(depth), group.join(" ")));
+                group.clear();
+            }
+        };
+        for op in &self.operators {
+            match op {
+                Operator::Descend | Operator::Ascend => {
+                    if !navigation_only {
+                        flush(&mut group, &mut lines, depth);
+                        navigation_only = true;
+                    }
+                    if *op == Operator::Descend {
+                        depth += 1;
+                    } else {
+                        depth = depth.saturating_sub(1);
+                    }
+                    group.push(op.to_string());
+                }
+                Operator::NoOp => {
+                    flush(&mut group, &mut lines, depth);
+                    navigation_only = true;
+                }
+                _ => {
+                    group.push(op.to_string());
+                    navigation_only = false;
+                }
+            }
+        }
+        flush(&mut group, &mut lines, depth);
+        lines.join("\n")
+    }
+}
+
+/// Returns true if `next` printed directly after `prev` would parse differently,
+/// e.g. the selections `0` and `1` would run together as `01`.
+fn needs_separator(prev: &Operator, next: &Operator) -> bool {
+    next.to_string()
+        .chars()
+        .next()
+        .is_some_and(|c| prev.continues_with(c))
+}
+
+impl Operator {
+    /// Returns true if this operator, printed, would read a following `c` as
+    /// more of itself.
+    fn continues_with(&self, c: char) -> bool {
+        // Only selections start with these
+        let selection = c.is_ascii_digit() || c == '-' || c == ':';
+        let name = c.is_ascii_alphanumeric() || c == '_';
+        match self {
+            Operator::Selection(_)
+            | Operator::LowercaseSelected(_)
+            | Operator::UppercaseSelected(_)
+            | Operator::ToNumberSelected(_)
+            | Operator::TrimSelected(_)
+            | Operator::Partition(_)
+            | Operator::DedupeSelectionWithCounts(Key::Selection(_))
+            // Not selections, but end in digits all the same
+            | Operator::Statistic(Statistic::Percentile(_))
+            | Operator::Scan(Scan::MovingAverage(_))
+            | Operator::Window(_)
+            | Operator::Sample(_)
+            | Operator::Duration { .. }
+            // A bare shuffle would take the digits as its sample size
+            | Operator::Shuffle => selection,
+            // A block would be read as a sort's key, or a conditional's
+            // else-branch
+            Operator::SortDescending
+            | Operator::SortAscending
+            | Operator::Conditional {
+                otherwise: None, ..
+            } => c == '{',
+            // A block would be read as a group's aggregates
+            Operator::GroupBy(Key::Selection(_)) => selection || c == '{',
+            Operator::GroupBy(_) => c == '{',
+            // An input name with no key would run into a following letter or
+            // digit
+            Operator::SetInput { key: None, .. }
+            | Operator::PatternFilter {
+                selection: None,
+                ..
+            }
+            | Operator::Map {
+                selection: None,
+                unmapped: Unmapped::Keep | Unmapped::Drop,
+                ..
+            } => selection || name,
+            // A key's selection would take a following `=` as the input's key
+            Operator::SetInput { key: Some(_), .. } | Operator::JoinInput { .. } => {
+                selection || c == '='
+            }
+            Operator::PatternFilter { .. } | Operator::Map { .. } => selection,
+            // A histogram would take a following `%` or `+` as its columns
+            Operator::Histogram {
+                percent,
+                cumulative,
+            } => (c == '%' && !percent && !cumulative) || (c == '+' && !cumulative),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operator::Split => write!(f, "s"),
+            Operator::SplitDelim(delim) => write!(f, "S{}", Delimiter(delim)),
+            Operator::Join => write!(f, "j"),
+            Operator::JoinDelim(delim) => write!(f, "J{}", Delimiter(delim)),
+            Operator::Descend => write!(f, "@"),
+            Operator::Ascend => write!(f, "^"),
+            Operator::Uppercase => write!(f, "u"),
+            Operator::UppercaseSelected(sel) => write!(f, "U{}", sel),
+            Operator::Lowercase => write!(f, "l"),
+            Operator::LowercaseSelected(sel) => write!(f, "L{}", sel),
+            Operator::Replace {
+                selection,
+                pattern,
+                replacement,
+            } => {
+                write!(f, "r")?;
+                if let Some(sel) = selection {
+                    write!(f, "{}", sel)?;
+                }
+                write!(f, "/{}/{}/", Slashed(pattern), Slashed(replacement))
+            }
+            Operator::ToNumber => write!(f, "n"),
+            Operator::ToNumberSelected(sel) => write!(f, "N{}", sel),
+            Operator::Trim => write!(f, "t"),
+            Operator::TrimSelected(sel) => write!(f, "T{}", sel),
+            Operator::DeleteEmpty => write!(f, "x"),
+            Operator::Flatten => write!(f, "f"),
+            Operator::Enumerate => write!(f, "e"),
+            Operator::Transpose => write!(f, "Z"),
+            Operator::Zip => write!(f, "z"),
+            Operator::DedupeWithCounts => write!(f, "d"),
+            Operator::DedupeSelectionWithCounts(key) => write!(f, "D{}", key),
+            Operator::Sum => write!(f, "+"),
+            Operator::Statistic(statistic) => write!(f, "%{}", statistic),
+            Operator::Describe => write!(f, "%describe"),
+            Operator::Scan(scan) => write!(f, "\\{}", scan),
+            Operator::Window(size) => write!(f, "w{}", size),
+            Operator::Reverse => write!(f, "v"),
+            Operator::Shuffle => write!(f, "~"),
+            Operator::Sample(sample) => write!(f, "~{}", sample),
+            Operator::Count => write!(f, "#"),
+            Operator::Columnate => write!(f, "c"),
+            Operator::Histogram {
+                percent,
+                cumulative,
+            } => {
+                write!(f, "h")?;
+                if *percent {
+                    write!(f, "%")?;
+                }
+                if *cumulative {
+                    write!(f, "+")?;
+                }
+                Ok(())
+            }
+            Operator::Sparkline => write!(f, "H"),
+            Operator::Partition(sel) => write!(f, "p{}", sel),
+            Operator::SortDescending => write!(f, "o"),
+            Operator::SortAscending => write!(f, "O"),
+            Operator::SortDescendingBy(key) => write!(f, "o{{{}}}", key),
+            Operator::SortAscendingBy(key) => write!(f, "O{{{}}}", key),
+            Operator::Selection(sel) => write!(f, "{}", sel),
+            Operator::Format(parts) => {
+                let template: String = parts.iter().map(FormatPart::to_string).collect();
+                write!(f, "F{}", Quoted(&template))
+            }
+            Operator::Arithmetic(expr) => write!(f, "={{{}}}", expr),
+            Operator::Time { selection, op } => {
+                write!(f, "k")?;
+                if let Some(selection) = selection {
+                    write!(f, "{}", selection)?;
+                }
+                write!(f, "{}", op)
+            }
+            Operator::Duration { from, to } => write!(f, "kd{},{}", from, to),
+            Operator::Command { command, batched } => {
+                let bar = if *batched { "|" } else { "" };
+                write!(f, "!{}{{{}}}", bar, command)
+            }
+            Operator::Filter { pattern, negate } => {
+                let bang = if *negate { "!" } else { "" };
+                write!(f, "{}/{}/", bang, Slashed(pattern))
+            }
+            Operator::FilterAll(clauses) => {
+                for clause in clauses {
+                    let bang = if clause.negate { "!" } else { "" };
+                    write!(f, "{}/{}/", bang, Slashed(&clause.pattern))?;
+                }
+                Ok(())
+            }
+            Operator::Match { pattern } => write!(f, "m/{}/", Slashed(pattern)),
+            Operator::GroupBy(key) => write!(f, "g{}", key),
+            Operator::GroupAggregate { key, aggregates } => {
+                write!(f, "g{}{{", key)?;
+                for (i, aggregate) in aggregates.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", aggregate)?;
+                }
+                write!(f, "}}")
+            }
+            Operator::NoOp => write!(f, ";"),
+            Operator::Block(programme) => write!(f, "{{{}}}", programme),
+            Operator::Fork(branches) => {
+                write!(f, "(")?;
+                for (i, branch) in branches.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "|")?;
+                    }
+                    write!(f, "{}", branch)?;
+                }
+                write!(f, ")")
+            }
+            Operator::Conditional {
+                selection,
+                pattern,
+                then,
+                otherwise,
+            } => {
+                write!(f, "?")?;
+                if let Some(sel) = selection {
+                    write!(f, "{}", sel)?;
+                }
+                write!(f, "/{}/{{{}}}", Slashed(pattern), then)?;
+                if let Some(otherwise) = otherwise {
+                    write!(f, "{{{}}}", otherwise)?;
+                }
+                Ok(())
+            }
+            Operator::Macro(name) => write!(f, "&{}", name),
+            Operator::PatternFilter {
+                input,
+                regex,
+                negate,
+                selection,
+            } => {
+                let bang = if *negate { "!" } else { "" };
+                let tilde = if *regex { "~" } else { "" };
+                write!(f, "*{}{}/{}", bang, tilde, input)?;
+                match selection {
+                    Some(sel) => write!(f, ":{}", sel),
+                    None => Ok(()),
+                }
+            }
+            Operator::Map {
+                input,
+                selection,
+                unmapped,
+            } => {
+                let bang = if *unmapped == Unmapped::Drop { "!" } else { "" };
+                write!(f, "*={}{}", bang, input)?;
+                if let Some(sel) = selection {
+                    write!(f, ":{}", sel)?;
+                }
+                match unmapped {
+                    Unmapped::Default(default) => write!(f, "{}", Quoted(default)),
+                    Unmapped::Keep | Unmapped::Drop => Ok(()),
+                }
+            }
+            Operator::SetInput {
+                input,
+                operation,
+                key,
+            } => {
+                let operation = match operation {
+                    SetOperation::Union => "|",
+                    SetOperation::Intersection => "&",
+                    SetOperation::Difference => "-",
+                    SetOperation::SymmetricDifference => "^",
+                };
+                write!(f, "*{}{}", operation, input)?;
+                match key {
+                    Some((left, right)) if right != left => write!(f, ":{}={}", left, right),
+                    Some((left, _)) => write!(f, ":{}", left),
+                    None => Ok(()),
+                }
+            }
+            Operator::JoinInput {
+                input,
+                kind,
+                left,
+                right,
+            } => {
+                let kind = match kind {
+                    JoinKind::Inner => "",
+                    JoinKind::Left => "?",
+                    JoinKind::Anti => "!",
+                };
+                write!(f, "*{}{}:{}", kind, input, left)?;
+                if right != left {
+                    write!(f, "={}", right)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Selection(sel) => write!(f, "{}", sel),
+            Key::Programme(programme) => write!(f, "{{{}}}", programme),
+        }
+    }
+}
+
+impl fmt::Display for Selection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, item) in self.items.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            match item {
+                SelectItem::Index(i) => write!(f, "{}", i)?,
+                SelectItem::Slice(slice) => write!(f, "{}", slice)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Slice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(start) = self.start {
+            write!(f, "{}", start)?;
+        }
+        write!(f, ":")?;
+        if let Some(end) = self.end {
+            write!(f, "{}", end)?;
+        }
+        if let Some(step) = self.step {
+            write!(f, ":{}", step)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Scan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scan::Sum => write!(f, "+"),
+            Scan::Delta => write!(f, "-"),
+            Scan::Rate => write!(f, "/"),
+            Scan::MovingAverage(size) => write!(f, "m{}", size),
+        }
+    }
+}
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sample::Count(size) => write!(f, "{}", size),
+            // Always with a decimal point, so `1.0` isn't read back as a count
+            Sample::Fraction(fraction) if fraction.fract() == 0.0 => write!(f, "{:.1}", fraction),
+            Sample::Fraction(fraction) => write!(f, "{}", fraction),
+        }
+    }
+}
+
+impl fmt::Display for Statistic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Statistic::Min => write!(f, "min"),
+            Statistic::Max => write!(f, "max"),
+            Statistic::Mean => write!(f, "mean"),
+            Statistic::Median => write!(f, "median"),
+            Statistic::Percentile(p) => write!(f, "p{}", p),
+            Statistic::StdDev => write!(f, "stddev"),
+            Statistic::Variance => write!(f, "var"),
+        }
+    }
+}
+
+impl fmt::Display for Aggregate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, field) = match self {
+            Aggregate::Count => return write!(f, "#"),
+            Aggregate::Sum(field) => ("+", field),
+            Aggregate::Min(field) => ("min", field),
+            Aggregate::Max(field) => ("max", field),
+            Aggregate::Mean(field) => ("mean", field),
+        };
+        write!(f, "{}", name)?;
+        if let Some(index) = field {
+            write!(f, "{}", index)?;
+        }
+        Ok(())
+    }
+}
+
+/// A `S`/`J` delimiter: bare if it is a single visible character, quoted otherwise.
+struct Delimiter<'a>(&'a str);
+
+impl fmt::Display for Delimiter<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut chars = self.0.chars();
+        if let (Some(c), None) = (chars.next(), chars.next())
+            && !c.is_whitespace()
+            && !c.is_control()
+            && c != '"'
+            && c != '\\'
+        {
+            return write!(f, "{}", c);
+        }
+        write!(f, "{}", Quoted(self.0))
+    }
+}
+
+/// A double-quoted string, with escapes as read by the parser.
+struct Quoted<'a>(&'a str);
+
+impl fmt::Display for Quoted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"")?;
+        for c in self.0.chars() {
+            match c {
+                '\\' => write!(f, "\\\\")?,
+                '"' => write!(f, "\\\"")?,
+                '\n' => write!(f, "\\n")?,
+                '\r' => write!(f, "\\r")?,
+                '\t' => write!(f, "\\t")?,
+                '\0' => write!(f, "\\0")?,
+                c if c.is_control() => write!(f, "\\x{:02x}", c as u32)?,
+                c => write!(f, "{}", c)?,
+            }
+        }
+        write!(f, "\"")
+    }
+}
+
+impl fmt::Display for FormatPart {
+    /// The part as it is written in a template.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatPart::Literal(text) => {
+                write!(f, "{}", text.replace('{', "{{").replace('}', "}}"))
+            }
+            FormatPart::Field {
+                selection,
+                separator,
+                spec,
+            } => {
+                write!(f, "{{")?;
+                if let Some(selection) = selection {
+                    write!(f, "{}", selection)?;
+                }
+                if let Some(separator) = separator {
+                    write!(f, "|{}", separator)?;
+                }
+                if let Some(spec) = spec {
+                    write!(f, "{}", spec)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+impl fmt::Display for TimeOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeOp::Parse(None) => write!(f, "p"),
+            TimeOp::Parse(Some(pattern)) => write!(f, "p{}", Quoted(pattern)),
+            TimeOp::Truncate(bucket) => write!(f, "/{}", bucket),
+            TimeOp::Format { offset, pattern } => {
+                write!(f, "f")?;
+                if let Some(offset) = offset {
+                    let sign = if *offset < 0 { '-' } else { '+' };
+                    let minutes = offset.abs() / 60;
+                    write!(f, "{}{:02}:{:02}", sign, minutes / 60, minutes % 60)?;
+                }
+                write!(f, "{}", Quoted(pattern))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Bucket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.count != 1 {
+            write!(f, "{}", self.count)?;
+        }
+        write!(f, "{}", self.unit.letter())
+    }
+}
+
+impl fmt::Display for FormatSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "%")?;
+        if self.left {
+            write!(f, "-")?;
+        }
+        if self.sign {
+            write!(f, "+")?;
+        }
+        if self.zero {
+            write!(f, "0")?;
+        }
+        if let Some(width) = self.width {
+            write!(f, "{}", width)?;
+        }
+        if let Some(precision) = self.precision {
+            write!(f, ".{}", precision)?;
+        }
+        write!(f, "{}", self.conversion.letter())
+    }
+}
+
+impl Expr {
+    /// How tightly the expression binds, for deciding where parentheses go.
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binary(BinaryOp::Add | BinaryOp::Subtract, ..) => 1,
+            Expr::Binary(BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Remainder, ..) => 2,
+            Expr::Negate(_) => 3,
+            Expr::Binary(BinaryOp::Power, ..) => 4,
+            Expr::Number(_) | Expr::Field(_) | Expr::Call(..) => 5,
+        }
+    }
+}
+
+/// An operand of an expression, parenthesised if it binds less tightly than
+/// `min`.
+struct Operand<'a>(&'a Expr, u8);
+
+impl fmt::Display for Operand<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.precedence() < self.1 {
+            write!(f, "({})", self.0)
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Number(n) => write!(f, "{}", n),
+            Expr::Field(Some(index)) => write!(f, "${}", index),
+            Expr::Field(None) => write!(f, "$"),
+            Expr::Negate(operand) => write!(f, "-{}", Operand(operand, 3)),
+            Expr::Binary(op, left, right) => {
+                let symbol = match op {
+                    BinaryOp::Add => "+",
+                    BinaryOp::Subtract => "-",
+                    BinaryOp::Multiply => "*",
+                    BinaryOp::Divide => "/",
+                    BinaryOp::Remainder => "%",
+                    BinaryOp::Power => "**",
+                };
+                // Left-associative, except for `**`, whose left operand is
+                // an atom and whose right operand may be negated
+                let (left_min, right_min) = match op {
+                    BinaryOp::Power => (5, 3),
+                    _ => (self.precedence(), self.precedence() + 1),
+                };
+                write!(
+                    f,
+                    "{} {} {}",
+                    Operand(left, left_min),
+                    symbol,
+                    Operand(right, right_min)
+                )
+            }
+            Expr::Call(function, args) => {
+                write!(f, "{}(", function.name())?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// A slash-delimited pattern, escaping any `/` not already escaped.
+struct Slashed<'a>(&'a str);
+
+impl fmt::Display for Slashed<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut chars = self.0.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(next) => write!(f, "\\{}", next)?,
+                    None => write!(f, "\\\\")?,
+                },
+                '/' => write!(f, "\\/")?,
+                c => write!(f, "{}", c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_programme;
+    use proptest::prelude::*;
+
+    fn canonical(source: &str) -> String {
+        parse_programme(source).unwrap().to_string()
+    }
+
+    #[test]
+    fn prints_simple_operators() {
+        let source = "sj@^ulntxfd+#coO;";
+        assert_eq!(canonical(source), source);
+    }
+
+    #[test]
+    fn prints_selections() {
+        assert_eq!(canonical("0,-1,1:5:2,::-1,3:,:2"), "0,-1,1:5:2,::-1,3:,:2");
+        assert_eq!(
+            canonical("s@0,2^L1U:2N0T-1p::2g0,1D2"),
+            "s@0,2^L1U:2N0T-1p::2g0,1D2"
+        );
+    }
+
+    #[test]
+    fn separates_adjacent_selections() {
+        let programme = Programme {
+            operators: vec![
+                Operator::Selection(Selection {
+                    items: vec![SelectItem::Index(0)],
+                }),
+                Operator::Selection(Selection {
+                    items: vec![SelectItem::Index(1)],
+                }),
+            ],
+        };
+        assert_eq!(programme.to_string(), "0;1");
+    }
+
+    #[test]
+    fn prints_bare_delimiters() {
+        assert_eq!(canonical("S,J:"), "S,J:");
+        assert_eq!(canonical("S\",\""), "S,");
+    }
+
+    #[test]
+    fn quotes_delimiters() {
+        assert_eq!(canonical("S\"::\""), "S\"::\"");
+        assert_eq!(canonical("J\"\""), "J\"\"");
+        assert_eq!(canonical("S\\t"), "S\"\\t\"");
+        assert_eq!(canonical("J\" \""), "J\" \"");
+        assert_eq!(canonical("S\\\""), "S\"\\\"\"");
+        assert_eq!(canonical("S\\\\"), "S\"\\\\\"");
+        assert_eq!(canonical("J\"\\x01\\0\\r\\n\""), "J\"\\x01\\0\\r\\n\"");
+        assert_eq!(canonical("S\\u00e9"), "Sé");
+    }
+
+    #[test]
+    fn prints_patterns_verbatim() {
+        let source = r"/a\/b/!/\d+/m/[a-z]+/r0/\s+/ /r/x/\//";
+        assert_eq!(canonical(source), source);
+    }
+
+    #[test]
+    fn escapes_unescaped_slashes() {
+        let programme = Programme {
+            operators: vec![Operator::Filter {
+                pattern: "a/b".to_string(),
+                negate: true,
+            }],
+        };
+        assert_eq!(programme.to_string(), r"!/a\/b/");
+    }
+
+    #[test]
+    fn prints_merged_filters_as_filters() {
+        let programme = Programme {
+            operators: vec![Operator::FilterAll(vec![
+                FilterClause {
+                    pattern: "a".to_string(),
+                    negate: false,
+                },
+                FilterClause {
+                    pattern: "b".to_string(),
+                    negate: true,
+                },
+            ])],
+        };
+        assert_eq!(programme.to_string(), "/a/!/b/");
+    }
+
+    #[test]
+    fn prints_blocks() {
+        assert_eq!(canonical("s{ @u^j }{}"), "s{@u^j}{}");
+        assert_eq!(canonical("{0}1"), "{0}1");
+    }
+
+    #[test]
+    fn prints_forks() {
+        assert_eq!(canonical("( # | s@0^d# |)"), "(#|s@0^d#|)");
+        assert_eq!(canonical("({s}|(0|1))"), "({s}|(0|1))");
+    }
+
+    #[test]
+    fn prints_conditionals() {
+        assert_eq!(canonical("?/ERROR/{u}"), "?/ERROR/{u}");
+        assert_eq!(canonical("?1/a\\/b/{ u }{l}"), "?1/a\\/b/{u}{l}");
+    }
+
+    #[test]
+    fn prints_keyed_operators() {
+        assert_eq!(canonical("g{ l }D{1}o{#}O{ l }"), "g{l}D{1}o{#}O{l}");
+        assert_eq!(canonical("o;{#}"), "o;{#}");
+        assert_eq!(canonical("g0;{#}"), "g0;{#}");
+    }
+
+    #[test]
+    fn prints_group_aggregates() {
+        assert_eq!(canonical("g0{ #, +9, max 3 }"), "g0{#,+9,max3}");
+        assert_eq!(canonical("g{l}{mean,min-1}"), "g{l}{mean,min-1}");
+    }
+
+    #[test]
+    fn separates_block_after_group() {
+        let programme = Programme {
+            operators: vec![
+                Operator::GroupBy(Key::Selection(Selection {
+                    items: vec![SelectItem::Index(0)],
+                })),
+                Operator::Block(parse_programme("#").unwrap()),
+            ],
+        };
+        assert_eq!(programme.to_string(), "g0;{#}");
+    }
+
+    #[test]
+    fn prints_statistics() {
+        assert_eq!(canonical("%p99 %p99.9 %p050"), "%p99%p99.9%p50");
+        assert_eq!(canonical("%stddev%describe"), "%stddev%describe");
+    }
+
+    #[test]
+    fn prints_scans_and_windows() {
+        assert_eq!(canonical("\\+ \\- \\/ \\m3 w2"), "\\+\\-\\/\\m3w2");
+        assert_eq!(canonical("w2;0"), "w2;0");
+    }
+
+    #[test]
+    fn separates_block_after_conditional() {
+        let programme = Programme {
+            operators: vec![
+                Operator::Conditional {
+                    selection: None,
+                    pattern: "a".to_string(),
+                    then: parse_programme("u").unwrap(),
+                    otherwise: None,
+                },
+                Operator::Block(parse_programme("l").unwrap()),
+            ],
+        };
+        assert_eq!(programme.to_string(), "?/a/{u};{l}");
+    }
+
+    #[test]
+    fn pretty_layout() {
+        let programme = parse_programme("s@s@::-1^j^j").unwrap();
+        assert_eq!(programme.pretty(), "s\n  @ s\n    @ ::-1\n  ^ j\n^ j");
+    }
+
+    #[test]
+    fn pretty_layout_breaks_on_separators() {
+        let programme = parse_programme("s@0^;d;o;:10").unwrap();
+        assert_eq!(programme.pretty(), "s\n  @ 0\n^\nd\no\n:10");
+    }
+
+    #[test]
+    fn pretty_layout_parses() {
+        let programme = parse_programme("s@s@::-1^j^j;d;o:10").unwrap();
+        let reparsed = parse_programme(&programme.pretty()).unwrap();
+        assert_eq!(without_noops(&reparsed), without_noops(&programme));
+    }
+
+    #[test]
+    fn pretty_empty_programme() {
+        assert_eq!(parse_programme("").unwrap().pretty(), "");
+    }
+
+    fn selections() -> impl Strategy<Value = Selection> {
+        let index = -20i64..20;
+        let item = prop_oneof![
+            index.clone().prop_map(SelectItem::Index),
+            (
+                proptest::option::of(index.clone()),
+                proptest::option::of(index.clone()),
+                proptest::option::of(index),
+            )
+                .prop_map(|(start, end, step)| SelectItem::Slice(Slice {
+                    start,
+                    end,
+                    step
+                })),
+        ];
+        prop::collection::vec(item, 1..4).prop_map(|items| Selection { items })
+    }
+
+    fn delimiters(min: usize) -> impl Strategy<Value = String> {
+        prop::collection::vec(any::<char>(), min..4).prop_map(|chars| chars.into_iter().collect())
+    }
+
+    fn patterns() -> impl Strategy<Value = String> {
+        prop::sample::select(vec![
+            "a".to_string(),
+            r"a\/b".to_string(),
+            r"\d+".to_string(),
+            r"[\/]".to_string(),
+            "(?i)é".to_string(),
+            r"\\".to_string(),
+        ])
+    }
+
+    fn time_ops() -> impl Strategy<Value = TimeOp> {
+        let patterns = prop::sample::select(vec![
+            "%Y-%m-%d".to_string(),
+            "%d/%b/%Y:%H:%M:%S %z".to_string(),
+            "%H:%M \"%s\"".to_string(),
+        ]);
+        prop_oneof![
+            proptest::option::of(patterns.clone()).prop_map(TimeOp::Parse),
+            (1u32..100, prop::sample::select(TimeUnit::ALL.to_vec()))
+                .prop_map(|(count, unit)| TimeOp::Truncate(Bucket { count, unit })),
+            (proptest::option::of(-1439i32..1440), patterns).prop_map(|(offset, pattern)| {
+                TimeOp::Format {
+                    offset: offset.map(|minutes| minutes * 60),
+                    pattern,
+                }
+            }),
+        ]
+    }
+
+    fn format_specs() -> impl Strategy<Value = FormatSpec> {
+        (
+            (any::<bool>(), any::<bool>(), any::<bool>()),
+            proptest::option::of(1usize..20),
+            proptest::option::of(0usize..10),
+            prop::sample::select(Conversion::ALL.to_vec()),
+        )
+            .prop_map(
+                |((left, sign, zero), width, precision, conversion)| FormatSpec {
+                    left,
+                    sign,
+                    zero,
+                    width,
+                    precision,
+                    conversion,
+                },
+            )
+    }
+
+    /// Templates alternating literals and placeholders, as parsing merges
+    /// adjacent literals.
+    fn templates() -> impl Strategy<Value = Vec<FormatPart>> {
+        let field = (
+            proptest::option::of(selections()),
+            proptest::option::of("[^%}]{0,3}"),
+            proptest::option::of(format_specs()),
+        )
+            .prop_map(|(selection, separator, spec)| FormatPart::Field {
+                selection,
+                separator,
+                spec,
+            });
+        let literal = proptest::option::of("[a-z{}\" \\\\]{1,5}");
+        (
+            prop::collection::vec((literal.clone(), field), 0..4),
+            literal,
+        )
+            .prop_map(|(fields, last)| {
+                let mut parts = Vec::new();
+                for (literal, field) in fields {
+                    parts.extend(literal.map(FormatPart::Literal));
+                    parts.push(field);
+                }
+                parts.extend(last.map(FormatPart::Literal));
+                parts
+            })
+    }
+
+    fn expressions() -> impl Strategy<Value = Expr> {
+        let leaf = prop_oneof![
+            prop_oneof![Just(0.0), Just(1.5), Just(1024.0), Just(0.001)].prop_map(Expr::Number),
+            proptest::option::of(-5i64..5).prop_map(Expr::Field),
+        ];
+        leaf.prop_recursive(3, 16, 2, |inner| {
+            prop_oneof![
+                inner.clone().prop_map(|e| Expr::Negate(Box::new(e))),
+                (
+                    prop::sample::select(vec![
+                        BinaryOp::Add,
+                        BinaryOp::Subtract,
+                        BinaryOp::Multiply,
+                        BinaryOp::Divide,
+                        BinaryOp::Remainder,
+                        BinaryOp::Power,
+                    ]),
+                    inner.clone(),
+                    inner.clone(),
+                )
+                    .prop_map(|(op, left, right)| Expr::Binary(
+                        op,
+                        Box::new(left),
+                        Box::new(right)
+                    )),
+                (
+                    prop::sample::select(Function::ALL.to_vec()),
+                    prop::collection::vec(inner, 1..3),
+                )
+                    .prop_map(|(function, mut args)| {
+                        args.truncate(*function.arity().end());
+                        Expr::Call(function, args)
+                    }),
+            ]
+        })
+    }
+
+    fn aggregates() -> impl Strategy<Value = Aggregate> {
+        let field = proptest::option::of(-5i64..5);
+        prop_oneof![
+            Just(Aggregate::Count),
+            field.clone().prop_map(Aggregate::Sum),
+            field.clone().prop_map(Aggregate::Min),
+            field.clone().prop_map(Aggregate::Max),
+            field.prop_map(Aggregate::Mean),
+        ]
+    }
+
+    fn operators() -> impl Strategy<Value = Operator> {
+        leaf_operators().prop_recursive(2, 16, 4, |inner| {
+            let programme =
+                prop::collection::vec(inner, 0..4).prop_map(|operators| Programme { operators });
+            prop_oneof![
+                programme.clone().prop_map(Operator::Block),
+                prop::collection::vec(programme.clone(), 1..4).prop_map(Operator::Fork),
+                (
+                    proptest::option::of(selections()),
+                    patterns(),
+                    programme.clone(),
+                    proptest::option::of(programme.clone()),
+                )
+                    .prop_map(|(selection, pattern, then, otherwise)| {
+                        Operator::Conditional {
+                            selection,
+                            pattern,
+                            then,
+                            otherwise,
+                        }
+                    }),
+                programme
+                    .clone()
+                    .prop_map(|p| Operator::GroupBy(Key::Programme(p))),
+                programme
+                    .clone()
+                    .prop_map(|p| Operator::DedupeSelectionWithCounts(Key::Programme(p))),
+                (
+                    programme.clone().prop_map(Key::Programme),
+                    prop::collection::vec(aggregates(), 1..4)
+                )
+                    .prop_map(|(key, aggregates)| Operator::GroupAggregate { key, aggregates }),
+                programme.clone().prop_map(Operator::SortDescendingBy),
+                programme.prop_map(Operator::SortAscendingBy),
+            ]
+        })
+    }
+
+    fn leaf_operators() -> impl Strategy<Value = Operator> {
+        prop_oneof![
+            prop::sample::select(vec![
+                Operator::Split,
+                Operator::Join,
+                Operator::Descend,
+                Operator::Ascend,
+                Operator::Uppercase,
+                Operator::Lowercase,
+                Operator::ToNumber,
+                Operator::Trim,
+                Operator::DeleteEmpty,
+                Operator::Flatten,
+                Operator::Enumerate,
+                Operator::Reverse,
+                Operator::Shuffle,
+                Operator::Transpose,
+                Operator::Zip,
+                Operator::DedupeWithCounts,
+                Operator::Sum,
+                Operator::Count,
+                Operator::Columnate,
+                Operator::Sparkline,
+                Operator::SortDescending,
+                Operator::SortAscending,
+                Operator::NoOp,
+                Operator::Describe,
+                Operator::Statistic(Statistic::Min),
+                Operator::Statistic(Statistic::Max),
+                Operator::Statistic(Statistic::Mean),
+                Operator::Statistic(Statistic::Median),
+                Operator::Statistic(Statistic::StdDev),
+                Operator::Statistic(Statistic::Variance),
+                Operator::Scan(Scan::Sum),
+                Operator::Scan(Scan::Delta),
+                Operator::Scan(Scan::Rate),
+            ]),
+            (1usize..20).prop_map(|n| Operator::Scan(Scan::MovingAverage(n))),
+            (1usize..20).prop_map(Operator::Window),
+            (0usize..1000).prop_map(|n| Operator::Sample(Sample::Count(n))),
+            (0u32..=1000).prop_map(|n| Operator::Sample(Sample::Fraction(f64::from(n) / 1000.0))),
+            (0u32..=1000)
+                .prop_map(|p| Operator::Statistic(Statistic::Percentile(f64::from(p) / 10.0))),
+            delimiters(1).prop_map(Operator::SplitDelim),
+            delimiters(0).prop_map(Operator::JoinDelim),
+            selections().prop_map(Operator::Selection),
+            selections().prop_map(Operator::UppercaseSelected),
+            selections().prop_map(Operator::LowercaseSelected),
+            selections().prop_map(Operator::ToNumberSelected),
+            selections().prop_map(Operator::TrimSelected),
+            selections().prop_map(Operator::Partition),
+            selections().prop_map(|s| Operator::GroupBy(Key::Selection(s))),
+            (selections(), prop::collection::vec(aggregates(), 1..4)).prop_map(
+                |(s, aggregates)| Operator::GroupAggregate {
+                    key: Key::Selection(s),
+                    aggregates
+                }
+            ),
+            selections().prop_map(|s| Operator::DedupeSelectionWithCounts(Key::Selection(s))),
+            (
+                prop::sample::select(vec!["users", "t2", "_x"]),
+                prop::sample::select(vec![
+                    SetOperation::Union,
+                    SetOperation::Intersection,
+                    SetOperation::Difference,
+                    SetOperation::SymmetricDifference,
+                ]),
+                proptest::option::of((selections(), selections())),
+            )
+                .prop_map(|(input, operation, key)| Operator::SetInput {
+                    input: input.to_string(),
+                    operation,
+                    key,
+                }),
+            (
+                prop::sample::select(vec!["users", "t2", "_x"]),
+                any::<bool>(),
+                any::<bool>(),
+                proptest::option::of(selections()),
+            )
+                .prop_map(|(input, regex, negate, selection)| {
+                    Operator::PatternFilter {
+                        input: input.to_string(),
+                        regex,
+                        negate,
+                        selection,
+                    }
+                }),
+            (
+                prop::sample::select(vec!["users", "t2", "_x"]),
+                proptest::option::of(selections()),
+                prop_oneof![
+                    Just(Unmapped::Keep),
+                    Just(Unmapped::Drop),
+                    ".*".prop_map(Unmapped::Default),
+                ],
+            )
+                .prop_map(|(input, selection, unmapped)| Operator::Map {
+                    input: input.to_string(),
+                    selection,
+                    unmapped,
+                }),
+            (
+                prop::sample::select(vec!["users", "t2", "_x"]),
+                prop::sample::select(vec![JoinKind::Inner, JoinKind::Left, JoinKind::Anti]),
+                selections(),
+                selections(),
+            )
+                .prop_map(|(input, kind, left, right)| Operator::JoinInput {
+                    input: input.to_string(),
+                    kind,
+                    left,
+                    right,
+                }),
+            (patterns(), any::<bool>())
+                .prop_map(|(pattern, negate)| Operator::Filter { pattern, negate }),
+            patterns().prop_map(|pattern| Operator::Match { pattern }),
+            expressions().prop_map(Operator::Arithmetic),
+            (any::<bool>(), any::<bool>()).prop_map(|(percent, cumulative)| {
+                Operator::Histogram {
+                    percent,
+                    cumulative,
+                }
+            }),
+            templates().prop_map(Operator::Format),
+            (proptest::option::of(selections()), time_ops())
+                .prop_map(|(selection, op)| Operator::Time { selection, op }),
+            (-5i64..5, -5i64..5).prop_map(|(from, to)| Operator::Duration { from, to }),
+            ("[a-z|' -]{1,8}(\\{[a-z ]{0,4}\\})?", any::<bool>())
+                .prop_map(|(command, batched)| Operator::Command { command, batched }),
+            (proptest::option::of(selections()), patterns(), patterns()).prop_map(
+                |(selection, pattern, replacement)| Operator::Replace {
+                    selection,
+                    pattern,
+                    replacement,
+                }
+            ),
+        ]
+    }
+
+    /// The operators of a programme without no-ops, and with merged filters
+    /// split back into the filters they print as.
+    fn without_noops(programme: &Programme) -> Vec<Operator> {
+        let strip = |programme: &Programme| Programme {
+            operators: without_noops(programme),
+        };
+        programme
+            .operators
+            .iter()
+            .filter(|op| **op != Operator::NoOp)
+            .flat_map(|op| match op {
+                Operator::FilterAll(clauses) => clauses
+                    .iter()
+                    .map(|clause| Operator::Filter {
+                        pattern: clause.pattern.clone(),
+                        negate: clause.negate,
+                    })
+                    .collect(),
+                op => vec![op.clone()],
+            })
+            .map(|op| match &op {
+                Operator::Block(inner) => Operator::Block(strip(inner)),
+                Operator::Fork(branches) => Operator::Fork(branches.iter().map(strip).collect()),
+                Operator::Conditional {
+                    selection,
+                    pattern,
+                    then,
+                    otherwise,
+                } => Operator::Conditional {
+                    selection: selection.clone(),
+                    pattern: pattern.clone(),
+                    then: strip(then),
+                    otherwise: otherwise.as_ref().map(strip),
+                },
+                Operator::GroupBy(Key::Programme(key)) => {
+                    Operator::GroupBy(Key::Programme(strip(key)))
+                }
+                Operator::GroupAggregate {
+                    key: Key::Programme(key),
+                    aggregates,
+                } => Operator::GroupAggregate {
+                    key: Key::Programme(strip(key)),
+                    aggregates: aggregates.clone(),
+                },
+                Operator::DedupeSelectionWithCounts(Key::Programme(key)) => {
+                    Operator::DedupeSelectionWithCounts(Key::Programme(strip(key)))
+                }
+                Operator::SortDescendingBy(key) => Operator::SortDescendingBy(strip(key)),
+                Operator::SortAscendingBy(key) => Operator::SortAscendingBy(strip(key)),
+                _ => op.clone(),
+            })
+            .collect()
+    }
+
+    proptest! {
+        #[test]
+        fn prop_round_trip(operators in prop::collection::vec(operators(), 0..10)) {
+            let programme = Programme { operators };
+            let source = programme.to_string();
+            let reparsed = parse_programme(&source).map_err(TestCaseError::fail)?;
+            prop_assert_eq!(reparsed.to_string(), source);
+            prop_assert_eq!(without_noops(&reparsed), without_noops(&programme));
+        }
+
+        #[test]
+        fn prop_optimised_round_trip(operators in prop::collection::vec(operators(), 0..10)) {
+            let optimised = crate::optimiser::optimise(Programme { operators });
+            let reparsed = parse_programme(&optimised.to_string()).map_err(TestCaseError::fail)?;
+            prop_assert_eq!(without_noops(&reparsed), without_noops(&optimised));
+        }
+
+        #[test]
+        fn prop_pretty_round_trip(operators in prop::collection::vec(operators(), 0..10)) {
+            let programme = Programme { operators };
+            let reparsed = parse_programme(&programme.pretty()).map_err(TestCaseError::fail)?;
+            prop_assert_eq!(without_noops(&reparsed), without_noops(&programme));
+        }
+    }
+}
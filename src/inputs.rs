@@ -0,0 +1,197 @@
+//! Named side inputs, loaded with `--with <name>=<file>`.
+//!
+//! Each input is kept both as lines and as a table: one row per line, split
+//! into fields the way `s` would split it. Files ending in `.csv` are split as
+//! CSV and files ending in `.tsv` on tabs, whatever the main input uses.
+//! Operators refer to an input by name, e.g. `*users:0=2`.
+//!
+//! Dictionaries for `*=` are read separately, as two columns of CSV for files
+//! ending in `.csv` and of TSV otherwise, so values may contain spaces.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use winnow::Parser;
+
+use crate::interpreter::Transform;
+use crate::operators::{Split, SplitMode};
+use crate::parser::macro_name;
+use crate::value::{Array, Level, Value};
+
+/// A set of named inputs.
+#[derive(Debug, Clone, Default)]
+pub struct Inputs {
+    inputs: HashMap<String, Input>,
+}
+
+/// A named input, as lines and as rows of fields.
+#[derive(Debug, Clone)]
+pub struct Input {
+    pub lines: Arc<Value>,
+    pub rows: Arc<Value>,
+    /// Field delimiter when the input is read as a dictionary.
+    delimiter: u8,
+}
+
+impl Inputs {
+    /// Load inputs from `<name>=<file>` specs, splitting rows with `split_mode`
+    /// unless the file extension says otherwise.
+    pub fn load(specs: &[String], split_mode: &SplitMode) -> Result<Self, String> {
+        let mut inputs = Self::default();
+        for spec in specs {
+            let Some((name, path)) = spec.split_once('=') else {
+                return Err(format!("{}: expected <name>=<file>", spec));
+            };
+            if macro_name.parse(name).is_err() {
+                return Err(format!("invalid input name '{}'", name));
+            }
+            if inputs.inputs.contains_key(name) {
+                return Err(format!("duplicate input '{}'", name));
+            }
+            let lines =
+                Array::from_files(&[path], Level::Line).map_err(|e| format!("{}: {}", path, e))?;
+            let path = Path::new(path);
+            inputs.insert(
+                name,
+                Value::Array(lines),
+                &split_mode_for(path, split_mode),
+                dictionary_delimiter_for(path),
+            );
+        }
+        Ok(inputs)
+    }
+
+    /// Add `lines` as an input under `name`, splitting rows with `split_mode`
+    /// and dictionary rows on `delimiter`. Replaces any input of that name.
+    pub fn insert(&mut self, name: &str, lines: Value, split_mode: &SplitMode, delimiter: u8) {
+        let rows = Split::new(split_mode.clone())
+            .apply(lines.deep_copy())
+            .expect("splitting never fails");
+        let input = Input {
+            lines: Arc::new(lines),
+            rows: Arc::new(rows),
+            delimiter,
+        };
+        self.inputs.insert(name.to_string(), input);
+    }
+
+    /// Get an input by name.
+    pub fn get(&self, name: &str) -> Option<&Input> {
+        self.inputs.get(name)
+    }
+}
+
+impl Input {
+    /// Read the input as a dictionary of its first column to its second.
+    ///
+    /// Later rows win, rows with no second column are ignored, and any
+    /// further columns are ignored.
+    pub fn dictionary(&self) -> Result<HashMap<String, String>, String> {
+        let text = match &*self.lines {
+            Value::Array(lines) => lines
+                .iter()
+                .map(|line| line.to_string())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            other => other.to_string(),
+        };
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .delimiter(self.delimiter)
+            .quoting(self.delimiter == b',')
+            .from_reader(text.as_bytes());
+        let mut dictionary = HashMap::new();
+        for record in reader.records() {
+            let record = record.map_err(|e| e.to_string())?;
+            if let (Some(key), Some(value)) = (record.get(0), record.get(1)) {
+                dictionary.insert(key.to_string(), value.to_string());
+            }
+        }
+        Ok(dictionary)
+    }
+}
+
+fn split_mode_for(path: &Path, split_mode: &SplitMode) -> SplitMode {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("csv") => SplitMode::Csv,
+        Some("tsv") => SplitMode::Delimiter("\t".to_string()),
+        _ => split_mode.clone(),
+    }
+}
+
+fn dictionary_delimiter_for(path: &Path) -> u8 {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("csv") => b',',
+        _ => b'\t',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(specs: &[&str]) -> Result<Inputs, String> {
+        let specs: Vec<String> = specs.iter().map(|s| s.to_string()).collect();
+        Inputs::load(&specs, &SplitMode::Whitespace)
+    }
+
+    fn row(fields: &[&str]) -> Value {
+        Value::Array(Array::from((
+            fields.iter().map(|s| Value::Text(s.to_string())).collect(),
+            Level::Word,
+        )))
+    }
+
+    #[test]
+    fn csv_input_is_split_into_rows() {
+        let path = std::env::temp_dir().join(format!("t_test_input_{}.csv", std::process::id()));
+        std::fs::write(&path, "1,alice\n2,\"bob, jr\"\n").unwrap();
+        let inputs = load(&[&format!("users={}", path.display())]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let users = inputs.get("users").unwrap();
+        assert_eq!(
+            *users.rows,
+            Value::Array(Array::from((
+                vec![row(&["1", "alice"]), row(&["2", "bob, jr"])],
+                Level::Line
+            )))
+        );
+    }
+
+    #[test]
+    fn dictionary_keeps_values_with_spaces() {
+        let path =
+            std::env::temp_dir().join(format!("t_test_dictionary_{}.txt", std::process::id()));
+        std::fs::write(&path, "200\tOK\n404\tNot Found\nbare\n").unwrap();
+        let inputs = load(&[&format!("codes={}", path.display())]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let dictionary = inputs.get("codes").unwrap().dictionary().unwrap();
+        assert_eq!(
+            dictionary,
+            HashMap::from([
+                ("200".to_string(), "OK".to_string()),
+                ("404".to_string(), "Not Found".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn missing_equals_error() {
+        assert_eq!(
+            load(&["users"]).unwrap_err(),
+            "users: expected <name>=<file>"
+        );
+    }
+
+    #[test]
+    fn invalid_name_error() {
+        assert_eq!(
+            load(&["1st=users.csv"]).unwrap_err(),
+            "invalid input name '1st'"
+        );
+    }
+}
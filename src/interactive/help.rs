@@ -22,6 +22,10 @@ pub const OPERATOR_HELP: &[HelpLine] = &[
     HelpLine::Row("u", "uppercase", "U<sel>", "uppercase selected"),
     HelpLine::Row("t", "trim whitespace", "T<sel>", "trim selected"),
     HelpLine::Row("n", "to number", "N<sel>", "to number selected"),
+    HelpLine::Single("={$2 - $1}", "arithmetic on fields ($ is the element)"),
+    HelpLine::Single("F\"{0} {-1%.2f}\"", "format fields with a template"),
+    HelpLine::Row("kp k<sel>p", "parse times", "k/15m", "round time down"),
+    HelpLine::Row("kf\"%H:%M\"", "format time", "kd<a>,<b>", "seconds between"),
     HelpLine::Row(
         "r/<p>/<r>/",
         "replace pattern",
@@ -29,12 +33,29 @@ pub const OPERATOR_HELP: &[HelpLine] = &[
         "replace in selected",
     ),
     HelpLine::Row("/<pat>/", "filter keep", "!/<pat>/", "filter remove"),
-    HelpLine::Single("m/<pat>/", "matches to array"),
+    HelpLine::Row(
+        "!{<cmd>}",
+        "pipe through shell",
+        "!|{<cmd>}",
+        "one run, line each",
+    ),
+    HelpLine::Row("m/<pat>/", "matches to array", "?/<p>/{t}{e}", "if/else"),
     HelpLine::Row("d", "dedupe", "D<sel>", "dedupe on selected"),
     HelpLine::Row("o", "sort descending", "O", "sort ascending"),
+    HelpLine::Row("v", "reverse", "~ ~<n> ~0.1", "shuffle, sample"),
     HelpLine::Row("x", "delete empty", "g<sel>", "group by"),
+    HelpLine::Row("f", "flatten", "Z z", "transpose, zip"),
+    HelpLine::Single("e", "enumerate (index, element)"),
     HelpLine::Row("#", "count", "+", "sum"),
+    HelpLine::Row("%min %max %p99", "statistics", "%describe", "summary row"),
+    HelpLine::Row(
+        "\\+ \\- \\/ \\m<n>",
+        "running sum/change",
+        "w<n>",
+        "sliding windows",
+    ),
     HelpLine::Row("c", "columnate", "p<sel>", "partition"),
+    HelpLine::Row("h h% h+", "bar chart of d", "H", "sparkline"),
     HelpLine::Row("@", "descend", "^", "ascend"),
     HelpLine::Row(
         ";",
@@ -42,6 +63,24 @@ pub const OPERATOR_HELP: &[HelpLine] = &[
         "<sel>",
         "select (e.g. 0, 1:3, ::2)",
     ),
+    HelpLine::Row("{<prog>}", "block", "(<p>|<p>)", "fork"),
+    HelpLine::Single("g{p} D{p} o{p}", "key by sub-programme"),
+    HelpLine::Single("g<sel>{#,+1,..}", "group and aggregate"),
+    HelpLine::Single("*<in>:<s>=<s>", "join named input (*? left, *! anti)"),
+    HelpLine::Single("*| *& *- *^<in>", "union, intersect, difference, xor"),
+    HelpLine::Row(
+        "*/<in>",
+        "keep lines in list",
+        "*!/<in>",
+        "drop lines in list",
+    ),
+    HelpLine::Row(
+        "*=<in>[:<s>]",
+        "map via dictionary",
+        "*=!<in>",
+        "drop unmapped",
+    ),
+    HelpLine::Single("&<name>", "expand macro"),
 ];
 
 pub const INTERACTIVE_KEYS: &[(&str, &str)] = &[
@@ -18,9 +18,12 @@ use crossterm::{
 };
 
 use crate::ast;
-use crate::interpreter::{self, CompileConfig};
+use crate::interpreter::{self, Commands, CompileConfig};
+use crate::macros::Macros;
+use crate::optimiser;
 use crate::parser;
 use crate::value::{Array, Value};
+use crate::variables::Variables;
 
 pub use help::{help_text, write_footer, write_help_text, write_intro, write_options};
 pub use json::{write_json_debug, write_json_highlighted};
@@ -43,6 +46,10 @@ pub struct InteractiveMode {
     history: history::History,
     /// Compile configuration for split/join modes.
     config: CompileConfig,
+    /// Macros available to the programme.
+    macros: Macros,
+    /// Variables available to the programme.
+    variables: Variables,
 }
 
 struct CachedOutput {
@@ -64,7 +71,14 @@ impl InteractiveMode {
         json_output: bool,
         debug_output: bool,
         config: CompileConfig,
+        macros: Macros,
+        variables: Variables,
     ) -> Self {
+        // Commands run once the programme is committed, never in the preview
+        let mut config = config;
+        if config.commands == Commands::Enabled {
+            config.commands = Commands::Preview;
+        }
         Self {
             input,
             programme: String::new(),
@@ -76,6 +90,8 @@ impl InteractiveMode {
             cached_output: None,
             history: history::History::load(),
             config,
+            macros,
+            variables,
         }
     }
 
@@ -450,15 +466,21 @@ impl InteractiveMode {
     /// Try to execute the programme. Returns (value, depth, optional error).
     fn try_execute(&self, needed_lines: usize) -> (Value, usize, Option<anyhow::Error>) {
         // Try parsing the full programme
-        let parse_result = parser::parse_programme(&self.programme);
+        let parse_result =
+            parser::parse_programme_with_macros(&self.programme, &self.macros, &self.variables);
 
         let (programme, parse_error) = match parse_result {
             Ok(prog) => (prog, None),
             Err(e) => {
                 // Try to find the longest valid prefix
                 let mut valid_prog = None;
-                for i in (0..self.programme.len()).rev() {
-                    if let Ok(prog) = parser::parse_programme(&self.programme[..i])
+                for i in (0..=self.programme.len()).rev() {
+                    if !self.programme.is_char_boundary(i) {
+                        continue;
+                    }
+                    let prefix = close_blocks(&self.programme[..i]);
+                    if let Ok(prog) =
+                        parser::parse_programme_with_macros(&prefix, &self.macros, &self.variables)
                         && !prog.operators.is_empty()
                     {
                         valid_prog = Some(prog);
@@ -473,6 +495,7 @@ impl InteractiveMode {
         };
 
         let depth = compute_depth(&programme);
+        let programme = optimiser::optimise(programme);
 
         // Compile and run whatever we successfully parsed
         let ops = match interpreter::compile_with_config(&programme, &self.config) {
@@ -529,18 +552,40 @@ enum KeyAction {
 
 /// Compute the current depth from a parsed programme.
 /// Depth increases with `@` (descend) and decreases with `^` (ascend).
+/// Blocks restore the depth they started at, so navigation inside them doesn't count.
 fn compute_depth(programme: &ast::Programme) -> usize {
     let mut depth: isize = 0;
     for op in &programme.operators {
         match op {
             ast::Operator::Descend => depth += 1,
             ast::Operator::Ascend => depth = (depth - 1).max(0),
+            // Blocks and forks are opaque: navigation inside them is undone at the end
             _ => {}
         }
     }
     depth.max(0) as usize
 }
 
+/// Close any blocks and forks left open at the end of a programme being
+/// typed, so the preview can show their effect before the `}` or `)` is entered.
+///
+/// Brackets inside patterns and delimiters are counted too, so the result may
+/// still fail to parse; callers fall back to shorter prefixes.
+fn close_blocks(programme: &str) -> String {
+    let mut open = Vec::new();
+    for c in programme.chars() {
+        match c {
+            '{' => open.push('}'),
+            '(' => open.push(')'),
+            '}' | ')' => {
+                open.pop();
+            }
+            _ => {}
+        }
+    }
+    programme.chars().chain(open.into_iter().rev()).collect()
+}
+
 /// Extract error offset and message from a parse error string.
 fn parse_error_info(err: &anyhow::Error) -> (usize, String) {
     let err_str = err.to_string();
@@ -567,3 +612,33 @@ fn parse_error_info(err: &anyhow::Error) -> (usize, String) {
     // Fallback for runtime errors or unexpected format
     (0, err_str)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn depth(source: &str) -> usize {
+        compute_depth(&parser::parse_programme(source).unwrap())
+    }
+
+    #[test]
+    fn depth_follows_navigation() {
+        assert_eq!(depth("s@@"), 2);
+        assert_eq!(depth("s@@^"), 1);
+        assert_eq!(depth("^^@"), 1);
+    }
+
+    #[test]
+    fn depth_ignores_blocks() {
+        assert_eq!(depth("s{@@}"), 0);
+        assert_eq!(depth("s@{@s@}"), 1);
+        assert_eq!(depth("(@|@@)"), 0);
+    }
+
+    #[test]
+    fn close_open_blocks() {
+        assert_eq!(close_blocks("s{@{u"), "s{@{u}}");
+        assert_eq!(close_blocks("s{u}"), "s{u}");
+        assert_eq!(close_blocks("({#|s"), "({#|s})");
+    }
+}
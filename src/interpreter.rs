@@ -7,13 +7,18 @@ use regex::Regex;
 
 use crate::ast;
 use crate::error::{Error, Result};
+use crate::inputs::{Input, Inputs};
 use crate::operators::{
-    Ascend, Columnate, Count, DedupeSelectionWithCounts, DedupeWithCounts, DeleteEmpty, Descend,
-    Filter, Flatten, GroupBy, Join, JoinDelim, JoinMode, Lowercase, LowercaseSelected, MatchAll,
-    NoOp, Partition, Replace, Select, SortAscending, SortDescending, Split, SplitDelim, SplitMode,
-    Sum, ToNumber, ToNumberSelected, Trim, TrimSelected, Uppercase, UppercaseSelected,
+    Arithmetic, Ascend, Block, Columnate, Command, Conditional, Count, DEFAULT_WIDTH,
+    DedupeSelectionWithCounts, DedupeWithCounts, DeleteEmpty, Descend, Describe, Duration,
+    Enumerate, Filter, Flatten, Fork, Format, GroupAggregate, GroupBy, Histogram, Join, JoinDelim,
+    JoinInput, JoinMode, Key, Lowercase, LowercaseSelected, Map, MatchAll, NoOp, Partition,
+    PatternFilter, Reduce, Replace, Reverse, Running, Sampler, Select, SetInput, Shuffle,
+    SortAscending, SortBy, SortDescending, Sparkline, Split, SplitDelim, SplitMode, Sum, Time,
+    ToNumber, ToNumberSelected, Transpose, Trim, TrimSelected, Uppercase, UppercaseSelected,
+    Window, Zip,
 };
-use crate::value::Value;
+use crate::value::{Array, Value};
 
 /// Configuration for the compiler.
 #[derive(Debug, Clone, Default)]
@@ -22,6 +27,27 @@ pub struct CompileConfig {
     pub split_mode: SplitMode,
     /// Mode for the `j` (join) operator
     pub join_mode: JoinMode,
+    /// Seed for the `~` (shuffle and sample) operators, for reproducible runs
+    pub seed: Option<u64>,
+    /// Named inputs for the `*` (join) operator
+    pub inputs: Inputs,
+    /// Whether the `!{}` (command) operator may run shell commands
+    pub commands: Commands,
+    /// Width of the terminal, which the `h` (histogram) and `H` (sparkline)
+    /// operators draw to, if known
+    pub width: Option<usize>,
+}
+
+/// Whether shell commands may run. They are opt-in, so that running an
+/// untrusted programme can't spawn processes.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Commands {
+    #[default]
+    Disabled,
+    Enabled,
+    /// Enabled, but not while previewing an interactive programme, where a
+    /// half-typed command would run on every keystroke
+    Preview,
 }
 
 /// A transform operator converts a value to a new value.
@@ -29,6 +55,12 @@ pub trait Transform {
     /// Apply the transformation to a value.
     fn apply(&self, value: Value) -> Result<Value>;
 
+    /// Apply the transformation to a borrowed value, for transforms that only
+    /// read their input. Returns None if the transform needs to own it.
+    fn apply_ref(&self, _value: &Value) -> Option<Result<Value>> {
+        None
+    }
+
     /// Returns true if this operator requires seeing all input to produce correct output.
     /// Operators like sort, dedupe, count, sum need full input and cannot use truncation.
     fn requires_full_input(&self) -> bool {
@@ -117,11 +149,7 @@ fn replace_at_depth(value: Value, depth: usize, op: &dyn Transform) -> Result<Va
     } else {
         match value {
             Value::Array(mut arr) => {
-                arr.elements = arr
-                    .elements
-                    .into_iter()
-                    .map(|v| replace_at_depth(v, depth - 1, op))
-                    .collect::<Result<Vec<_>>>()?;
+                arr.elements = try_map_elements(&mut arr, |v| replace_at_depth(v, depth - 1, op))?;
                 Ok(Value::Array(arr))
             }
             // At depth > 0 but not an array: just return unchanged
@@ -131,6 +159,30 @@ fn replace_at_depth(value: Value, depth: usize, op: &dyn Transform) -> Result<Va
     }
 }
 
+/// Map each element of an array in turn, stopping at the first error.
+///
+/// The elements are taken out of the array, for the caller to put back what
+/// they map to. An error in an element read from input points at its line,
+/// unless it already points at one.
+pub fn try_map_elements<T>(
+    arr: &mut Array,
+    mut f: impl FnMut(Value) -> Result<T>,
+) -> Result<Vec<T>> {
+    let lines: Option<Vec<usize>> = arr
+        .origins()
+        .map(|origins| origins.iter().map(|origin| origin.line).collect());
+    std::mem::take(&mut arr.elements)
+        .into_iter()
+        .enumerate()
+        .map(|(i, elem)| {
+            f(elem).map_err(|e| match &lines {
+                Some(lines) if e.position.input_line.is_none() => e.with_input_line(lines[i]),
+                _ => e,
+            })
+        })
+        .collect()
+}
+
 /// Run a programme (sequence of operators) on a context.
 pub fn run(ops: &[Operator], ctx: &mut Context) -> Result<()> {
     for op in ops {
@@ -164,6 +216,16 @@ pub fn compile_with_config(
         .collect()
 }
 
+/// Look up an input loaded with `--with`.
+fn named_input<'a>(name: &str, config: &'a CompileConfig) -> Result<&'a Input> {
+    config.inputs.get(name).ok_or_else(|| {
+        Error::runtime(format!(
+            "unknown input '{}' (load one with --with {}=<file>)",
+            name, name
+        ))
+    })
+}
+
 /// Compile a single AST operator into an Operator.
 ///
 /// Returns an error if a regex pattern is invalid.
@@ -211,30 +273,211 @@ fn compile_op(op: &ast::Operator, config: &CompileConfig) -> Result<Operator> {
             Operator::Transform(Box::new(TrimSelected::new(sel.clone())))
         }
         ast::Operator::DeleteEmpty => Operator::Transform(Box::new(DeleteEmpty)),
+        ast::Operator::Enumerate => Operator::Transform(Box::new(Enumerate)),
+        ast::Operator::Transpose => Operator::Transform(Box::new(Transpose)),
+        ast::Operator::Zip => Operator::Transform(Box::new(Zip)),
         ast::Operator::Flatten => Operator::Transform(Box::new(Flatten)),
         ast::Operator::DedupeWithCounts => Operator::Transform(Box::new(DedupeWithCounts)),
-        ast::Operator::DedupeSelectionWithCounts(sel) => {
+        ast::Operator::DedupeSelectionWithCounts(ast::Key::Selection(sel)) => {
             Operator::Transform(Box::new(DedupeSelectionWithCounts::new(sel.clone())))
         }
+        ast::Operator::DedupeSelectionWithCounts(ast::Key::Programme(key)) => {
+            Operator::Transform(Box::new(DedupeSelectionWithCounts::with_key(
+                Key::Programme(compile_with_config(key, config)?),
+            )))
+        }
         ast::Operator::Sum => Operator::Transform(Box::new(Sum)),
         ast::Operator::Count => Operator::Transform(Box::new(Count)),
         ast::Operator::Columnate => Operator::Transform(Box::new(Columnate)),
+        ast::Operator::Histogram {
+            percent,
+            cumulative,
+        } => Operator::Transform(Box::new(Histogram::new(
+            *percent,
+            *cumulative,
+            config.width.unwrap_or(DEFAULT_WIDTH),
+        ))),
+        ast::Operator::Sparkline => Operator::Transform(Box::new(Sparkline::new(
+            config.width.unwrap_or(DEFAULT_WIDTH),
+        ))),
         ast::Operator::Partition(sel) => Operator::Transform(Box::new(Partition::new(sel.clone()))),
         ast::Operator::SortDescending => Operator::Transform(Box::new(SortDescending)),
         ast::Operator::SortAscending => Operator::Transform(Box::new(SortAscending)),
+        ast::Operator::SortDescendingBy(key) => Operator::Transform(Box::new(SortBy::new(
+            Key::Programme(compile_with_config(key, config)?),
+            true,
+        ))),
+        ast::Operator::SortAscendingBy(key) => Operator::Transform(Box::new(SortBy::new(
+            Key::Programme(compile_with_config(key, config)?),
+            false,
+        ))),
         ast::Operator::Selection(sel) => Operator::Transform(Box::new(Select::new(sel.clone()))),
+        ast::Operator::Format(parts) => Operator::Transform(Box::new(Format::new(parts.clone()))),
+        ast::Operator::Time { selection, op } => {
+            Operator::Transform(Box::new(Time::new(selection.clone(), op.clone())))
+        }
+        ast::Operator::Duration { from, to } => {
+            Operator::Transform(Box::new(Duration::new(*from, *to)))
+        }
+        ast::Operator::Arithmetic(expr) => {
+            Operator::Transform(Box::new(Arithmetic::new(expr.clone())))
+        }
+        ast::Operator::Command { command, batched } => match config.commands {
+            Commands::Enabled => {
+                Operator::Transform(Box::new(Command::new(command.clone(), *batched)))
+            }
+            Commands::Disabled => {
+                return Err(Error::runtime(
+                    "shell commands are disabled (enable them with --allow-commands)",
+                ));
+            }
+            Commands::Preview => {
+                return Err(Error::runtime(
+                    "shell commands don't run in the preview (press Enter to run the programme)",
+                ));
+            }
+        },
         ast::Operator::Filter { pattern, negate } => {
             let regex = Regex::new(pattern)
                 .map_err(|e| Error::runtime(format!("invalid regex '{}': {}", pattern, e)))?;
             Operator::Transform(Box::new(Filter::new(regex, *negate)))
         }
+        ast::Operator::FilterAll(clauses) => {
+            let clauses = clauses
+                .iter()
+                .map(|clause| {
+                    Regex::new(&clause.pattern)
+                        .map(|regex| (regex, clause.negate))
+                        .map_err(|e| {
+                            Error::runtime(format!("invalid regex '{}': {}", clause.pattern, e))
+                        })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Operator::Transform(Box::new(Filter::all(clauses)))
+        }
         ast::Operator::Match { pattern } => {
             let regex = Regex::new(pattern)
                 .map_err(|e| Error::runtime(format!("invalid regex '{}': {}", pattern, e)))?;
             Operator::Transform(Box::new(MatchAll::new(regex)))
         }
-        ast::Operator::GroupBy(sel) => Operator::Transform(Box::new(GroupBy::new(sel.clone()))),
+        ast::Operator::GroupBy(ast::Key::Selection(sel)) => {
+            Operator::Transform(Box::new(GroupBy::new(sel.clone())))
+        }
+        ast::Operator::GroupBy(ast::Key::Programme(key)) => Operator::Transform(Box::new(
+            GroupBy::with_key(Key::Programme(compile_with_config(key, config)?)),
+        )),
+        ast::Operator::GroupAggregate { key, aggregates } => Operator::Transform(Box::new(
+            GroupAggregate::new(compile_key(key, config)?, aggregates.clone()),
+        )),
+        ast::Operator::Statistic(statistic) => {
+            Operator::Transform(Box::new(Reduce::new(statistic.clone())))
+        }
+        ast::Operator::Describe => Operator::Transform(Box::new(Describe)),
+        ast::Operator::Scan(scan) => Operator::Transform(Box::new(Running::new(scan.clone()))),
+        ast::Operator::Window(size) => Operator::Transform(Box::new(Window::new(*size))),
+        ast::Operator::JoinInput {
+            input,
+            kind,
+            left,
+            right,
+        } => Operator::Transform(Box::new(JoinInput::new(
+            named_input(input, config)?.rows.clone(),
+            kind.clone(),
+            left.clone(),
+            right.clone(),
+        ))),
+        ast::Operator::PatternFilter {
+            input,
+            regex,
+            negate,
+            selection,
+        } => {
+            let patterns: Vec<String> = match &*named_input(input, config)?.lines {
+                Value::Array(lines) => lines
+                    .iter()
+                    .map(|line| line.to_string())
+                    .filter(|line| !line.is_empty())
+                    .collect(),
+                other => vec![other.to_string()],
+            };
+            let filter = if *regex {
+                PatternFilter::regex(patterns, selection.clone(), *negate)
+            } else {
+                PatternFilter::literal(patterns, selection.clone(), *negate)
+            };
+            Operator::Transform(Box::new(
+                filter.map_err(|e| Error::runtime(format!("input '{}': {}", input, e.message)))?,
+            ))
+        }
+        ast::Operator::Map {
+            input,
+            selection,
+            unmapped,
+        } => {
+            let dictionary = named_input(input, config)?
+                .dictionary()
+                .map_err(|e| Error::runtime(format!("input '{}': {}", input, e)))?;
+            Operator::Transform(Box::new(Map::new(
+                dictionary,
+                selection.clone(),
+                unmapped.clone(),
+            )))
+        }
+        ast::Operator::SetInput {
+            input,
+            operation,
+            key,
+        } => Operator::Transform(Box::new(SetInput::new(
+            operation.clone(),
+            key.clone(),
+            named_input(input, config)?.clone(),
+        ))),
+        ast::Operator::Reverse => Operator::Transform(Box::new(Reverse)),
+        ast::Operator::Shuffle => Operator::Transform(Box::new(Shuffle::new(config.seed))),
+        ast::Operator::Sample(sample) => {
+            Operator::Transform(Box::new(Sampler::new(sample.clone(), config.seed)))
+        }
         ast::Operator::NoOp => Operator::Transform(Box::new(NoOp)),
+        ast::Operator::Block(programme) => Operator::Transform(Box::new(Block::new(
+            compile_with_config(programme, config)?,
+        ))),
+        ast::Operator::Fork(branches) => {
+            let branches = branches
+                .iter()
+                .map(|branch| compile_with_config(branch, config))
+                .collect::<Result<Vec<_>>>()?;
+            Operator::Transform(Box::new(Fork::new(branches)))
+        }
+        ast::Operator::Conditional {
+            selection,
+            pattern,
+            then,
+            otherwise,
+        } => {
+            let regex = Regex::new(pattern)
+                .map_err(|e| Error::runtime(format!("invalid regex '{}': {}", pattern, e)))?;
+            let otherwise = match otherwise {
+                Some(otherwise) => Some(compile_with_config(otherwise, config)?),
+                None => None,
+            };
+            Operator::Transform(Box::new(Conditional::new(
+                regex,
+                selection.clone(),
+                compile_with_config(then, config)?,
+                otherwise,
+            )))
+        }
+        ast::Operator::Macro(name) => {
+            return Err(Error::runtime(format!("unexpanded macro &{}", name)));
+        }
+    })
+}
+
+/// Compile a grouping, deduping or sorting key.
+fn compile_key(key: &ast::Key, config: &CompileConfig) -> Result<Key> {
+    Ok(match key {
+        ast::Key::Selection(sel) => Key::Selection(sel.clone()),
+        ast::Key::Programme(programme) => Key::Programme(compile_with_config(programme, config)?),
     })
 }
 
@@ -289,6 +532,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn element_error_reports_input_line() {
+        let path =
+            std::env::temp_dir().join(format!("t_test_input_line_{}.txt", std::process::id()));
+        std::fs::write(&path, "1\n2\n").unwrap();
+        let input = Array::from_files(&[&path], Level::Line).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let programme = crate::parser::parse_programme("n@0").unwrap();
+        let ops = compile(&programme).unwrap();
+        let mut ctx = Context::new(Value::Array(input));
+        let err = run(&ops, &mut ctx).unwrap_err();
+        assert_eq!(err.position.input_line, Some(1));
+    }
+
     #[test]
     fn compile_simple_programme() {
         let programme = ast::Programme {
@@ -305,6 +563,17 @@ mod tests {
         assert_eq!(ops.len(), 5);
     }
 
+    #[test]
+    fn block_restores_depth() {
+        // The `@` inside the block doesn't leak, so `0` selects the first line
+        let programme = crate::parser::parse_programme("s{@0}0").unwrap();
+        let ops = compile(&programme).unwrap();
+        let mut ctx = Context::new(line_array(&["ab cd", "ef gh"]));
+        run(&ops, &mut ctx).unwrap();
+        assert_eq!(ctx.depth(), 0);
+        assert_eq!(ctx.into_value(), text("ab"));
+    }
+
     #[test]
     fn compile_filter() {
         let programme = ast::Programme {
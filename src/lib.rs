@@ -1,6 +1,11 @@
 pub mod ast;
 pub mod error;
+pub mod inputs;
 pub mod interpreter;
+pub mod macros;
 pub mod operators;
+pub mod optimiser;
 pub mod parser;
+pub mod render;
 pub mod value;
+pub mod variables;
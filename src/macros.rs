@@ -0,0 +1,112 @@
+//! Named macros loaded from a definitions file.
+//!
+//! Each line of the file defines one macro as `<name> = <programme>`. Blank
+//! lines and lines starting with `--` are ignored. Programmes invoke a macro
+//! as `&<name>`, and invocations are expanded at parse time.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use winnow::Parser;
+
+use crate::parser::macro_name;
+
+/// A set of named macro definitions.
+#[derive(Debug, Clone, Default)]
+pub struct Macros {
+    definitions: HashMap<String, String>,
+}
+
+impl Macros {
+    /// Load macros from the default location, or none if there is no file there.
+    pub fn load_default() -> Result<Self, String> {
+        match default_path() {
+            Some(path) if path.exists() => Self::load(&path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// Load macros from a file.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::parse(&source).map_err(|e| format!("{}:{}", path.display(), e))
+    }
+
+    /// Parse macro definitions. Errors are prefixed with the 1-based line number.
+    ///
+    /// Bodies are not parsed until they are invoked, so a broken macro only
+    /// affects programmes that use it.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut definitions = HashMap::new();
+        for (i, line) in source.lines().enumerate() {
+            let line = line.trim_start();
+            if line.is_empty() || line.starts_with("--") {
+                continue;
+            }
+            let Some((name, body)) = line.split_once('=') else {
+                return Err(format!("{}: expected <name> = <programme>", i + 1));
+            };
+            let name = name.trim_end();
+            if macro_name.parse(name).is_err() {
+                return Err(format!("{}: invalid macro name '{}'", i + 1, name));
+            }
+            // Only leading whitespace is insignificant: a body may end in `S `
+            let body = body.trim_start().to_string();
+            if definitions.insert(name.to_string(), body).is_some() {
+                return Err(format!("{}: duplicate macro '{}'", i + 1, name));
+            }
+        }
+        Ok(Self { definitions })
+    }
+
+    /// Get the body of a macro.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.definitions.get(name).map(String::as_str)
+    }
+}
+
+/// The default macros file: `<config dir>/t/macros`.
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|p| p.join("t").join("macros"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_definitions() {
+        let macros = Macros::parse(
+            "-- network helpers\n\nips = m/\\d+\\.\\d+\\.\\d+\\.\\d+/f\n  fields=S:@0,-1\n",
+        )
+        .unwrap();
+        assert_eq!(macros.get("ips"), Some("m/\\d+\\.\\d+\\.\\d+\\.\\d+/f"));
+        assert_eq!(macros.get("fields"), Some("S:@0,-1"));
+        assert_eq!(macros.get("missing"), None);
+    }
+
+    #[test]
+    fn body_keeps_trailing_whitespace() {
+        let macros = Macros::parse("words = S ").unwrap();
+        assert_eq!(macros.get("words"), Some("S "));
+    }
+
+    #[test]
+    fn missing_equals_error() {
+        let err = Macros::parse("ok = s\nbroken\n").unwrap_err();
+        assert_eq!(err, "2: expected <name> = <programme>");
+    }
+
+    #[test]
+    fn invalid_name_error() {
+        let err = Macros::parse("1st = s").unwrap_err();
+        assert_eq!(err, "1: invalid macro name '1st'");
+    }
+
+    #[test]
+    fn duplicate_name_error() {
+        let err = Macros::parse("a = s\na = j").unwrap_err();
+        assert_eq!(err, "2: duplicate macro 'a'");
+    }
+}
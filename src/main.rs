@@ -1,19 +1,28 @@
 use std::io::{self, IsTerminal, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use clap::{CommandFactory, Parser};
+use clap::{CommandFactory, Parser, ValueEnum};
 
 mod ast;
 mod error;
+mod inputs;
 mod interactive;
 mod interpreter;
+mod macros;
 mod operators;
+mod optimiser;
 mod parser;
+mod render;
 mod value;
+mod variables;
 
-use interpreter::{CompileConfig, Context};
-use operators::{JoinMode, SplitMode};
-use value::{Array, Level, Value};
+use inputs::Inputs;
+use interpreter::{Commands, CompileConfig, Context};
+use macros::Macros;
+use operators::{JoinMode, Sampler, SplitMode};
+use render::Format;
+use value::{Array, Level, Origin, Value};
+use variables::Variables;
 
 const ABOUT_INTRO: &str = r#"T is a concise language for manipulating text, replacing common usage
 patterns of Unix utilities like grep, sed, cut, awk, sort, and uniq.
@@ -34,6 +43,9 @@ Top 20 most frequent words (lowercased):
 
 const ABOUT_FOOTER: &str = "For full documentation, see: https://github.com/alecthomas/t";
 
+/// The widest charts are drawn.
+const MAX_WIDTH: usize = 10_000;
+
 fn about_text() -> String {
     format!(
         "{}\n{}\n\n{}",
@@ -65,6 +77,15 @@ struct Cli {
     /// Optional files to process
     files: Vec<String>,
 
+    /// Read the programme from a file (the first positional argument is then a file to process)
+    #[arg(
+        short = 'f',
+        long = "file",
+        value_name = "FILE",
+        conflicts_with = "interactive"
+    )]
+    programme_file: Option<PathBuf>,
+
     /// Output as JSON
     #[arg(short = 'j', long = "json")]
     json: bool,
@@ -89,9 +110,53 @@ struct Cli {
     #[arg(short = 'c', long = "csv")]
     csv: bool,
 
+    /// Output format: csv, tsv, ndjson, markdown, html or yaml
+    #[arg(long = "output", value_name = "FORMAT", conflicts_with_all = ["json", "debug", "line_numbers"])]
+    output: Option<Format>,
+
+    /// Take the first row as the header of a table (with --output markdown or html)
+    #[arg(long = "header", requires = "output")]
+    header: bool,
+
     /// Debug mode (show semantic level before arrays)
     #[arg(long = "debug")]
     debug: bool,
+
+    /// Seed the random number generator for shuffling and sampling
+    #[arg(long = "seed", value_name = "N")]
+    seed: Option<u64>,
+
+    /// Width to draw charts to, up to 10000 (default: the terminal's, or $COLUMNS, or 80)
+    #[arg(long = "width", value_name = "N")]
+    width: Option<usize>,
+
+    /// Prefix each output line with the file and line number it was read from
+    #[arg(short = 'n', long = "line-numbers", conflicts_with_all = ["json", "debug"])]
+    line_numbers: bool,
+
+    /// Let the programme run shell commands with `!{<command>}`
+    #[arg(long = "allow-commands")]
+    allow_commands: bool,
+
+    /// Load a file as a named input for joins, e.g. --with users=users.csv
+    #[arg(long = "with", value_name = "NAME=FILE")]
+    with: Vec<String>,
+
+    /// Set a variable for `{$name}` references in the programme, e.g. --arg ip=1.2.3.4
+    #[arg(long = "arg", value_name = "NAME=VALUE")]
+    arg: Vec<String>,
+
+    /// Macro definitions file (default: <config dir>/t/macros)
+    #[arg(long = "macros", value_name = "FILE")]
+    macros: Option<PathBuf>,
+
+    /// Print the programme in canonical form and exit
+    #[arg(long = "fmt")]
+    fmt: bool,
+
+    /// Print one operator group per line (with --fmt)
+    #[arg(long = "pretty", requires = "fmt")]
+    pretty: bool,
 }
 
 fn main() {
@@ -113,25 +178,61 @@ fn main() {
 
     let cli = Cli::parse();
 
-    // In interactive mode, prog is treated as the first file argument
-    let (prog, files) = if cli.interactive {
+    // In interactive mode or with -f, prog is treated as the first file argument
+    let (prog, files) = if cli.interactive || cli.programme_file.is_some() {
         let mut all_files = Vec::new();
         if !cli.prog.is_empty() {
             all_files.push(cli.prog.clone());
         }
         all_files.extend(cli.files.iter().cloned());
-        (String::new(), all_files)
+        let prog = match &cli.programme_file {
+            Some(path) => read_programme_file(path),
+            None => String::new(),
+        };
+        (prog, all_files)
     } else {
         (cli.prog.clone(), cli.files.clone())
     };
 
+    if cli.fmt {
+        format_programme(&prog, cli.pretty);
+        return;
+    }
+
     if cli.interactive && files.is_empty() {
         eprintln!("Error: interactive mode requires file arguments (cannot read from stdin)");
         std::process::exit(1);
     }
 
+    let macros = match &cli.macros {
+        Some(path) => Macros::load(path),
+        None => Macros::load_default(),
+    };
+    let macros = match macros {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("Error loading macros: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let variables = match Variables::load(&cli.arg) {
+        Ok(variables) => variables,
+        Err(e) => {
+            eprintln!("Error in --arg: {}", e);
+            std::process::exit(1);
+        }
+    };
+
     // Build compile config from CLI flags
-    let config = build_compile_config(&cli);
+    let mut config = build_compile_config(&cli);
+    config.inputs = match Inputs::load(&cli.with, &config.split_mode) {
+        Ok(inputs) => inputs,
+        Err(e) => {
+            eprintln!("Error loading input: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     // Check which files are regular files (before reading, as pipes become invalid after)
     let regular_files: Vec<_> = files
@@ -144,32 +245,97 @@ fn main() {
         .cloned()
         .collect();
 
+    let output = Output {
+        json: cli.json,
+        debug: cli.debug,
+        line_numbers: cli.line_numbers,
+        format: cli.output,
+        header: cli.header,
+    };
+    if cli.interactive {
+        run_interactive(
+            read_input(&files),
+            &regular_files,
+            cli.print_command.then(|| rerun_flags(&cli)),
+            output,
+            &config,
+            &macros,
+            &variables,
+        );
+    } else {
+        run_batch(
+            &prog,
+            BatchInput::Files(&files),
+            &output,
+            &config,
+            &macros,
+            &variables,
+        );
+    }
+}
+
+/// Read the input files, or stdin if there are none, as lines.
+fn read_input(files: &[String]) -> Array {
     let input = if files.is_empty() {
         Array::from_stdin(Level::Line)
     } else {
         let paths: Vec<PathBuf> = files.iter().map(PathBuf::from).collect();
         Array::from_files(&paths, Level::Line)
     };
-
-    let array = match input {
+    match input {
         Ok(a) => a,
         Err(e) => {
             eprintln!("Error reading input: {}", e);
             std::process::exit(1);
         }
-    };
+    }
+}
 
-    if cli.interactive {
-        run_interactive(
-            array,
-            &regular_files,
-            cli.print_command,
-            cli.json,
-            cli.debug,
-            &config,
-        );
+/// Read the input like `read_input`, sampling the lines as they are read so
+/// that only the sample is held in memory.
+fn sample_input(files: &[String], sampler: &Sampler) -> Array {
+    let lines: Box<dyn Iterator<Item = io::Result<(Value, Origin)>>> = if files.is_empty() {
+        Box::new(Array::stdin_lines())
     } else {
-        run_batch(&prog, array, cli.json, cli.debug, &config);
+        Box::new(Array::file_lines(files))
+    };
+    let (elements, origins) = match sampler.sample(lines) {
+        Ok(sample) => sample.into_iter().unzip(),
+        Err(e) => {
+            eprintln!("Error reading input: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut array = Array::from((elements, Level::Line));
+    array.set_origins(Some(origins));
+    array
+}
+
+/// Read a programme file, blanking out a leading `#!` line so the file can be
+/// made executable while keeping line numbers in errors accurate.
+fn read_programme_file(path: &Path) -> String {
+    let mut source = match std::fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Error reading programme file {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    };
+    if source.starts_with("#!") {
+        let end = source.find('\n').unwrap_or(source.len());
+        source.replace_range(..end, "");
+    }
+    source
+}
+
+fn format_programme(prog: &str, pretty: bool) {
+    match parser::parse_programme(prog) {
+        Ok(programme) if pretty => println!("{}", programme.pretty()),
+        Ok(programme) => println!("{}", programme),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
     }
 }
 
@@ -193,38 +359,124 @@ fn build_compile_config(cli: &Cli) -> CompileConfig {
     CompileConfig {
         split_mode,
         join_mode,
+        seed: cli.seed,
+        inputs: Inputs::default(),
+        commands: if cli.allow_commands {
+            Commands::Enabled
+        } else {
+            Commands::Disabled
+        },
+        width: cli
+            .width
+            .or_else(terminal_width)
+            .map(|width| width.min(MAX_WIDTH)),
     }
 }
 
+/// The width of the terminal, from `$COLUMNS` or else the terminal itself.
+fn terminal_width() -> Option<usize> {
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .or_else(|| {
+            crossterm::terminal::size()
+                .ok()
+                .map(|(columns, _)| usize::from(columns))
+        })
+}
+
+/// The flags a programme needs to run the same way again: its named inputs,
+/// variables, seed and permission to run commands.
+fn rerun_flags(cli: &Cli) -> Vec<String> {
+    let mut flags = Vec::new();
+    for with in &cli.with {
+        flags.extend(["--with".to_string(), with.clone()]);
+    }
+    for arg in &cli.arg {
+        flags.extend(["--arg".to_string(), arg.clone()]);
+    }
+    if let Some(seed) = cli.seed {
+        flags.extend(["--seed".to_string(), seed.to_string()]);
+    }
+    if cli.allow_commands {
+        flags.push("--allow-commands".to_string());
+    }
+    flags
+}
+
+/// A word quoted for the shell if it needs to be.
+fn shell_word(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_alphanumeric() || "_-./=:,@%+".contains(c));
+    if plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+/// Run interactively, then the committed programme on the full input. With
+/// `print_command`, also print the command line to rerun it, with these flags.
 fn run_interactive(
     input: Array,
     files: &[String],
-    print_command: bool,
-    json: bool,
-    debug: bool,
+    print_command: Option<Vec<String>>,
+    output: Output,
     config: &CompileConfig,
+    macros: &Macros,
+    variables: &Variables,
 ) {
-    let mut mode =
-        interactive::InteractiveMode::new_with_config(input, json, debug, config.clone());
+    let mut mode = interactive::InteractiveMode::new_with_config(
+        input,
+        output.json,
+        output.debug,
+        config.clone(),
+        macros.clone(),
+        variables.clone(),
+    );
     match mode.run() {
         Ok(Some((prog, json, debug))) => {
             // User committed - run full programme on full input
             let input = mode.full_input();
-            run_batch(&prog, input, json, debug, config);
-
-            // Print equivalent command line
-            if print_command {
+            let output = Output {
+                json,
+                debug,
+                ..output
+            };
+            run_batch(
+                &prog,
+                BatchInput::Loaded(input),
+                &output,
+                config,
+                macros,
+                variables,
+            );
+
+            // Print equivalent command line, with the programme in canonical form
+            if let Some(flags) = print_command {
                 eprint!("t");
                 if json {
                     eprint!(" -j");
+                } else if let Some(format) = output.format {
+                    let name = format.to_possible_value().expect("no skipped formats");
+                    eprint!(" --output {}", name.get_name());
+                    if output.header {
+                        eprint!(" --header");
+                    }
+                } else if output.line_numbers {
+                    eprint!(" -n");
+                }
+                for flag in &flags {
+                    eprint!(" {}", shell_word(flag));
                 }
-                eprint!(" '{}'", prog);
+                let prog = parser::parse_programme(&prog)
+                    .map(|p| p.to_string())
+                    .unwrap_or(prog);
+                eprint!(" '{}'", prog.replace('\'', "'\\''"));
                 for file in files {
-                    if file.contains(char::is_whitespace) || file.contains('\'') {
-                        eprint!(" '{}'", file.replace('\'', "'\\''"));
-                    } else {
-                        eprint!(" {}", file);
-                    }
+                    eprint!(" {}", shell_word(file));
                 }
                 eprintln!();
             }
@@ -239,8 +491,32 @@ fn run_interactive(
     }
 }
 
-fn run_batch(prog: &str, array: Array, json: bool, debug: bool, config: &CompileConfig) {
-    let programme = match parser::parse_programme(prog) {
+/// How to print the result of a programme.
+struct Output {
+    json: bool,
+    debug: bool,
+    line_numbers: bool,
+    format: Option<Format>,
+    header: bool,
+}
+
+/// Where a batch run gets its input.
+enum BatchInput<'a> {
+    /// Input that has already been read, as in interactive mode.
+    Loaded(Array),
+    /// Files to read, or stdin if there are none.
+    Files(&'a [String]),
+}
+
+fn run_batch(
+    prog: &str,
+    input: BatchInput,
+    output: &Output,
+    config: &CompileConfig,
+    macros: &Macros,
+    variables: &Variables,
+) {
+    let programme = match parser::parse_programme_with_macros(prog, macros, variables) {
         Ok(p) => p,
         Err(e) => {
             eprintln!("{}", e);
@@ -248,6 +524,17 @@ fn run_batch(prog: &str, array: Array, json: bool, debug: bool, config: &Compile
         }
     };
 
+    let programme = optimiser::optimise(programme);
+    // A leading sample is drawn while the input is read
+    let (sampler, programme) = match (&input, programme.operators.first()) {
+        (BatchInput::Files(_), Some(ast::Operator::Sample(sample))) => (
+            Some(Sampler::new(sample.clone(), config.seed)),
+            ast::Programme {
+                operators: programme.operators[1..].to_vec(),
+            },
+        ),
+        _ => (None, programme),
+    };
     let ops = match interpreter::compile_with_config(&programme, config) {
         Ok(o) => o,
         Err(e) => {
@@ -255,6 +542,11 @@ fn run_batch(prog: &str, array: Array, json: bool, debug: bool, config: &Compile
             std::process::exit(1);
         }
     };
+    let array = match (input, &sampler) {
+        (BatchInput::Loaded(array), _) => array,
+        (BatchInput::Files(files), Some(sampler)) => sample_input(files, sampler),
+        (BatchInput::Files(files), None) => read_input(files),
+    };
     let mut ctx = Context::new(Value::Array(array));
 
     if let Err(e) = interpreter::run(&ops, &mut ctx) {
@@ -266,12 +558,16 @@ fn run_batch(prog: &str, array: Array, json: bool, debug: bool, config: &Compile
     let stdout = io::stdout();
     let use_color = stdout.is_terminal();
     let mut handle = stdout.lock();
-    let result = if debug {
+    let result = if output.debug {
         interactive::write_json_debug(&mut handle, &value, use_color)
             .and_then(|()| writeln!(handle))
-    } else if json {
+    } else if output.json {
         interactive::write_json_highlighted(&mut handle, &value, use_color)
             .and_then(|()| writeln!(handle))
+    } else if let Some(format) = output.format {
+        format.renderer(output.header).render(&value, &mut handle)
+    } else if output.line_numbers {
+        write_with_origins(&mut handle, &value)
     } else {
         write!(handle, "{}", value).and_then(|()| writeln!(handle))
     };
@@ -282,3 +578,21 @@ fn run_batch(prog: &str, array: Array, json: bool, debug: bool, config: &Compile
         std::process::exit(1);
     }
 }
+
+/// Write each element prefixed with the file and line it was read from, like
+/// `grep -Hn`, or just the line for stdin.
+fn write_with_origins(out: &mut impl Write, value: &Value) -> io::Result<()> {
+    if let Value::Array(arr) = value
+        && let Some(origins) = arr.origins()
+    {
+        for (elem, origin) in arr.elements.iter().zip(origins) {
+            if let Some(file) = &origin.file {
+                write!(out, "{}:", file)?;
+            }
+            writeln!(out, "{}:{}", origin.line, elem)?;
+        }
+        return Ok(());
+    }
+    eprintln!("Warning: line numbers are lost once lines are combined or rearranged");
+    writeln!(out, "{}", value)
+}
@@ -0,0 +1,208 @@
+use crate::ast::{BinaryOp, Expr, Function};
+use crate::error::{Error, Result};
+use crate::interpreter::{Transform, try_map_elements};
+use crate::value::Value;
+
+use super::sum::to_number;
+
+/// Replaces each element with the number an expression computes from its
+/// fields, or from the element itself with `$`.
+///
+/// Fields may be numbers or text that parses as a number. A field that is
+/// neither, a missing field, division by zero and undefined results such as
+/// `sqrt(-1)` and `log(0)` are errors, naming the part of the expression that failed.
+pub struct Arithmetic {
+    expr: Expr,
+}
+
+impl Arithmetic {
+    pub fn new(expr: Expr) -> Self {
+        Self { expr }
+    }
+}
+
+impl Transform for Arithmetic {
+    fn apply(&self, value: Value) -> Result<Value> {
+        match value {
+            Value::Array(mut arr) => {
+                arr.elements =
+                    try_map_elements(&mut arr, |elem| eval(&self.expr, &elem).map(Value::Number))?;
+                Ok(Value::Array(arr))
+            }
+            other => Ok(Value::Number(eval(&self.expr, &other)?)),
+        }
+    }
+}
+
+/// Evaluate an expression on an element.
+fn eval(expr: &Expr, elem: &Value) -> Result<f64> {
+    let n = match expr {
+        Expr::Number(n) => *n,
+        Expr::Field(None) => to_number(elem)
+            .ok_or_else(|| Error::runtime(format!("element is not a number: '{}'", elem)))?,
+        Expr::Field(Some(index)) => {
+            let field =
+                field(elem, *index).ok_or_else(|| Error::runtime(format!("no field {}", index)))?;
+            to_number(field).ok_or_else(|| {
+                Error::runtime(format!("field {} is not a number: '{}'", index, field))
+            })?
+        }
+        Expr::Negate(operand) => -eval(operand, elem)?,
+        Expr::Binary(op, left, right) => {
+            let (left, right) = (eval(left, elem)?, eval(right, elem)?);
+            match op {
+                BinaryOp::Add => left + right,
+                BinaryOp::Subtract => left - right,
+                BinaryOp::Multiply => left * right,
+                BinaryOp::Divide | BinaryOp::Remainder if right == 0.0 => {
+                    return Err(Error::runtime(format!("division by zero in '{}'", expr)));
+                }
+                BinaryOp::Divide => left / right,
+                BinaryOp::Remainder => left % right,
+                BinaryOp::Power => left.powf(right),
+            }
+        }
+        Expr::Call(function, args) => {
+            let x = eval(&args[0], elem)?;
+            match function {
+                Function::Log | Function::Log2 | Function::Log10 if x <= 0.0 => {
+                    return Err(Error::runtime(format!("'{}' is undefined", expr)));
+                }
+                Function::Abs => x.abs(),
+                Function::Ceil => x.ceil(),
+                Function::Floor => x.floor(),
+                Function::Round => match args.get(1) {
+                    Some(digits) => {
+                        let scale = 10f64.powi(eval(digits, elem)? as i32);
+                        (x * scale).round() / scale
+                    }
+                    None => x.round(),
+                },
+                Function::Sqrt => x.sqrt(),
+                Function::Exp => x.exp(),
+                Function::Log => x.ln(),
+                Function::Log2 => x.log2(),
+                Function::Log10 => x.log10(),
+            }
+        }
+    };
+    if n.is_nan() {
+        return Err(Error::runtime(format!("'{}' is undefined", expr)));
+    }
+    Ok(n)
+}
+
+/// The field at `index` of an element, counting from the end if negative.
+fn field(elem: &Value, index: i64) -> Option<&Value> {
+    let Value::Array(arr) = elem else {
+        return None;
+    };
+    let len = arr.len() as i64;
+    let index = if index < 0 { len + index } else { index };
+    if index < 0 {
+        return None;
+    }
+    arr.elements.get(index as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_programme;
+    use crate::value::{Array, Level};
+
+    fn row(fields: &[&str]) -> Value {
+        Value::Array(Array::from((
+            fields.iter().map(|s| Value::Text(s.to_string())).collect(),
+            Level::Word,
+        )))
+    }
+
+    fn arithmetic(source: &str) -> Arithmetic {
+        match parse_programme(source).unwrap().operators.remove(0) {
+            crate::ast::Operator::Arithmetic(expr) => Arithmetic::new(expr),
+            op => panic!("not arithmetic: {:?}", op),
+        }
+    }
+
+    /// Evaluate on a single element.
+    fn eval(source: &str, elem: Value) -> Result<Value> {
+        let value = Value::Array(Array::from((vec![elem], Level::Line)));
+        match arithmetic(source).apply(value)? {
+            Value::Array(mut arr) => Ok(arr.elements.remove(0)),
+            other => Ok(other),
+        }
+    }
+
+    #[test]
+    fn fields_and_precedence() {
+        let elem = row(&["GET", "10", "25", "2048"]);
+        assert_eq!(
+            eval("={$2 - $1 * 2}", elem.deep_copy()).unwrap(),
+            Value::Number(5.0)
+        );
+        assert_eq!(
+            eval("=($2-$1)*2", elem.deep_copy()).unwrap(),
+            Value::Number(30.0)
+        );
+        assert_eq!(
+            eval("={$-1 / 1024}", elem.deep_copy()).unwrap(),
+            Value::Number(2.0)
+        );
+        assert_eq!(
+            eval("={-2 ** 2 + 7 % 4}", elem).unwrap(),
+            Value::Number(-1.0)
+        );
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(
+            eval("={round($ / 3, 2)}", Value::Number(10.0)).unwrap(),
+            Value::Number(3.33)
+        );
+        assert_eq!(
+            eval("={floor(abs(-2.5)) + log10(100)}", Value::Number(0.0)).unwrap(),
+            Value::Number(4.0)
+        );
+    }
+
+    #[test]
+    fn each_element_is_replaced() {
+        let rows = Value::Array(Array::from((
+            vec![row(&["1", "4"]), row(&["2", "3"])],
+            Level::Line,
+        )));
+        assert_eq!(
+            arithmetic("=$1-$0").apply(rows).unwrap(),
+            Value::Array(Array::from((
+                vec![Value::Number(3.0), Value::Number(1.0)],
+                Level::Line
+            )))
+        );
+    }
+
+    #[test]
+    fn division_by_zero_error() {
+        let err = eval("={$0 / ($1 - 1)}", row(&["3", "1"])).unwrap_err();
+        assert_eq!(err.message, "division by zero in '$0 / ($1 - 1)'");
+    }
+
+    #[test]
+    fn non_numeric_field_error() {
+        let err = eval("=$0*2", row(&["GET"])).unwrap_err();
+        assert_eq!(err.message, "field 0 is not a number: 'GET'");
+        let err = eval("=$3", row(&["1"])).unwrap_err();
+        assert_eq!(err.message, "no field 3");
+    }
+
+    #[test]
+    fn undefined_result_error() {
+        let err = eval("={sqrt($)}", Value::Number(-1.0)).unwrap_err();
+        assert_eq!(err.message, "'sqrt($)' is undefined");
+        let err = eval("={log($)}", Value::Number(0.0)).unwrap_err();
+        assert_eq!(err.message, "'log($)' is undefined");
+        let err = eval("={log10($ - 1)}", Value::Number(-1.0)).unwrap_err();
+        assert_eq!(err.message, "'log10($ - 1)' is undefined");
+    }
+}
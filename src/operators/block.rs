@@ -0,0 +1,86 @@
+use crate::error::Result;
+use crate::interpreter::{Context, Operator, Transform, run};
+use crate::value::Value;
+
+/// Runs a sub-programme on a value in a fresh context starting at depth 0.
+///
+/// Executed at the current depth like any transform, so the sub-programme
+/// runs once for each value at that depth, and navigation inside it can't
+/// change the depth outside.
+pub struct Block {
+    ops: Vec<Operator>,
+}
+
+impl Block {
+    pub fn new(ops: Vec<Operator>) -> Self {
+        Self { ops }
+    }
+}
+
+impl Transform for Block {
+    fn apply(&self, value: Value) -> Result<Value> {
+        let mut ctx = Context::new(value);
+        run(&self.ops, &mut ctx)?;
+        Ok(ctx.into_value())
+    }
+
+    fn requires_full_input(&self) -> bool {
+        self.ops.iter().any(|op| op.requires_full_input())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operators::{Ascend, Descend, Join, JoinMode, Select, Split, SplitMode, Uppercase};
+    use crate::value::{Array, Level};
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    fn line_array(lines: &[&str]) -> Value {
+        Value::Array(Array::from((
+            lines.iter().map(|s| text(s)).collect(),
+            Level::Line,
+        )))
+    }
+
+    fn first(index: i64) -> Operator {
+        Operator::Transform(Box::new(Select::new(crate::ast::Selection {
+            items: vec![crate::ast::SelectItem::Index(index)],
+        })))
+    }
+
+    #[test]
+    fn block_runs_programme() {
+        let block = Block::new(vec![
+            Operator::Transform(Box::new(Split::new(SplitMode::Whitespace))),
+            Operator::Navigate(Box::new(Descend)),
+            first(0),
+        ]);
+        let result = block.apply(line_array(&["a b", "c d"])).unwrap();
+        assert_eq!(result, line_array(&["a", "c"]));
+    }
+
+    #[test]
+    fn block_supports_navigation() {
+        let block = Block::new(vec![
+            Operator::Transform(Box::new(Split::new(SplitMode::Whitespace))),
+            Operator::Navigate(Box::new(Descend)),
+            Operator::Navigate(Box::new(Descend)),
+            Operator::Transform(Box::new(Uppercase)),
+            Operator::Navigate(Box::new(Ascend)),
+            Operator::Navigate(Box::new(Ascend)),
+            Operator::Transform(Box::new(Join::new(JoinMode::Semantic))),
+        ]);
+        let result = block.apply(line_array(&["a b"])).unwrap();
+        assert_eq!(result, line_array(&["A B"]));
+    }
+
+    #[test]
+    fn block_on_text() {
+        let block = Block::new(vec![Operator::Transform(Box::new(Uppercase))]);
+        assert_eq!(block.apply(text("abc")).unwrap(), text("ABC"));
+    }
+}
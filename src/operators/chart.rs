@@ -0,0 +1,287 @@
+use crate::error::{Error, Result};
+use crate::interpreter::{Transform, try_map_elements};
+use crate::value::{Array, Level, Value};
+
+use super::sum::to_number;
+
+/// The width charts are drawn to when the terminal's is unknown.
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// The narrowest a histogram's bars are drawn, however narrow the terminal.
+const MIN_BAR_WIDTH: usize = 10;
+
+/// Eighths of a block, for the ends of bars.
+const PARTIAL_BLOCKS: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+
+/// Sparkline levels, lowest to highest.
+const LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Draws `[count, label]` rows, as `d` makes, as a bar chart: a line per row
+/// of its label, its count and a bar scaled so the longest fills the width.
+///
+/// A row that is a single number is a count with no label. Optionally each
+/// line also shows the count's percentage of the total, and the cumulative
+/// percentage of the rows so far.
+pub struct Histogram {
+    percent: bool,
+    cumulative: bool,
+    width: usize,
+}
+
+impl Histogram {
+    pub fn new(percent: bool, cumulative: bool, width: usize) -> Self {
+        Self {
+            percent,
+            cumulative,
+            width,
+        }
+    }
+}
+
+impl Transform for Histogram {
+    fn apply(&self, value: Value) -> Result<Value> {
+        let mut arr = match value {
+            Value::Array(arr) => arr,
+            other => Array::from((vec![other], Level::Line)),
+        };
+        let rows = try_map_elements(&mut arr, |elem| row(&elem))?;
+
+        let total: f64 = rows.iter().map(|(count, _)| count).sum();
+        let max = rows.iter().map(|(count, _)| *count).fold(0.0, f64::max);
+        let share = |count: f64| {
+            let percent = if total > 0.0 {
+                count / total * 100.0
+            } else {
+                0.0
+            };
+            format!("{:.1}%", percent)
+        };
+        let mut running = 0.0;
+        let columns: Vec<Vec<String>> = rows
+            .iter()
+            .map(|(count, _)| {
+                running += count;
+                let mut columns = Vec::new();
+                if self.percent {
+                    columns.push(share(*count));
+                }
+                if self.cumulative {
+                    columns.push(share(running));
+                }
+                columns
+            })
+            .collect();
+
+        let counts: Vec<String> = rows
+            .iter()
+            .map(|(count, _)| Value::Number(*count).to_string())
+            .collect();
+        let label_width = rows.iter().map(|(_, label)| label.chars().count()).max();
+        let label_width = label_width.unwrap_or(0);
+        let count_width = counts.iter().map(String::len).max().unwrap_or(0);
+        let column_widths: Vec<usize> = (0..columns.first().map_or(0, Vec::len))
+            .map(|i| columns.iter().map(|c| c[i].len()).max().unwrap_or(0))
+            .collect();
+        let fixed = if label_width > 0 { label_width + 1 } else { 0 }
+            + count_width
+            + 1
+            + column_widths.iter().map(|w| w + 1).sum::<usize>();
+        let bar_width = self.width.saturating_sub(fixed).max(MIN_BAR_WIDTH);
+
+        let mut result = Vec::with_capacity(rows.len());
+        for (((count, label), count_text), columns) in rows.iter().zip(&counts).zip(&columns) {
+            let mut line = String::new();
+            if label_width > 0 {
+                let padding = label_width - label.chars().count();
+                line.push_str(label);
+                line.push_str(&" ".repeat(padding + 1));
+            }
+            line.push_str(&format!("{:>width$} ", count_text, width = count_width));
+            let eighths = if max > 0.0 {
+                (count / max * bar_width as f64 * 8.0).round() as usize
+            } else {
+                0
+            };
+            let bar = bar(eighths);
+            line.push_str(&bar);
+            if !columns.is_empty() {
+                line.push_str(&" ".repeat(bar_width - bar.chars().count()));
+            }
+            for (column, width) in columns.iter().zip(&column_widths) {
+                line.push_str(&format!(" {:>width$}", column, width = width));
+            }
+            result.push(Value::Text(line.trim_end().to_string()));
+        }
+        Ok(Value::Array(Array::from((result, Level::Line))))
+    }
+
+    fn requires_full_input(&self) -> bool {
+        true
+    }
+}
+
+/// The count and label of a histogram row.
+fn row(elem: &Value) -> Result<(f64, String)> {
+    let (count, label) = match elem {
+        Value::Array(row) => {
+            let Some(count) = row.elements.first() else {
+                return Err(Error::runtime("histogram row is empty"));
+            };
+            let label: Vec<String> = row.elements[1..].iter().map(Value::to_string).collect();
+            (count, label.join(" "))
+        }
+        other => (other, String::new()),
+    };
+    let n = to_number(count)
+        .ok_or_else(|| Error::runtime(format!("count is not a number: '{}'", count)))?;
+    if n < 0.0 {
+        return Err(Error::runtime(format!("count is negative: '{}'", count)));
+    }
+    Ok((n, label))
+}
+
+/// A bar `eighths` eighths of a block long.
+fn bar(eighths: usize) -> String {
+    let mut bar = "█".repeat(eighths / 8);
+    let rest = eighths % 8;
+    if rest > 0 {
+        bar.push(PARTIAL_BLOCKS[rest]);
+    }
+    bar
+}
+
+/// Draws a series of numbers as a sparkline: one text of a bar per number,
+/// from lowest to highest. A series wider than the terminal is averaged down
+/// to fit on one line.
+pub struct Sparkline {
+    width: usize,
+}
+
+impl Sparkline {
+    pub fn new(width: usize) -> Self {
+        Self { width }
+    }
+}
+
+impl Transform for Sparkline {
+    fn apply(&self, value: Value) -> Result<Value> {
+        let elements = match &value {
+            Value::Array(arr) => arr.elements.iter().collect(),
+            other => vec![other],
+        };
+        let mut series = Vec::with_capacity(elements.len());
+        for elem in elements {
+            series.push(
+                to_number(elem)
+                    .ok_or_else(|| Error::runtime(format!("not a number: '{}'", elem)))?,
+            );
+        }
+        let width = self.width.max(1);
+        if series.len() > width {
+            let len = series.len();
+            series = (0..width)
+                .map(|i| {
+                    let bucket = &series[i * len / width..(i + 1) * len / width];
+                    bucket.iter().sum::<f64>() / bucket.len() as f64
+                })
+                .collect();
+        }
+        let min = series.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = series.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let line = series
+            .iter()
+            .map(|n| {
+                if max > min {
+                    LEVELS[((n - min) / (max - min) * 7.0).round() as usize]
+                } else {
+                    LEVELS[3]
+                }
+            })
+            .collect();
+        Ok(Value::Text(line))
+    }
+
+    fn requires_full_input(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(rows: &[(f64, &str)]) -> Value {
+        Value::Array(Array::from((
+            rows.iter()
+                .map(|(count, label)| {
+                    Value::Array(Array::from((
+                        vec![Value::Number(*count), Value::Text(label.to_string())],
+                        Level::Word,
+                    )))
+                })
+                .collect(),
+            Level::Line,
+        )))
+    }
+
+    fn lines(value: Value) -> Vec<String> {
+        match value {
+            Value::Array(arr) => arr.elements.iter().map(Value::to_string).collect(),
+            other => panic!("not lines: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bars_scale_to_width() {
+        let histogram = Histogram::new(false, false, 20);
+        let value = rows(&[(8.0, "the"), (3.0, "a"), (1.0, "of")]);
+        assert_eq!(
+            lines(histogram.apply(value).unwrap()),
+            vec!["the 8 ██████████████", "a   3 █████▎", "of  1 █▊",]
+        );
+    }
+
+    #[test]
+    fn percent_and_cumulative_columns() {
+        let histogram = Histogram::new(true, true, 30);
+        let value = rows(&[(3.0, "x"), (1.0, "y")]);
+        assert_eq!(
+            lines(histogram.apply(value).unwrap()),
+            vec![
+                "x 3 █████████████ 75.0%  75.0%",
+                "y 1 ████▍         25.0% 100.0%",
+            ]
+        );
+    }
+
+    #[test]
+    fn non_numeric_count_error() {
+        let histogram = Histogram::new(false, false, 80);
+        let value = Value::Array(Array::from((
+            vec![Value::Text("many".to_string())],
+            Level::Line,
+        )));
+        let err = histogram.apply(value).unwrap_err();
+        assert_eq!(err.message, "count is not a number: 'many'");
+    }
+
+    #[test]
+    fn sparkline() {
+        let series = Value::Array(Array::from((
+            [1.0, 2.0, 4.0, 8.0, 4.0, 1.0]
+                .into_iter()
+                .map(Value::Number)
+                .collect(),
+            Level::Line,
+        )));
+        assert_eq!(
+            Sparkline::new(80).apply(series.deep_copy()).unwrap(),
+            Value::Text("▁▂▄█▄▁".to_string())
+        );
+        // Averaged in pairs to fit
+        assert_eq!(
+            Sparkline::new(3).apply(series).unwrap(),
+            Value::Text("▁█▃".to_string())
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use std::io::Write;
+use std::process::{Command as Process, Stdio};
+use std::thread;
+
+use crate::error::{Error, Result};
+use crate::interpreter::{Transform, try_map_elements};
+use crate::value::Value;
+
+/// Pipes each element through a shell command, replacing it with the output.
+///
+/// By default the command runs once per element, with the element on stdin,
+/// and its output without the trailing newline becomes the element. Batched,
+/// the command runs once, with one element per line on stdin, and must write
+/// back one line per element.
+pub struct Command {
+    command: String,
+    batched: bool,
+}
+
+impl Command {
+    pub fn new(command: String, batched: bool) -> Self {
+        Self { command, batched }
+    }
+
+    /// Run the command with `input` on stdin, returning its stdout.
+    fn run(&self, input: String) -> Result<String> {
+        let mut child = Process::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| Error::runtime(format!("command '{}': {}", self.command, e)))?;
+        // Write from another thread, so a command writing before it has read
+        // all of its input can't fill the pipe and deadlock
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+        let output = child
+            .wait_with_output()
+            .map_err(|e| Error::runtime(format!("command '{}': {}", self.command, e)))?;
+        // A command may exit without reading its input, which is no error
+        let _ = writer.join();
+        if !output.status.success() {
+            return Err(Error::runtime(format!(
+                "command '{}' failed: {}",
+                self.command, output.status
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    fn run_one(&self, value: &Value) -> Result<Value> {
+        let mut output = self.run(format!("{}\n", value))?;
+        if output.ends_with('\n') {
+            output.pop();
+            if output.ends_with('\r') {
+                output.pop();
+            }
+        }
+        Ok(Value::Text(output))
+    }
+
+    fn run_batch(&self, elements: &[Value]) -> Result<Vec<Value>> {
+        let mut input = String::new();
+        for elem in elements {
+            let line = elem.to_string();
+            if line.contains('\n') {
+                return Err(Error::runtime(format!(
+                    "command '{}': a batched element must be one line",
+                    self.command
+                )));
+            }
+            input.push_str(&line);
+            input.push('\n');
+        }
+        let output = self.run(input)?;
+        let lines: Vec<Value> = output
+            .lines()
+            .map(|line| Value::Text(line.to_string()))
+            .collect();
+        if lines.len() != elements.len() {
+            return Err(Error::runtime(format!(
+                "command '{}': expected {} lines of output, got {}",
+                self.command,
+                elements.len(),
+                lines.len()
+            )));
+        }
+        Ok(lines)
+    }
+}
+
+impl Transform for Command {
+    fn apply(&self, value: Value) -> Result<Value> {
+        match value {
+            Value::Array(mut arr) if self.batched => {
+                arr.elements = self.run_batch(&arr.elements)?;
+                Ok(Value::Array(arr))
+            }
+            Value::Array(mut arr) => {
+                arr.elements = try_map_elements(&mut arr, |elem| self.run_one(&elem))?;
+                Ok(Value::Array(arr))
+            }
+            other => self.run_one(&other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::{Array, Level};
+
+    fn lines(lines: &[&str]) -> Value {
+        Value::Array(Array::from((
+            lines.iter().map(|s| Value::Text(s.to_string())).collect(),
+            Level::Line,
+        )))
+    }
+
+    #[test]
+    fn per_element() {
+        let command = Command::new("tr a-z A-Z".to_string(), false);
+        assert_eq!(
+            command.apply(lines(&["ab", "cd"])).unwrap(),
+            lines(&["AB", "CD"])
+        );
+    }
+
+    #[test]
+    fn batched() {
+        let command = Command::new("rev".to_string(), true);
+        assert_eq!(
+            command.apply(lines(&["ab", "cd"])).unwrap(),
+            lines(&["ba", "dc"])
+        );
+    }
+
+    #[test]
+    fn batched_line_count_mismatch_error() {
+        let command = Command::new("head -n 1".to_string(), true);
+        let err = command.apply(lines(&["a", "b"])).unwrap_err();
+        assert_eq!(
+            err.message,
+            "command 'head -n 1': expected 2 lines of output, got 1"
+        );
+    }
+
+    #[test]
+    fn failure_error() {
+        let command = Command::new("exit 3".to_string(), false);
+        let err = command.apply(Value::Text("a".to_string())).unwrap_err();
+        assert_eq!(err.message, "command 'exit 3' failed: exit status: 3");
+    }
+}
@@ -0,0 +1,182 @@
+use regex::Regex;
+
+use crate::ast::Selection;
+use crate::error::Result;
+use crate::interpreter::{Context, Operator, Transform, run, try_map_elements};
+use crate::value::Value;
+
+use super::filter::selected_text;
+
+/// Applies one of two sub-programmes to each element, depending on whether it
+/// matches a regex. Elements are never dropped: without an else-branch,
+/// non-matching elements are left untouched.
+pub struct Conditional {
+    pattern: Regex,
+    selection: Option<Selection>,
+    then: Vec<Operator>,
+    otherwise: Option<Vec<Operator>>,
+}
+
+impl Conditional {
+    pub fn new(
+        pattern: Regex,
+        selection: Option<Selection>,
+        then: Vec<Operator>,
+        otherwise: Option<Vec<Operator>>,
+    ) -> Self {
+        Self {
+            pattern,
+            selection,
+            then,
+            otherwise,
+        }
+    }
+
+    /// Test an element, looking only at the selected fields if there is a selection.
+    fn matches(&self, elem: &Value) -> bool {
+        self.pattern
+            .is_match(&selected_text(elem, self.selection.as_ref()))
+    }
+}
+
+impl Transform for Conditional {
+    fn apply(&self, value: Value) -> Result<Value> {
+        match value {
+            Value::Array(mut arr) => {
+                arr.elements = try_map_elements(&mut arr, |elem| {
+                    let ops = if self.matches(&elem) {
+                        &self.then
+                    } else {
+                        match &self.otherwise {
+                            Some(ops) => ops,
+                            None => return Ok(elem),
+                        }
+                    };
+                    let mut ctx = Context::new(elem);
+                    run(ops, &mut ctx)?;
+                    Ok(ctx.into_value())
+                })?;
+                Ok(Value::Array(arr))
+            }
+            other => Ok(other),
+        }
+    }
+
+    fn requires_full_input(&self) -> bool {
+        self.then
+            .iter()
+            .chain(self.otherwise.iter().flatten())
+            .any(|op| op.requires_full_input())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::SelectItem;
+    use crate::error::Error;
+    use crate::operators::{Count, Lowercase, Uppercase};
+    use crate::value::{Array, Level, Origin};
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    fn line_array(lines: &[&str]) -> Value {
+        Value::Array(Array::from((
+            lines.iter().map(|s| text(s)).collect(),
+            Level::Line,
+        )))
+    }
+
+    fn word_array(words: &[&str]) -> Value {
+        Value::Array(Array::from((
+            words.iter().map(|s| text(s)).collect(),
+            Level::Word,
+        )))
+    }
+
+    fn transform(t: impl Transform + 'static) -> Operator {
+        Operator::Transform(Box::new(t))
+    }
+
+    #[test]
+    fn conditional_then_only() {
+        let cond = Conditional::new(
+            Regex::new("ERROR").unwrap(),
+            None,
+            vec![transform(Uppercase)],
+            None,
+        );
+        let result = cond
+            .apply(line_array(&["ERROR: disk", "info: ok"]))
+            .unwrap();
+        assert_eq!(result, line_array(&["ERROR: DISK", "info: ok"]));
+    }
+
+    #[test]
+    fn conditional_then_else() {
+        let cond = Conditional::new(
+            Regex::new("^a").unwrap(),
+            None,
+            vec![transform(Uppercase)],
+            Some(vec![transform(Lowercase)]),
+        );
+        let result = cond.apply(line_array(&["ab", "BC"])).unwrap();
+        assert_eq!(result, line_array(&["AB", "bc"]));
+    }
+
+    #[test]
+    fn conditional_on_selected_field() {
+        let cond = Conditional::new(
+            Regex::new("^GET$").unwrap(),
+            Some(Selection {
+                items: vec![SelectItem::Index(1)],
+            }),
+            vec![transform(Count)],
+            None,
+        );
+        let input = Value::Array(Array::from((
+            vec![word_array(&["a", "GET", "/"]), word_array(&["GET", "POST"])],
+            Level::Line,
+        )));
+        let result = cond.apply(input).unwrap();
+        assert_eq!(
+            result,
+            Value::Array(Array::from((
+                vec![Value::Number(3.0), word_array(&["GET", "POST"])],
+                Level::Line,
+            )))
+        );
+    }
+
+    struct Fail;
+
+    impl Transform for Fail {
+        fn apply(&self, _value: Value) -> Result<Value> {
+            Err(Error::runtime("failed"))
+        }
+    }
+
+    #[test]
+    fn conditional_branch_error_reports_input_line() {
+        let cond = Conditional::new(Regex::new("b").unwrap(), None, vec![transform(Fail)], None);
+        let Value::Array(mut input) = line_array(&["a", "b"]) else {
+            unreachable!()
+        };
+        input.set_origins(Some(
+            (1..=2).map(|line| Origin { file: None, line }).collect(),
+        ));
+        let err = cond.apply(Value::Array(input)).unwrap_err();
+        assert_eq!(err.position.input_line, Some(2));
+    }
+
+    #[test]
+    fn conditional_requires_full_input_if_a_branch_does() {
+        let pattern = || Regex::new("a").unwrap();
+        let cond = Conditional::new(pattern(), None, vec![], Some(vec![transform(Count)]));
+        assert!(cond.requires_full_input());
+        let cond = Conditional::new(pattern(), None, vec![transform(Uppercase)], None);
+        assert!(!cond.requires_full_input());
+    }
+}
@@ -6,11 +6,11 @@ pub struct Count;
 
 impl Transform for Count {
     fn apply(&self, value: Value) -> Result<Value> {
-        match value {
-            Value::Array(arr) => Ok(Value::Number(arr.len() as f64)),
-            Value::Text(s) => Ok(Value::Number(s.chars().count() as f64)),
-            Value::Number(_) => Ok(Value::Number(0.0)),
-        }
+        Ok(count(&value))
+    }
+
+    fn apply_ref(&self, value: &Value) -> Option<Result<Value>> {
+        Some(Ok(count(value)))
     }
 
     fn requires_full_input(&self) -> bool {
@@ -18,6 +18,14 @@ impl Transform for Count {
     }
 }
 
+fn count(value: &Value) -> Value {
+    match value {
+        Value::Array(arr) => Value::Number(arr.len() as f64),
+        Value::Text(s) => Value::Number(s.chars().count() as f64),
+        Value::Number(_) => Value::Number(0.0),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -5,7 +5,7 @@ use crate::error::Result;
 use crate::interpreter::Transform;
 use crate::value::{Array, Level, Value};
 
-use super::group::extract_key;
+use super::group::{Key, extract_key};
 
 fn dedupe_with_counts_by<F, G>(arr: Array, key_fn: F, output_fn: G) -> Result<Value>
 where
@@ -82,12 +82,16 @@ pub fn value_to_key(value: &Value) -> String {
 }
 
 pub struct DedupeSelectionWithCounts {
-    selection: Selection,
+    key: Key,
 }
 
 impl DedupeSelectionWithCounts {
     pub fn new(selection: Selection) -> Self {
-        Self { selection }
+        Self::with_key(Key::Selection(selection))
+    }
+
+    pub fn with_key(key: Key) -> Self {
+        Self { key }
     }
 }
 
@@ -97,10 +101,10 @@ impl Transform for DedupeSelectionWithCounts {
             Value::Array(arr) => dedupe_with_counts_by(
                 arr,
                 |elem| {
-                    let extracted = extract_key(elem, &self.selection)?;
+                    let extracted = extract_key(elem, &self.key)?;
                     Ok(value_to_key(&extracted))
                 },
-                |elem| extract_key(elem, &self.selection),
+                |elem| extract_key(elem, &self.key),
             ),
             other => Ok(other),
         }
@@ -345,4 +349,33 @@ mod tests {
         let result = dedupe.apply(input).unwrap();
         assert_eq!(result, text("hello"));
     }
+
+    #[test]
+    fn dedupe_selection_with_counts_programme_key() {
+        use crate::interpreter::Operator;
+        use crate::operators::Lowercase;
+
+        let input = Value::Array(Array::from((
+            vec![text("A"), text("b"), text("a")],
+            Level::Line,
+        )));
+        let dedupe =
+            DedupeSelectionWithCounts::with_key(Key::Programme(vec![Operator::Transform(
+                Box::new(Lowercase),
+            )]));
+        let result = dedupe.apply(input).unwrap();
+        match result {
+            Value::Array(arr) => {
+                assert_eq!(arr.len(), 2);
+                match &arr.elements[0] {
+                    Value::Array(pair) => {
+                        assert_eq!(pair.elements[0], Value::Number(2.0));
+                        assert_eq!(pair.elements[1], text("a"));
+                    }
+                    _ => panic!("expected array"),
+                }
+            }
+            _ => panic!("expected array"),
+        }
+    }
 }
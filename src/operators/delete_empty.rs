@@ -8,7 +8,7 @@ impl Transform for DeleteEmpty {
     fn apply(&self, value: Value) -> Result<Value> {
         match value {
             Value::Array(mut arr) => {
-                arr.elements.retain(|v| !v.is_empty());
+                arr.retain(|v| !v.is_empty());
                 Ok(Value::Array(arr))
             }
             other => Ok(other),
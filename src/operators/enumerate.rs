@@ -0,0 +1,60 @@
+use crate::error::Result;
+use crate::interpreter::Transform;
+use crate::value::{Array, Level, Value};
+
+/// Pairs each element with its 0-based index.
+///
+/// `["a", "b"]` → `[[0, "a"], [1, "b"]]`
+pub struct Enumerate;
+
+impl Transform for Enumerate {
+    fn apply(&self, value: Value) -> Result<Value> {
+        match value {
+            Value::Array(mut arr) => {
+                arr.elements = arr
+                    .elements
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, elem)| {
+                        Value::Array(Array::from((
+                            vec![Value::Number(i as f64), elem],
+                            Level::Word,
+                        )))
+                    })
+                    .collect();
+                Ok(Value::Array(arr))
+            }
+            other => Ok(other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    #[test]
+    fn enumerate_pairs_index_and_element() {
+        let input = Value::Array(Array::from((vec![text("a"), text("b")], Level::Line)));
+        let result = Enumerate.apply(input).unwrap();
+        let pair = |i: f64, s: &str| {
+            Value::Array(Array::from((vec![Value::Number(i), text(s)], Level::Word)))
+        };
+        assert_eq!(
+            result,
+            Value::Array(Array::from((
+                vec![pair(0.0, "a"), pair(1.0, "b")],
+                Level::Line
+            )))
+        );
+    }
+
+    #[test]
+    fn enumerate_non_array_is_identity() {
+        assert_eq!(Enumerate.apply(text("a")).unwrap(), text("a"));
+    }
+}
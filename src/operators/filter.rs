@@ -1,48 +1,76 @@
 use regex::Regex;
 
+use crate::ast::Selection;
 use crate::error::Result;
 use crate::interpreter::Transform;
-use crate::value::{Array, Value};
+use crate::value::Value;
+
+use super::select::selection_indices;
 
 pub struct Filter {
-    pattern: Regex,
-    negate: bool,
+    /// Every clause must hold for an element to be kept.
+    clauses: Vec<(Regex, bool)>,
 }
 
 impl Filter {
     pub fn new(pattern: Regex, negate: bool) -> Self {
-        Self { pattern, negate }
+        Self {
+            clauses: vec![(pattern, negate)],
+        }
+    }
+
+    /// Create a filter that keeps elements satisfying all clauses.
+    pub fn all(clauses: Vec<(Regex, bool)>) -> Self {
+        Self { clauses }
     }
 }
 
 impl Transform for Filter {
     fn apply(&self, value: Value) -> Result<Value> {
         match value {
-            Value::Array(arr) => {
-                let filtered: Vec<Value> = arr
-                    .elements
-                    .into_iter()
-                    .filter(|elem| {
-                        let text = match elem {
-                            Value::Text(s) => s.clone(),
-                            Value::Number(n) => n.to_string(),
-                            Value::Array(inner) => inner.to_string(),
-                        };
-                        let matches = self.pattern.is_match(&text);
-                        if self.negate { !matches } else { matches }
+            Value::Array(mut arr) => {
+                arr.retain(|elem| {
+                    let text = match_text(elem);
+                    self.clauses.iter().all(|(pattern, negate)| {
+                        let matches = pattern.is_match(&text);
+                        if *negate { !matches } else { matches }
                     })
-                    .collect();
-                Ok(Value::Array(Array::from((filtered, arr.level))))
+                });
+                Ok(Value::Array(arr))
             }
             other => Ok(other),
         }
     }
 }
 
+/// The text a regex predicate is matched against: arrays are joined as they print.
+pub fn match_text(elem: &Value) -> String {
+    match elem {
+        Value::Text(s) => s.clone(),
+        Value::Number(n) => n.to_string(),
+        Value::Array(inner) => inner.to_string(),
+    }
+}
+
+/// The text of the selected fields of an element, joined as they print, or
+/// of the whole element without a selection or if it isn't an array.
+pub fn selected_text(elem: &Value, selection: Option<&Selection>) -> String {
+    match (selection, elem) {
+        (Some(sel), Value::Array(arr)) => {
+            let fields: Vec<String> = selection_indices(sel, arr.len() as i64)
+                .into_iter()
+                .map(|i| match_text(&arr.elements[i]))
+                .collect();
+            fields.join(arr.level.join_delimiter())
+        }
+        _ => match_text(elem),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::value::Level;
+    use crate::value::{Array, Level};
 
     fn text(s: &str) -> Value {
         Value::Text(s.to_string())
@@ -199,4 +227,29 @@ mod tests {
             _ => panic!("expected array"),
         }
     }
+
+    #[test]
+    fn filter_all_clauses() {
+        let input = Value::Array(Array::from((
+            vec![
+                text("ERROR: fail"),
+                text("ERROR: expected"),
+                text("INFO: ok"),
+            ],
+            Level::Line,
+        )));
+        let filter = Filter::all(vec![
+            (Regex::new("ERROR").unwrap(), false),
+            (Regex::new("expected").unwrap(), true),
+        ]);
+        let result = filter.apply(input).unwrap();
+
+        match result {
+            Value::Array(arr) => {
+                assert_eq!(arr.len(), 1);
+                assert_eq!(arr.elements[0], text("ERROR: fail"));
+            }
+            _ => panic!("expected array"),
+        }
+    }
 }
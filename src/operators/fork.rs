@@ -0,0 +1,186 @@
+use crate::error::Result;
+use crate::interpreter::{Context, Operator, Transform, run};
+use crate::value::{Array, Level, Value};
+
+/// Runs several sub-programmes over the same value, collecting their results
+/// into an array with one element per branch.
+///
+/// Branches that open with a transform that only reads its input, such as
+/// `#` or `+`, run first on the shared value. Of the rest, each but the last
+/// runs on a copy of the value and the last takes the original, so a fork of
+/// reductions and one transformation never copies. Every branch's result is
+/// kept until the last has run.
+pub struct Fork {
+    branches: Vec<Vec<Operator>>,
+}
+
+impl Fork {
+    pub fn new(branches: Vec<Vec<Operator>>) -> Self {
+        Self { branches }
+    }
+}
+
+impl Transform for Fork {
+    fn apply(&self, value: Value) -> Result<Value> {
+        let mut results: Vec<Option<Value>> = Vec::with_capacity(self.branches.len());
+        for ops in &self.branches {
+            results.push(match ops.first() {
+                Some(Operator::Transform(t)) => match t.apply_ref(&value) {
+                    Some(result) => Some(run_branch(&ops[1..], result?)?),
+                    None => None,
+                },
+                _ => None,
+            });
+        }
+
+        let mut value = Some(value);
+        let last = results.iter().rposition(Option::is_none);
+        for (i, ops) in self.branches.iter().enumerate() {
+            if results[i].is_some() {
+                continue;
+            }
+            let input = if Some(i) == last {
+                value
+                    .take()
+                    .expect("only the last copying branch takes the value")
+            } else {
+                value
+                    .as_ref()
+                    .expect("value is kept until the last copying branch")
+                    .deep_copy()
+            };
+            results[i] = Some(run_branch(ops, input)?);
+        }
+        Ok(Value::Array(Array::from((
+            results.into_iter().flatten().collect(),
+            Level::Line,
+        ))))
+    }
+
+    fn requires_full_input(&self) -> bool {
+        self.branches
+            .iter()
+            .flatten()
+            .any(|op| op.requires_full_input())
+    }
+}
+
+fn run_branch(ops: &[Operator], input: Value) -> Result<Value> {
+    let mut ctx = Context::new(input);
+    run(ops, &mut ctx)?;
+    Ok(ctx.into_value())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::operators::{Count, Lowercase, Uppercase};
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    fn line_array(lines: &[&str]) -> Value {
+        Value::Array(Array::from((
+            lines.iter().map(|s| text(s)).collect(),
+            Level::Line,
+        )))
+    }
+
+    fn transform(t: impl Transform + 'static) -> Operator {
+        Operator::Transform(Box::new(t))
+    }
+
+    #[test]
+    fn fork_collects_branch_results() {
+        let fork = Fork::new(vec![
+            vec![transform(Count)],
+            vec![transform(Uppercase)],
+            vec![transform(Lowercase)],
+        ]);
+        let result = fork.apply(line_array(&["a", "B"])).unwrap();
+        assert_eq!(
+            result,
+            Value::Array(Array::from((
+                vec![
+                    Value::Number(2.0),
+                    line_array(&["A", "B"]),
+                    line_array(&["a", "b"]),
+                ],
+                Level::Line,
+            )))
+        );
+    }
+
+    /// Records the address of the elements of each array it reads.
+    struct Probe(Rc<RefCell<Vec<usize>>>);
+
+    impl Probe {
+        fn record(&self, value: &Value) -> Value {
+            if let Value::Array(arr) = value {
+                self.0.borrow_mut().push(arr.elements.as_ptr() as usize);
+            }
+            Value::Number(0.0)
+        }
+    }
+
+    impl Transform for Probe {
+        fn apply(&self, value: Value) -> Result<Value> {
+            Ok(self.record(&value))
+        }
+
+        fn apply_ref(&self, value: &Value) -> Option<Result<Value>> {
+            Some(Ok(self.record(value)))
+        }
+    }
+
+    #[test]
+    fn fork_read_only_branches_share_the_value() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let fork = Fork::new(vec![
+            vec![transform(Probe(seen.clone()))],
+            vec![transform(Uppercase)],
+            vec![transform(Probe(seen.clone())), transform(Count)],
+        ]);
+        let input = line_array(&["a", "B"]);
+        let Value::Array(arr) = &input else {
+            unreachable!()
+        };
+        let original = arr.elements.as_ptr() as usize;
+
+        let result = fork.apply(input).unwrap();
+        assert_eq!(*seen.borrow(), vec![original, original]);
+        assert_eq!(
+            result,
+            Value::Array(Array::from((
+                vec![
+                    Value::Number(0.0),
+                    line_array(&["A", "B"]),
+                    Value::Number(0.0),
+                ],
+                Level::Line,
+            )))
+        );
+    }
+
+    #[test]
+    fn fork_empty_branch_is_identity() {
+        let fork = Fork::new(vec![vec![]]);
+        let result = fork.apply(text("a")).unwrap();
+        assert_eq!(
+            result,
+            Value::Array(Array::from((vec![text("a")], Level::Line)))
+        );
+    }
+
+    #[test]
+    fn fork_requires_full_input_if_any_branch_does() {
+        let fork = Fork::new(vec![vec![transform(Uppercase)], vec![transform(Count)]]);
+        assert!(fork.requires_full_input());
+        let fork = Fork::new(vec![vec![transform(Uppercase)], vec![]]);
+        assert!(!fork.requires_full_input());
+    }
+}
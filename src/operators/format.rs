@@ -0,0 +1,218 @@
+use crate::ast::{Conversion, FormatPart, FormatSpec};
+use crate::error::{Error, Result};
+use crate::interpreter::{Transform, try_map_elements};
+use crate::value::Value;
+
+use super::select::selection_indices;
+use super::sum::to_number;
+
+/// Replaces each element with the text of a template, its placeholders filled
+/// with the element's fields.
+///
+/// A non-array element counts as a row of one field. Fields missing from the
+/// element render as nothing; a numeric spec on a field that isn't a number is
+/// an error, naming the placeholder.
+pub struct Format {
+    parts: Vec<FormatPart>,
+}
+
+impl Format {
+    pub fn new(parts: Vec<FormatPart>) -> Self {
+        Self { parts }
+    }
+
+    fn render(&self, elem: &Value) -> Result<Value> {
+        let mut result = String::new();
+        for part in &self.parts {
+            match part {
+                FormatPart::Literal(text) => result.push_str(text),
+                FormatPart::Field {
+                    selection,
+                    separator,
+                    spec,
+                } => {
+                    let fields: Vec<&Value> = match (selection, elem) {
+                        (None, Value::Array(arr)) if separator.is_some() => {
+                            arr.elements.iter().collect()
+                        }
+                        (None, elem) => vec![elem],
+                        (Some(selection), Value::Array(arr)) => {
+                            selection_indices(selection, arr.len() as i64)
+                                .into_iter()
+                                .map(|i| &arr.elements[i])
+                                .collect()
+                        }
+                        (Some(selection), elem) => selection_indices(selection, 1)
+                            .into_iter()
+                            .map(|_| elem)
+                            .collect(),
+                    };
+                    let separator = separator.as_deref().unwrap_or(" ");
+                    for (i, field) in fields.into_iter().enumerate() {
+                        if i > 0 {
+                            result.push_str(separator);
+                        }
+                        match spec {
+                            Some(spec) => {
+                                result.push_str(&apply_spec(spec, field).ok_or_else(|| {
+                                    Error::runtime(format!(
+                                        "not a number for '{}': '{}'",
+                                        part, field
+                                    ))
+                                })?)
+                            }
+                            None => result.push_str(&field.to_string()),
+                        }
+                    }
+                }
+            }
+        }
+        Ok(Value::Text(result))
+    }
+}
+
+impl Transform for Format {
+    fn apply(&self, value: Value) -> Result<Value> {
+        match value {
+            Value::Array(mut arr) => {
+                arr.elements = try_map_elements(&mut arr, |elem| self.render(&elem))?;
+                Ok(Value::Array(arr))
+            }
+            other => self.render(&other),
+        }
+    }
+}
+
+/// Format a field with a spec, or `None` if the spec needs a number and the
+/// field isn't one.
+fn apply_spec(spec: &FormatSpec, field: &Value) -> Option<String> {
+    let (sign, body) = match spec.conversion {
+        Conversion::String => {
+            let text = field.to_string();
+            let body = match spec.precision {
+                Some(precision) => text.chars().take(precision).collect(),
+                None => text,
+            };
+            (String::new(), body)
+        }
+        conversion => {
+            let n = to_number(field)?;
+            let n = match conversion {
+                Conversion::Integer | Conversion::Hex => n.trunc(),
+                _ => n,
+            };
+            let sign = if n < 0.0 {
+                "-"
+            } else if spec.sign {
+                "+"
+            } else {
+                ""
+            };
+            let body = match conversion {
+                Conversion::Integer => min_digits(format!("{:.0}", n.abs()), spec.precision),
+                Conversion::Hex => min_digits(format!("{:x}", n.abs() as u64), spec.precision),
+                Conversion::Fixed => format!("{:.*}", spec.precision.unwrap_or(6), n.abs()),
+                _ => exponent(n.abs(), spec.precision.unwrap_or(6)),
+            };
+            (sign.to_string(), body)
+        }
+    };
+    let len = sign.chars().count() + body.chars().count();
+    let padding = spec.width.unwrap_or(0).saturating_sub(len);
+    Some(if spec.left {
+        format!("{}{}{}", sign, body, " ".repeat(padding))
+    } else if spec.zero && spec.conversion != Conversion::String {
+        format!("{}{}{}", sign, "0".repeat(padding), body)
+    } else {
+        format!("{}{}{}", " ".repeat(padding), sign, body)
+    })
+}
+
+/// Left-pad the digits of an integer with zeros to at least `precision`.
+fn min_digits(digits: String, precision: Option<usize>) -> String {
+    let padding = precision.unwrap_or(0).saturating_sub(digits.len());
+    format!("{}{}", "0".repeat(padding), digits)
+}
+
+/// Scientific notation as printf writes it: `1.500000e+03`.
+fn exponent(n: f64, precision: usize) -> String {
+    let formatted = format!("{:.*e}", precision, n);
+    match formatted.split_once('e') {
+        Some((mantissa, exp)) => {
+            let exp: i32 = exp.parse().expect("Rust writes an integer exponent");
+            let sign = if exp < 0 { '-' } else { '+' };
+            format!("{}e{}{:02}", mantissa, sign, exp.abs())
+        }
+        // Infinity has no exponent
+        None => formatted,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_programme;
+    use crate::value::{Array, Level};
+
+    fn row(fields: &[&str]) -> Value {
+        Value::Array(Array::from((
+            fields.iter().map(|s| Value::Text(s.to_string())).collect(),
+            Level::Word,
+        )))
+    }
+
+    /// Render a template for a single element.
+    fn render(source: &str, elem: Value) -> Result<String> {
+        let format = match parse_programme(source).unwrap().operators.remove(0) {
+            crate::ast::Operator::Format(parts) => Format::new(parts),
+            op => panic!("not a format: {:?}", op),
+        };
+        Ok(format.render(&elem)?.to_string())
+    }
+
+    #[test]
+    fn positional_placeholders() {
+        let elem = row(&["GET", "/index.html", "200", "5120"]);
+        assert_eq!(
+            render(r#"F"{0} {1} -> {-2} ({-1} bytes)""#, elem).unwrap(),
+            "GET /index.html -> 200 (5120 bytes)"
+        );
+    }
+
+    #[test]
+    fn slices_joined_with_separator() {
+        let elem = row(&["a", "b", "c", "d"]);
+        assert_eq!(
+            render(r#"F"{0}: {1:|, }""#, elem.deep_copy()).unwrap(),
+            "a: b, c, d"
+        );
+        assert_eq!(render(r#"F"{:2} [{5}]""#, elem).unwrap(), "a b []");
+    }
+
+    #[test]
+    fn number_specs() {
+        let elem = row(&["3.14159", "-42", "255", "1500"]);
+        assert_eq!(
+            render(
+                r#"F"[{0%8.2f}] [{1%-6d}] [{1%05d}] [{2%x}] [{3%+.1e}]""#,
+                elem
+            )
+            .unwrap(),
+            "[    3.14] [-42   ] [-0042] [ff] [+1.5e+03]"
+        );
+    }
+
+    #[test]
+    fn string_specs_and_escaped_braces() {
+        assert_eq!(
+            render(r#"F"{{{%-6s}}} {%.3s}""#, Value::Text("abcdef".to_string())).unwrap(),
+            "{abcdef} abc"
+        );
+    }
+
+    #[test]
+    fn non_numeric_field_error() {
+        let err = render(r#"F"{1%d}""#, row(&["GET", "x"])).unwrap_err();
+        assert_eq!(err.message, "not a number for '{1%d}': 'x'");
+    }
+}
@@ -1,20 +1,33 @@
 use std::collections::HashMap;
 
-use crate::ast::Selection;
+use crate::ast::{Aggregate, Selection};
 use crate::error::{Error, Result};
-use crate::interpreter::Transform;
-use crate::value::{Array, Value};
+use crate::interpreter::{Context, Operator, Transform, run};
+use crate::value::{Array, Level, Value};
 
 use super::dedupe::value_to_key;
 use super::select::selection_indices;
+use super::sum::{sum_recursive, to_number};
+
+/// How to compute the key of an element.
+pub enum Key {
+    /// The value(s) at the selection
+    Selection(Selection),
+    /// The result of running the operators on a copy of the element
+    Programme(Vec<Operator>),
+}
 
 pub struct GroupBy {
-    selection: Selection,
+    key: Key,
 }
 
 impl GroupBy {
     pub fn new(selection: Selection) -> Self {
-        Self { selection }
+        Self::with_key(Key::Selection(selection))
+    }
+
+    pub fn with_key(key: Key) -> Self {
+        Self { key }
     }
 }
 
@@ -26,7 +39,7 @@ impl Transform for GroupBy {
                 let mut key_indices: HashMap<String, usize> = HashMap::new();
 
                 for elem in arr.elements {
-                    let key = extract_key(&elem, &self.selection)?;
+                    let key = extract_key(&elem, &self.key)?;
                     let key_str = value_to_key(&key);
 
                     if let Some(&idx) = key_indices.get(&key_str) {
@@ -59,7 +72,138 @@ impl Transform for GroupBy {
     }
 }
 
-pub fn extract_key(elem: &Value, selection: &Selection) -> Result<Value> {
+/// Groups elements by key, reducing each group to a single row of the key
+/// followed by its aggregates. Elements are folded into per-group
+/// accumulators as they are read, so the groups themselves are never built.
+pub struct GroupAggregate {
+    key: Key,
+    aggregates: Vec<Aggregate>,
+}
+
+impl GroupAggregate {
+    pub fn new(key: Key, aggregates: Vec<Aggregate>) -> Self {
+        Self { key, aggregates }
+    }
+}
+
+impl Transform for GroupAggregate {
+    fn apply(&self, value: Value) -> Result<Value> {
+        match value {
+            Value::Array(arr) => {
+                let mut groups: Vec<(Value, Vec<Accumulator>)> = Vec::new();
+                let mut key_indices: HashMap<String, usize> = HashMap::new();
+
+                for elem in arr.elements {
+                    let key = extract_key(&elem, &self.key)?;
+                    let key_str = value_to_key(&key);
+
+                    let idx = *key_indices.entry(key_str).or_insert_with(|| {
+                        let accumulators = self.aggregates.iter().map(|_| Accumulator::default());
+                        groups.push((key, accumulators.collect()));
+                        groups.len() - 1
+                    });
+                    for (acc, aggregate) in groups[idx].1.iter_mut().zip(&self.aggregates) {
+                        acc.add(aggregate_field(&elem, aggregate));
+                    }
+                }
+
+                let elements: Vec<Value> = groups
+                    .into_iter()
+                    .map(|(key, accumulators)| {
+                        // Composite keys are spliced in to keep the row flat
+                        let mut row = match key {
+                            Value::Array(key) => key.elements,
+                            key => vec![key],
+                        };
+                        row.extend(
+                            accumulators
+                                .iter()
+                                .zip(&self.aggregates)
+                                .map(|(acc, aggregate)| acc.finish(aggregate)),
+                        );
+                        Value::Array(Array::from((row, Level::Word)))
+                    })
+                    .collect();
+
+                Ok(Value::Array(Array::from((elements, arr.level))))
+            }
+            other => Ok(other),
+        }
+    }
+
+    fn requires_full_input(&self) -> bool {
+        true
+    }
+}
+
+/// The running state of one aggregate over one group.
+#[derive(Default)]
+struct Accumulator {
+    count: usize,
+    sum: f64,
+    numbers: usize,
+    numeric_sum: f64,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl Accumulator {
+    fn add(&mut self, field: Option<&Value>) {
+        self.count += 1;
+        let Some(field) = field else { return };
+        self.sum += sum_recursive(field);
+        if let Some(n) = to_number(field) {
+            self.numbers += 1;
+            self.numeric_sum += n;
+            self.min = Some(self.min.map_or(n, |m| m.min(n)));
+            self.max = Some(self.max.map_or(n, |m| m.max(n)));
+        }
+    }
+
+    /// The aggregate's value, or empty text if the group had no numeric values.
+    fn finish(&self, aggregate: &Aggregate) -> Value {
+        let result = match aggregate {
+            Aggregate::Count => Some(self.count as f64),
+            Aggregate::Sum(_) => Some(self.sum),
+            Aggregate::Min(_) => self.min,
+            Aggregate::Max(_) => self.max,
+            Aggregate::Mean(_) => {
+                (self.numbers > 0).then(|| self.numeric_sum / self.numbers as f64)
+            }
+        };
+        result.map_or_else(|| Value::Text(String::new()), Value::Number)
+    }
+}
+
+/// The value an aggregate reads from an element: the field at its index, or
+/// the whole element if it has none.
+fn aggregate_field<'a>(elem: &'a Value, aggregate: &Aggregate) -> Option<&'a Value> {
+    let field = match aggregate {
+        Aggregate::Count => return None,
+        Aggregate::Sum(field)
+        | Aggregate::Min(field)
+        | Aggregate::Max(field)
+        | Aggregate::Mean(field) => *field,
+    };
+    match (field, elem) {
+        (None, elem) => Some(elem),
+        (Some(index), Value::Array(arr)) => arr.get(index),
+        (Some(_), _) => None,
+    }
+}
+
+pub fn extract_key(elem: &Value, key: &Key) -> Result<Value> {
+    match key {
+        Key::Selection(selection) => extract_selection(elem, selection),
+        Key::Programme(ops) => {
+            let mut ctx = Context::new(elem.deep_copy());
+            run(ops, &mut ctx)?;
+            Ok(ctx.into_value())
+        }
+    }
+}
+
+fn extract_selection(elem: &Value, selection: &Selection) -> Result<Value> {
     match elem {
         Value::Array(arr) => {
             let len = arr.len() as i64;
@@ -307,4 +451,120 @@ mod tests {
             _ => panic!("expected array"),
         }
     }
+
+    #[test]
+    fn group_by_programme_key() {
+        use crate::interpreter::Operator;
+        use crate::operators::Lowercase;
+
+        let input = Value::Array(Array::from((
+            vec![text("A"), text("b"), text("a")],
+            Level::Line,
+        )));
+        let group_by = GroupBy::with_key(Key::Programme(vec![Operator::Transform(Box::new(
+            Lowercase,
+        ))]));
+        let result = group_by.apply(input).unwrap();
+        let group = |key: &str, elems: &[&str]| {
+            Value::Array(Array::from((
+                vec![
+                    text(key),
+                    Value::Array(Array::from((
+                        elems.iter().map(|s| text(s)).collect(),
+                        Level::Line,
+                    ))),
+                ],
+                Level::Line,
+            )))
+        };
+        assert_eq!(
+            result,
+            Value::Array(Array::from((
+                vec![group("a", &["A", "a"]), group("b", &["b"])],
+                Level::Line,
+            )))
+        );
+    }
+
+    #[test]
+    fn group_aggregate_rows() {
+        let row = |ip: &str, bytes: Value| {
+            Value::Array(Array::from((
+                vec![text(ip), text("GET"), bytes],
+                Level::Word,
+            )))
+        };
+        let input = Value::Array(Array::from((
+            vec![
+                row("a", text("100")),
+                row("b", Value::Number(50.0)),
+                row("a", text("300")),
+                row("b", text("-")),
+            ],
+            Level::Line,
+        )));
+        let group = GroupAggregate::new(
+            Key::Selection(Selection {
+                items: vec![SelectItem::Index(0)],
+            }),
+            vec![
+                Aggregate::Count,
+                Aggregate::Sum(Some(2)),
+                Aggregate::Max(Some(-1)),
+                Aggregate::Mean(Some(2)),
+            ],
+        );
+        let result = group.apply(input).unwrap();
+        let expected = |values: Vec<Value>| Value::Array(Array::from((values, Level::Word)));
+        assert_eq!(
+            result,
+            Value::Array(Array::from((
+                vec![
+                    expected(vec![
+                        text("a"),
+                        Value::Number(2.0),
+                        Value::Number(400.0),
+                        Value::Number(300.0),
+                        Value::Number(200.0),
+                    ]),
+                    expected(vec![
+                        text("b"),
+                        Value::Number(2.0),
+                        Value::Number(50.0),
+                        Value::Number(50.0),
+                        Value::Number(50.0),
+                    ]),
+                ],
+                Level::Line,
+            )))
+        );
+    }
+
+    #[test]
+    fn group_aggregate_splices_composite_key() {
+        let input = Value::Array(Array::from((
+            vec![
+                Value::Array(Array::from((vec![text("a"), text("x")], Level::Word))),
+                Value::Array(Array::from((vec![text("a"), text("x")], Level::Word))),
+            ],
+            Level::Line,
+        )));
+        let group = GroupAggregate::new(
+            Key::Selection(Selection {
+                items: vec![SelectItem::Index(0), SelectItem::Index(1)],
+            }),
+            vec![Aggregate::Count, Aggregate::Min(None)],
+        );
+        let result = group.apply(input).unwrap();
+        assert_eq!(
+            result,
+            Value::Array(Array::from((
+                vec![Value::Array(Array::from((
+                    vec![text("a"), text("x"), Value::Number(2.0), text("")],
+                    Level::Word,
+                )))],
+                Level::Line,
+            )))
+        );
+    }
 }
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::ast::{JoinKind, Selection};
+use crate::error::Result;
+use crate::interpreter::Transform;
+use crate::value::{Array, Level, Value};
+
+use super::dedupe::value_to_key;
+use super::group::{Key, extract_key};
+
+/// Joins each row with the rows of a named input that have an equal key.
+///
+/// The input is indexed by key once, when the operator is built, so the rows
+/// being joined are read only once and in order. A joined row is the row
+/// followed by the fields of the matching input row, with one joined row per
+/// match. Rows too short to have a key never match.
+pub struct JoinInput {
+    kind: JoinKind,
+    key: Key,
+    table: Arc<Value>,
+    index: HashMap<String, Vec<usize>>,
+}
+
+impl JoinInput {
+    pub fn new(table: Arc<Value>, kind: JoinKind, left: Selection, right: Selection) -> Self {
+        let right = Key::Selection(right);
+        let mut index: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, row) in rows(&table).iter().enumerate() {
+            if let Ok(key) = extract_key(row, &right) {
+                index.entry(value_to_key(&key)).or_default().push(i);
+            }
+        }
+        Self {
+            kind,
+            key: Key::Selection(left),
+            table,
+            index,
+        }
+    }
+
+    /// The indices of the input rows that match `row`.
+    fn matches(&self, row: &Value) -> &[usize] {
+        extract_key(row, &self.key)
+            .ok()
+            .and_then(|key| self.index.get(&value_to_key(&key)))
+            .map_or(&[], Vec::as_slice)
+    }
+}
+
+impl Transform for JoinInput {
+    fn apply(&self, value: Value) -> Result<Value> {
+        match value {
+            Value::Array(mut arr) => {
+                let table = rows(&self.table);
+                let origins = arr.take_origins();
+                let mut joined_origins = Vec::new();
+                let mut joined = Vec::new();
+                for (i, row) in arr.elements.drain(..).enumerate() {
+                    let matches = self.matches(&row);
+                    let before = joined.len();
+                    match self.kind {
+                        JoinKind::Inner | JoinKind::Left if !matches.is_empty() => {
+                            joined.extend(matches.iter().map(|&j| join_rows(&row, &table[j])));
+                        }
+                        JoinKind::Left | JoinKind::Anti if matches.is_empty() => joined.push(row),
+                        _ => {}
+                    }
+                    if let Some(origins) = &origins {
+                        joined_origins.extend((before..joined.len()).map(|_| origins[i].clone()));
+                    }
+                }
+                arr.elements = joined;
+                arr.set_origins(origins.map(|_| joined_origins));
+                Ok(Value::Array(arr))
+            }
+            other => Ok(other),
+        }
+    }
+}
+
+fn rows(table: &Value) -> &[Value] {
+    match table {
+        Value::Array(arr) => &arr.elements,
+        _ => &[],
+    }
+}
+
+/// The fields of `row` followed by the fields of `other`.
+fn join_rows(row: &Value, other: &Value) -> Value {
+    let (mut fields, level) = match row {
+        Value::Array(arr) => (arr.iter().map(Value::deep_copy).collect(), arr.level),
+        row => (vec![row.deep_copy()], Level::Word),
+    };
+    match other {
+        Value::Array(arr) => fields.extend(arr.iter().map(Value::deep_copy)),
+        other => fields.push(other.deep_copy()),
+    }
+    Value::Array(Array::from((fields, level)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::SelectItem;
+
+    fn row(fields: &[&str]) -> Value {
+        Value::Array(Array::from((
+            fields.iter().map(|s| Value::Text(s.to_string())).collect(),
+            Level::Word,
+        )))
+    }
+
+    fn table(rows: Vec<Value>) -> Value {
+        Value::Array(Array::from((rows, Level::Line)))
+    }
+
+    fn index(i: i64) -> Selection {
+        Selection {
+            items: vec![SelectItem::Index(i)],
+        }
+    }
+
+    fn users() -> Arc<Value> {
+        Arc::new(table(vec![
+            row(&["alice", "1"]),
+            row(&["bob", "2"]),
+            row(&["alice2", "1"]),
+        ]))
+    }
+
+    fn requests() -> Value {
+        table(vec![row(&["GET", "1"]), row(&["PUT", "3"]), row(&["GET"])])
+    }
+
+    #[test]
+    fn inner_join_pairs_each_match() {
+        let join = JoinInput::new(users(), JoinKind::Inner, index(1), index(1));
+        assert_eq!(
+            join.apply(requests()).unwrap(),
+            table(vec![
+                row(&["GET", "1", "alice", "1"]),
+                row(&["GET", "1", "alice2", "1"]),
+            ])
+        );
+    }
+
+    #[test]
+    fn left_join_keeps_unmatched_rows() {
+        let join = JoinInput::new(users(), JoinKind::Left, index(1), index(1));
+        assert_eq!(
+            join.apply(requests()).unwrap(),
+            table(vec![
+                row(&["GET", "1", "alice", "1"]),
+                row(&["GET", "1", "alice2", "1"]),
+                row(&["PUT", "3"]),
+                row(&["GET"]),
+            ])
+        );
+    }
+
+    #[test]
+    fn anti_join_keeps_only_unmatched_rows() {
+        let join = JoinInput::new(users(), JoinKind::Anti, index(1), index(1));
+        assert_eq!(
+            join.apply(requests()).unwrap(),
+            table(vec![row(&["PUT", "3"]), row(&["GET"])])
+        );
+    }
+
+    #[test]
+    fn join_on_different_fields() {
+        let join = JoinInput::new(users(), JoinKind::Inner, index(1), index(0));
+        let result = join.apply(table(vec![row(&["x", "bob"])])).unwrap();
+        assert_eq!(result, table(vec![row(&["x", "bob", "bob", "2"])]));
+    }
+}
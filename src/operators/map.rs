@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+
+use crate::ast::{Selection, Unmapped};
+use crate::error::Result;
+use crate::interpreter::Transform;
+use crate::value::Value;
+
+use super::filter::match_text;
+use super::select::selection_indices;
+
+/// Replaces values with their entries in a dictionary.
+///
+/// Without a selection, every text or number is looked up, recursing through
+/// arrays the way `l` does. With one, only the selected fields of each element
+/// are. Values with no entry are kept, replaced with a default, or dropped;
+/// with a selection, dropping removes the whole element.
+pub struct Map {
+    dictionary: HashMap<String, String>,
+    selection: Option<Selection>,
+    unmapped: Unmapped,
+}
+
+impl Map {
+    pub fn new(
+        dictionary: HashMap<String, String>,
+        selection: Option<Selection>,
+        unmapped: Unmapped,
+    ) -> Self {
+        Self {
+            dictionary,
+            selection,
+            unmapped,
+        }
+    }
+
+    /// The entry for a single value, or what to do without one.
+    fn lookup(&self, value: Value) -> Option<Value> {
+        match self.dictionary.get(&match_text(&value)) {
+            Some(mapped) => Some(Value::Text(mapped.clone())),
+            None => match &self.unmapped {
+                Unmapped::Keep => Some(value),
+                Unmapped::Default(default) => Some(Value::Text(default.clone())),
+                Unmapped::Drop => None,
+            },
+        }
+    }
+
+    /// Map every value, dropping unmapped values from their arrays.
+    fn map_all(&self, value: Value) -> Option<Value> {
+        match value {
+            Value::Array(mut arr) => {
+                arr.filter_map(|v| self.map_all(v));
+                Some(Value::Array(arr))
+            }
+            other => self.lookup(other),
+        }
+    }
+
+    /// Map the selected fields of an element, or None to drop it.
+    fn map_selected(&self, value: Value, selection: &Selection) -> Option<Value> {
+        let Value::Array(mut arr) = value else {
+            return Some(value);
+        };
+        for i in selection_indices(selection, arr.len() as i64) {
+            let field = std::mem::replace(&mut arr.elements[i], Value::Text(String::new()));
+            arr.elements[i] = self.lookup(field)?;
+        }
+        Some(Value::Array(arr))
+    }
+}
+
+impl Transform for Map {
+    fn apply(&self, value: Value) -> Result<Value> {
+        Ok(match (value, &self.selection) {
+            (Value::Array(mut arr), Some(selection)) => {
+                arr.filter_map(|elem| self.map_selected(elem, selection));
+                Value::Array(arr)
+            }
+            (value, None) => self
+                .map_all(value)
+                .unwrap_or_else(|| Value::Text(String::new())),
+            (other, Some(_)) => other,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::SelectItem;
+    use crate::value::{Array, Level};
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    fn row(fields: &[&str]) -> Value {
+        Value::Array(Array::from((
+            fields.iter().map(|s| text(s)).collect(),
+            Level::Word,
+        )))
+    }
+
+    fn lines(rows: Vec<Value>) -> Value {
+        Value::Array(Array::from((rows, Level::Line)))
+    }
+
+    fn map(selection: Option<Selection>, unmapped: Unmapped) -> Map {
+        let dictionary = [("200", "OK"), ("404", "Not Found")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Map::new(dictionary, selection, unmapped)
+    }
+
+    fn second() -> Option<Selection> {
+        Some(Selection {
+            items: vec![SelectItem::Index(1)],
+        })
+    }
+
+    #[test]
+    fn map_recurses_and_keeps_unmapped() {
+        let input = lines(vec![row(&["200", "x"]), text("404"), Value::Number(200.0)]);
+        assert_eq!(
+            map(None, Unmapped::Keep).apply(input).unwrap(),
+            lines(vec![row(&["OK", "x"]), text("Not Found"), text("OK")])
+        );
+    }
+
+    #[test]
+    fn map_with_default() {
+        let input = lines(vec![text("200"), text("500")]);
+        assert_eq!(
+            map(None, Unmapped::Default("?".to_string()))
+                .apply(input)
+                .unwrap(),
+            lines(vec![text("OK"), text("?")])
+        );
+    }
+
+    #[test]
+    fn map_drops_unmapped() {
+        let input = lines(vec![row(&["200", "500"]), text("404")]);
+        assert_eq!(
+            map(None, Unmapped::Drop).apply(input).unwrap(),
+            lines(vec![row(&["OK"]), text("Not Found")])
+        );
+    }
+
+    #[test]
+    fn map_selected_field() {
+        let input = lines(vec![row(&["GET", "200"]), row(&["404", "404"])]);
+        assert_eq!(
+            map(second(), Unmapped::Keep).apply(input).unwrap(),
+            lines(vec![row(&["GET", "OK"]), row(&["404", "Not Found"])])
+        );
+    }
+
+    #[test]
+    fn map_selected_drops_whole_element() {
+        let input = lines(vec![row(&["GET", "200"]), row(&["PUT", "500"])]);
+        assert_eq!(
+            map(second(), Unmapped::Drop).apply(input).unwrap(),
+            lines(vec![row(&["GET", "OK"])])
+        );
+    }
+}
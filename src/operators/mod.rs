@@ -1,41 +1,79 @@
+mod arithmetic;
+mod block;
 mod case;
+mod chart;
 mod columnate;
+mod command;
+mod conditional;
 mod count;
 mod dedupe;
 mod delete_empty;
+mod enumerate;
 mod filter;
 mod flatten;
+mod fork;
+mod format;
 mod group;
 mod join;
+mod join_input;
+mod map;
 mod match_all;
 mod navigate;
 mod noop;
 mod number;
 mod partition;
+mod pattern_filter;
 mod replace;
+mod reverse;
+mod sample;
+mod scan;
 mod select;
+mod set;
 mod sort;
 mod split;
+mod stats;
 mod sum;
+mod time;
+mod transpose;
 mod trim;
+mod window;
 
+pub use arithmetic::Arithmetic;
+pub use block::Block;
 pub use case::{Lowercase, LowercaseSelected, Uppercase, UppercaseSelected};
+pub use chart::{DEFAULT_WIDTH, Histogram, Sparkline};
 pub use columnate::Columnate;
+pub use command::Command;
+pub use conditional::Conditional;
 pub use count::Count;
 pub use dedupe::{DedupeSelectionWithCounts, DedupeWithCounts};
 pub use delete_empty::DeleteEmpty;
+pub use enumerate::Enumerate;
 pub use filter::Filter;
 pub use flatten::Flatten;
-pub use group::GroupBy;
+pub use fork::Fork;
+pub use format::Format;
+pub use group::{GroupAggregate, GroupBy, Key};
 pub use join::{Join, JoinDelim, JoinMode};
+pub use join_input::JoinInput;
+pub use map::Map;
 pub use match_all::MatchAll;
 pub use navigate::{Ascend, Descend};
 pub use noop::NoOp;
 pub use number::{ToNumber, ToNumberSelected};
 pub use partition::Partition;
+pub use pattern_filter::PatternFilter;
 pub use replace::Replace;
+pub use reverse::Reverse;
+pub use sample::{Sampler, Shuffle};
+pub use scan::Running;
 pub use select::Select;
-pub use sort::{SortAscending, SortDescending};
+pub use set::SetInput;
+pub use sort::{SortAscending, SortBy, SortDescending};
 pub use split::{Split, SplitDelim, SplitMode};
+pub use stats::{Describe, Reduce};
 pub use sum::Sum;
+pub use time::{Duration, Time};
+pub use transpose::{Transpose, Zip};
 pub use trim::{Trim, TrimSelected};
+pub use window::Window;
@@ -0,0 +1,166 @@
+use std::collections::HashSet;
+
+use aho_corasick::AhoCorasick;
+use regex::RegexSet;
+
+use crate::ast::Selection;
+use crate::error::{Error, Result};
+use crate::interpreter::Transform;
+use crate::value::Value;
+
+use super::filter::selected_text;
+
+/// How the patterns of a [`PatternFilter`] are matched.
+enum Patterns {
+    /// Contains any of the strings, searched for all at once
+    Substrings(AhoCorasick),
+    /// Equals one of the strings
+    Exact(HashSet<String>),
+    /// Matches any of the regexes
+    Regexes(RegexSet),
+}
+
+/// Keeps the elements matching any pattern of a list, or with `negate` drops
+/// them. The text matched is the whole element, or the selected fields.
+///
+/// Literal patterns match anywhere in a whole element but must equal the
+/// selected fields, so a list of IPs can be checked against one field exactly.
+pub struct PatternFilter {
+    patterns: Patterns,
+    selection: Option<Selection>,
+    negate: bool,
+}
+
+impl PatternFilter {
+    /// A filter on literal strings.
+    pub fn literal(
+        patterns: Vec<String>,
+        selection: Option<Selection>,
+        negate: bool,
+    ) -> Result<Self> {
+        let patterns = match selection {
+            Some(_) => Patterns::Exact(patterns.into_iter().collect()),
+            None => Patterns::Substrings(
+                AhoCorasick::new(patterns)
+                    .map_err(|e| Error::runtime(format!("invalid pattern list: {}", e)))?,
+            ),
+        };
+        Ok(Self {
+            patterns,
+            selection,
+            negate,
+        })
+    }
+
+    /// A filter on regexes.
+    pub fn regex(
+        patterns: Vec<String>,
+        selection: Option<Selection>,
+        negate: bool,
+    ) -> Result<Self> {
+        let patterns = RegexSet::new(patterns)
+            .map_err(|e| Error::runtime(format!("invalid regex in pattern list: {}", e)))?;
+        Ok(Self {
+            patterns: Patterns::Regexes(patterns),
+            selection,
+            negate,
+        })
+    }
+
+    fn matches(&self, elem: &Value) -> bool {
+        let text = selected_text(elem, self.selection.as_ref());
+        match &self.patterns {
+            Patterns::Substrings(patterns) => patterns.is_match(&text),
+            Patterns::Exact(patterns) => patterns.contains(&text),
+            Patterns::Regexes(patterns) => patterns.is_match(&text),
+        }
+    }
+}
+
+impl Transform for PatternFilter {
+    fn apply(&self, value: Value) -> Result<Value> {
+        match value {
+            Value::Array(mut arr) => {
+                arr.retain(|elem| self.matches(elem) != self.negate);
+                Ok(Value::Array(arr))
+            }
+            other => Ok(other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::SelectItem;
+    use crate::value::{Array, Level};
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    fn lines(lines: &[&str]) -> Value {
+        Value::Array(Array::from((
+            lines.iter().map(|s| text(s)).collect(),
+            Level::Line,
+        )))
+    }
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn literal_substrings() {
+        let filter = PatternFilter::literal(patterns(&["1.2.3.4", "noise"]), None, false).unwrap();
+        let input = lines(&["from 1.2.3.4", "from 5.6.7.8", "a noise", "1.2.3.45"]);
+        assert_eq!(
+            filter.apply(input).unwrap(),
+            lines(&["from 1.2.3.4", "a noise", "1.2.3.45"])
+        );
+    }
+
+    #[test]
+    fn literal_negated() {
+        let filter = PatternFilter::literal(patterns(&["noise"]), None, true).unwrap();
+        let input = lines(&["signal", "noise", "more noise"]);
+        assert_eq!(filter.apply(input).unwrap(), lines(&["signal"]));
+    }
+
+    #[test]
+    fn literal_selection_is_exact() {
+        let row = |fields: &[&str]| {
+            Value::Array(Array::from((
+                fields.iter().map(|s| text(s)).collect(),
+                Level::Word,
+            )))
+        };
+        let first = Selection {
+            items: vec![SelectItem::Index(0)],
+        };
+        let filter = PatternFilter::literal(patterns(&["1.2.3.4"]), Some(first), false).unwrap();
+        let input = Value::Array(Array::from((
+            vec![row(&["1.2.3.4", "GET"]), row(&["1.2.3.45", "GET"])],
+            Level::Line,
+        )));
+        assert_eq!(
+            filter.apply(input).unwrap(),
+            Value::Array(Array::from((vec![row(&["1.2.3.4", "GET"])], Level::Line)))
+        );
+    }
+
+    #[test]
+    fn regexes() {
+        let filter = PatternFilter::regex(patterns(&["^a", "z$"]), None, false).unwrap();
+        let input = lines(&["abc", "xyz", "bcd"]);
+        assert_eq!(filter.apply(input).unwrap(), lines(&["abc", "xyz"]));
+    }
+
+    #[test]
+    fn invalid_regex_error() {
+        let err = PatternFilter::regex(patterns(&["("]), None, false)
+            .err()
+            .unwrap();
+        assert!(err.message.starts_with("invalid regex in pattern list"));
+    }
+}
@@ -0,0 +1,62 @@
+use crate::error::Result;
+use crate::interpreter::Transform;
+use crate::value::Value;
+
+/// Reverses the elements of an array, or the characters of text.
+///
+/// `["a", "b", "c"]` → `["c", "b", "a"]`
+pub struct Reverse;
+
+impl Transform for Reverse {
+    fn apply(&self, value: Value) -> Result<Value> {
+        match value {
+            Value::Array(mut arr) => {
+                arr.reverse();
+                Ok(Value::Array(arr))
+            }
+            Value::Text(s) => Ok(Value::Text(s.chars().rev().collect())),
+            other => Ok(other),
+        }
+    }
+
+    fn requires_full_input(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::{Array, Level};
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    #[test]
+    fn reverse_array() {
+        let input = Value::Array(Array::from((
+            vec![text("a"), text("b"), text("c")],
+            Level::Line,
+        )));
+        let result = Reverse.apply(input).unwrap();
+        assert_eq!(
+            result,
+            Value::Array(Array::from((
+                vec![text("c"), text("b"), text("a")],
+                Level::Line
+            )))
+        );
+    }
+
+    #[test]
+    fn reverse_text() {
+        assert_eq!(Reverse.apply(text("abc")).unwrap(), text("cba"));
+    }
+
+    #[test]
+    fn reverse_number_is_identity() {
+        let result = Reverse.apply(Value::Number(12.0)).unwrap();
+        assert_eq!(result, Value::Number(12.0));
+    }
+}
@@ -0,0 +1,234 @@
+use std::cell::RefCell;
+use std::convert::Infallible;
+use std::iter;
+
+use fastrand::Rng;
+
+use crate::ast::Sample;
+use crate::error::Result;
+use crate::interpreter::Transform;
+use crate::value::{Origin, Value};
+
+/// A generator seeded with `seed`, or randomly if there is none.
+fn rng(seed: Option<u64>) -> RefCell<Rng> {
+    RefCell::new(seed.map_or_else(Rng::new, Rng::with_seed))
+}
+
+/// Puts the elements of an array in a random order.
+pub struct Shuffle {
+    rng: RefCell<Rng>,
+}
+
+impl Shuffle {
+    pub fn new(seed: Option<u64>) -> Self {
+        Self { rng: rng(seed) }
+    }
+}
+
+impl Transform for Shuffle {
+    fn apply(&self, value: Value) -> Result<Value> {
+        match value {
+            Value::Array(mut arr) => {
+                let origins = arr.take_origins();
+                let mut order: Vec<usize> = (0..arr.len()).collect();
+                self.rng.borrow_mut().shuffle(&mut order);
+                let mut elements: Vec<Option<Value>> = arr.elements.drain(..).map(Some).collect();
+                arr.elements = order
+                    .iter()
+                    .map(|&i| elements[i].take().expect("each index appears once"))
+                    .collect();
+                arr.set_origins(
+                    origins.map(|origins| order.iter().map(|&i| origins[i].clone()).collect()),
+                );
+                Ok(Value::Array(arr))
+            }
+            other => Ok(other),
+        }
+    }
+
+    fn requires_full_input(&self) -> bool {
+        true
+    }
+}
+
+/// Keeps a random sample of the elements of an array, in their original order.
+///
+/// A fixed-size sample is drawn by reservoir sampling in a single pass over
+/// the elements. A fractional sample keeps each element independently with the
+/// given probability. Either way only the sample is held on to, so input can
+/// be sampled with [`Sampler::sample`] as it is read.
+pub struct Sampler {
+    sample: Sample,
+    rng: RefCell<Rng>,
+}
+
+impl Sampler {
+    pub fn new(sample: Sample, seed: Option<u64>) -> Self {
+        Self {
+            sample,
+            rng: rng(seed),
+        }
+    }
+
+    /// Sample items one at a time, stopping at the first error.
+    pub fn sample<T, E>(
+        &self,
+        items: impl Iterator<Item = std::result::Result<T, E>>,
+    ) -> std::result::Result<Vec<T>, E> {
+        let mut rng = self.rng.borrow_mut();
+        match &self.sample {
+            Sample::Count(size) => {
+                let mut reservoir: Vec<(usize, T)> = Vec::new();
+                for (i, item) in items.enumerate() {
+                    let item = item?;
+                    if i < *size {
+                        reservoir.push((i, item));
+                    } else {
+                        let j = rng.usize(..=i);
+                        if j < *size {
+                            reservoir[j] = (i, item);
+                        }
+                    }
+                }
+                reservoir.sort_by_key(|(i, _)| *i);
+                Ok(reservoir.into_iter().map(|(_, item)| item).collect())
+            }
+            Sample::Fraction(fraction) => {
+                let mut kept = Vec::new();
+                for item in items {
+                    let item = item?;
+                    if rng.f64() < *fraction {
+                        kept.push(item);
+                    }
+                }
+                Ok(kept)
+            }
+        }
+    }
+}
+
+impl Transform for Sampler {
+    fn apply(&self, value: Value) -> Result<Value> {
+        match value {
+            Value::Array(mut arr) => {
+                let origins = arr.take_origins();
+                let has_origins = origins.is_some();
+                let origins = origins
+                    .into_iter()
+                    .flatten()
+                    .map(Some)
+                    .chain(iter::repeat(None));
+                let items = arr.elements.drain(..).zip(origins).map(Ok);
+                let sample = self
+                    .sample(items)
+                    .unwrap_or_else(|e: Infallible| match e {});
+                let (elements, origins): (Vec<_>, Vec<Option<Origin>>) = sample.into_iter().unzip();
+                arr.elements = elements;
+                arr.set_origins(has_origins.then(|| origins.into_iter().flatten().collect()));
+                Ok(Value::Array(arr))
+            }
+            other => Ok(other),
+        }
+    }
+
+    fn requires_full_input(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::{Array, Level};
+
+    fn numbers(n: usize) -> Value {
+        Value::Array(Array::from((
+            (0..n).map(|i| Value::Number(i as f64)).collect(),
+            Level::Line,
+        )))
+    }
+
+    fn elements(value: Value) -> Vec<f64> {
+        match value {
+            Value::Array(arr) => arr
+                .elements
+                .into_iter()
+                .map(|v| match v {
+                    Value::Number(n) => n,
+                    other => panic!("expected number, got {:?}", other),
+                })
+                .collect(),
+            other => panic!("expected array, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn shuffle_is_a_permutation() {
+        let mut result = elements(Shuffle::new(Some(1)).apply(numbers(50)).unwrap());
+        assert_ne!(result, elements(numbers(50)));
+        result.sort_by(f64::total_cmp);
+        assert_eq!(result, elements(numbers(50)));
+    }
+
+    #[test]
+    fn shuffle_is_reproducible_with_a_seed() {
+        let a = Shuffle::new(Some(7)).apply(numbers(20)).unwrap();
+        let b = Shuffle::new(Some(7)).apply(numbers(20)).unwrap();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn sample_count_keeps_order() {
+        let result = elements(
+            Sampler::new(Sample::Count(10), Some(3))
+                .apply(numbers(1000))
+                .unwrap(),
+        );
+        assert_eq!(result.len(), 10);
+        assert!(result.is_sorted());
+    }
+
+    #[test]
+    fn sample_count_larger_than_input_keeps_everything() {
+        let result = Sampler::new(Sample::Count(10), None)
+            .apply(numbers(3))
+            .unwrap();
+        assert_eq!(result, numbers(3));
+    }
+
+    #[test]
+    fn sample_count_is_not_allocated_up_front() {
+        let result = Sampler::new(Sample::Count(usize::MAX), None)
+            .apply(numbers(3))
+            .unwrap();
+        assert_eq!(result, numbers(3));
+    }
+
+    #[test]
+    fn sample_items_as_they_come() {
+        for sample in [Sample::Count(10), Sample::Fraction(0.1)] {
+            let items = (0..1000).map(|i| Ok::<_, ()>(Value::Number(i as f64)));
+            let streamed = Sampler::new(sample.clone(), Some(3)).sample(items).unwrap();
+            let expected = Sampler::new(sample, Some(3)).apply(numbers(1000)).unwrap();
+            assert_eq!(
+                elements(Value::Array(Array::from((streamed, Level::Line)))),
+                elements(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn sample_fraction() {
+        let result = elements(
+            Sampler::new(Sample::Fraction(0.1), Some(5))
+                .apply(numbers(10000))
+                .unwrap(),
+        );
+        assert!((800..1200).contains(&result.len()));
+        assert!(result.is_sorted());
+        let all = Sampler::new(Sample::Fraction(1.0), None)
+            .apply(numbers(5))
+            .unwrap();
+        assert_eq!(all, numbers(5));
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::VecDeque;
+
+use crate::ast::Scan;
+use crate::error::Result;
+use crate::interpreter::Transform;
+use crate::value::Value;
+
+use super::sum::sum_recursive;
+
+/// Replaces each element with a running computation over the elements so far.
+///
+/// Elements are read as numbers with the coercion rules of `+`. Where there is
+/// no result, such as the change into the first element, the element becomes
+/// empty text so the array keeps its length.
+pub struct Running {
+    scan: Scan,
+}
+
+impl Running {
+    pub fn new(scan: Scan) -> Self {
+        Self { scan }
+    }
+}
+
+impl Transform for Running {
+    fn apply(&self, value: Value) -> Result<Value> {
+        match value {
+            Value::Array(mut arr) => {
+                let numbers = arr.elements.iter().map(sum_recursive);
+                let results: Vec<Option<f64>> = match self.scan {
+                    Scan::Sum => numbers
+                        .scan(0.0, |total, n| {
+                            *total += n;
+                            Some(Some(*total))
+                        })
+                        .collect(),
+                    Scan::Delta => with_previous(numbers, |prev, n| Some(n - prev)),
+                    Scan::Rate => {
+                        with_previous(numbers, |prev, n| (prev != 0.0).then(|| (n - prev) / prev))
+                    }
+                    Scan::MovingAverage(size) => {
+                        let mut window = VecDeque::with_capacity(size.min(arr.len()));
+                        numbers
+                            .map(|n| {
+                                if window.len() == size {
+                                    window.pop_front();
+                                }
+                                window.push_back(n);
+                                Some(window.iter().sum::<f64>() / window.len() as f64)
+                            })
+                            .collect()
+                    }
+                };
+                arr.elements = results
+                    .into_iter()
+                    .map(|n| n.map_or_else(|| Value::Text(String::new()), Value::Number))
+                    .collect();
+                Ok(Value::Array(arr))
+            }
+            other => Ok(other),
+        }
+    }
+}
+
+/// Combine each number with the one before it; the first has no result.
+fn with_previous(
+    numbers: impl Iterator<Item = f64>,
+    f: impl Fn(f64, f64) -> Option<f64>,
+) -> Vec<Option<f64>> {
+    let mut prev = None;
+    numbers
+        .map(|n| {
+            let result = prev.and_then(|prev| f(prev, n));
+            prev = Some(n);
+            result
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::{Array, Level};
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    fn numbers(values: &[f64]) -> Value {
+        Value::Array(Array::from((
+            values.iter().map(|n| Value::Number(*n)).collect(),
+            Level::Line,
+        )))
+    }
+
+    #[test]
+    fn running_sum() {
+        let result = Running::new(Scan::Sum)
+            .apply(Value::Array(Array::from((
+                vec![text("1"), text("2"), Value::Number(3.0)],
+                Level::Line,
+            ))))
+            .unwrap();
+        assert_eq!(result, numbers(&[1.0, 3.0, 6.0]));
+    }
+
+    #[test]
+    fn delta_and_rate() {
+        let result = Running::new(Scan::Delta)
+            .apply(numbers(&[10.0, 15.0, 12.0]))
+            .unwrap();
+        assert_eq!(
+            result,
+            Value::Array(Array::from((
+                vec![text(""), Value::Number(5.0), Value::Number(-3.0)],
+                Level::Line,
+            )))
+        );
+        let result = Running::new(Scan::Rate)
+            .apply(numbers(&[0.0, 10.0, 15.0]))
+            .unwrap();
+        assert_eq!(
+            result,
+            Value::Array(Array::from((
+                vec![text(""), text(""), Value::Number(0.5)],
+                Level::Line,
+            )))
+        );
+    }
+
+    #[test]
+    fn moving_average_starts_with_partial_windows() {
+        let result = Running::new(Scan::MovingAverage(2))
+            .apply(numbers(&[2.0, 4.0, 8.0]))
+            .unwrap();
+        assert_eq!(result, numbers(&[2.0, 3.0, 6.0]));
+    }
+
+    #[test]
+    fn moving_average_wider_than_input() {
+        let result = Running::new(Scan::MovingAverage(usize::MAX))
+            .apply(numbers(&[2.0, 4.0]))
+            .unwrap();
+        assert_eq!(result, numbers(&[2.0, 3.0]));
+    }
+
+    #[test]
+    fn running_non_array_is_identity() {
+        let result = Running::new(Scan::Sum).apply(text("5")).unwrap();
+        assert_eq!(result, text("5"));
+    }
+}
@@ -23,7 +23,7 @@ impl Transform for Select {
     }
 }
 
-fn select_from_array(arr: Array, selection: &Selection) -> Result<Value> {
+fn select_from_array(mut arr: Array, selection: &Selection) -> Result<Value> {
     let len = arr.len() as i64;
     let indices = selection_indices(selection, len);
 
@@ -42,8 +42,16 @@ fn select_from_array(arr: Array, selection: &Selection) -> Result<Value> {
             result.push(v.deep_copy());
         }
     }
-
-    Ok(Value::Array(Array::from((result, arr.level))))
+    let origins = arr.take_origins().map(|origins| {
+        indices
+            .iter()
+            .filter_map(|&i| origins.get(i).cloned())
+            .collect()
+    });
+
+    let mut result = Array::from((result, arr.level));
+    result.set_origins(origins);
+    Ok(Value::Array(result))
 }
 
 fn select_from_string(s: &str, selection: &Selection) -> Result<Value> {
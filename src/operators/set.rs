@@ -0,0 +1,204 @@
+use std::collections::HashSet;
+
+use crate::ast::{Selection, SetOperation};
+use crate::error::Result;
+use crate::inputs::Input;
+use crate::interpreter::Transform;
+use crate::value::{Origin, Value};
+
+use super::dedupe::value_to_key;
+use super::group::{Key, extract_key};
+
+/// A set operation between the elements and a named input.
+///
+/// Elements are compared with the key equality of `d`: by the whole element,
+/// or by the values at the key selections. The result is distinct, keeping the
+/// first element with each key, with elements of the array before those of the
+/// input. The input is compared by rows if the elements are rows, and by lines
+/// otherwise.
+pub struct SetInput {
+    operation: SetOperation,
+    keys: Option<(Key, Key)>,
+    input: Input,
+}
+
+impl SetInput {
+    pub fn new(
+        operation: SetOperation,
+        keys: Option<(Selection, Selection)>,
+        input: Input,
+    ) -> Self {
+        Self {
+            operation,
+            keys: keys.map(|(left, right)| (Key::Selection(left), Key::Selection(right))),
+            input,
+        }
+    }
+
+    fn key(&self, elem: &Value, key: impl Fn(&(Key, Key)) -> &Key) -> Result<String> {
+        match &self.keys {
+            Some(keys) => Ok(value_to_key(&extract_key(elem, key(keys))?)),
+            None => Ok(value_to_key(elem)),
+        }
+    }
+}
+
+impl Transform for SetInput {
+    fn apply(&self, value: Value) -> Result<Value> {
+        let Value::Array(mut arr) = value else {
+            return Ok(value);
+        };
+        let by_rows = matches!(arr.elements.first(), Some(Value::Array(_)));
+        let other = if by_rows {
+            &self.input.rows
+        } else {
+            &self.input.lines
+        };
+        let (other, other_origins) = match &**other {
+            Value::Array(other) => (other.elements.as_slice(), other.origins()),
+            _ => (&[][..], None),
+        };
+        let other_keys = other
+            .iter()
+            .map(|elem| self.key(elem, |(_, right)| right))
+            .collect::<Result<Vec<_>>>()?;
+        let in_other: HashSet<&str> = other_keys.iter().map(String::as_str).collect();
+
+        let origins = arr.take_origins();
+        let mut seen = HashSet::new();
+        let mut elements = Vec::new();
+        let mut kept_origins: Vec<Option<Origin>> = Vec::new();
+        for (i, elem) in arr.elements.drain(..).enumerate() {
+            let key = self.key(&elem, |(left, _)| left)?;
+            let keep = match self.operation {
+                SetOperation::Union => true,
+                SetOperation::Intersection => in_other.contains(key.as_str()),
+                SetOperation::Difference | SetOperation::SymmetricDifference => {
+                    !in_other.contains(key.as_str())
+                }
+            };
+            // Every key counts as seen, kept or not, so that neither a union
+            // nor a symmetric difference adds input elements matching one
+            let seen_before = !seen.insert(key);
+            if keep && !seen_before {
+                elements.push(elem);
+                kept_origins.push(origins.as_ref().map(|origins| origins[i].clone()));
+            }
+        }
+        if matches!(
+            self.operation,
+            SetOperation::Union | SetOperation::SymmetricDifference
+        ) {
+            let mut added = HashSet::new();
+            for (i, (elem, key)) in other.iter().zip(&other_keys).enumerate() {
+                if !seen.contains(key) && added.insert(key) {
+                    elements.push(elem.deep_copy());
+                    kept_origins.push(other_origins.map(|origins| origins[i].clone()));
+                }
+            }
+        }
+        arr.elements = elements;
+        arr.set_origins(kept_origins.into_iter().collect());
+        Ok(Value::Array(arr))
+    }
+
+    fn requires_full_input(&self) -> bool {
+        // The other input's lines added at the end depend on every key seen
+        matches!(
+            self.operation,
+            SetOperation::Union | SetOperation::SymmetricDifference
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::SelectItem;
+    use crate::inputs::Inputs;
+    use crate::operators::SplitMode;
+    use crate::value::{Array, Level};
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    fn lines(lines: &[&str]) -> Value {
+        Value::Array(Array::from((
+            lines.iter().map(|s| text(s)).collect(),
+            Level::Line,
+        )))
+    }
+
+    fn input(other: &[&str]) -> Input {
+        let mut inputs = Inputs::default();
+        inputs.insert("b", lines(other), &SplitMode::Whitespace, b'\t');
+        inputs.get("b").unwrap().clone()
+    }
+
+    fn set(operation: SetOperation, a: &[&str], b: &[&str]) -> Value {
+        SetInput::new(operation, None, input(b))
+            .apply(lines(a))
+            .unwrap()
+    }
+
+    #[test]
+    fn union_is_distinct() {
+        let result = set(SetOperation::Union, &["a", "b", "a"], &["c", "b", "c"]);
+        assert_eq!(result, lines(&["a", "b", "c"]));
+    }
+
+    #[test]
+    fn intersection() {
+        let result = set(
+            SetOperation::Intersection,
+            &["a", "b", "b", "c"],
+            &["c", "b"],
+        );
+        assert_eq!(result, lines(&["b", "c"]));
+    }
+
+    #[test]
+    fn difference() {
+        let result = set(SetOperation::Difference, &["a", "b", "a", "c"], &["b"]);
+        assert_eq!(result, lines(&["a", "c"]));
+    }
+
+    #[test]
+    fn symmetric_difference() {
+        let result = set(
+            SetOperation::SymmetricDifference,
+            &["a", "b", "a"],
+            &["b", "c", "c"],
+        );
+        assert_eq!(result, lines(&["a", "c"]));
+    }
+
+    #[test]
+    fn rows_are_compared_by_key_with_the_input_rows() {
+        let row = |fields: &[&str]| {
+            Value::Array(Array::from((
+                fields.iter().map(|s| text(s)).collect(),
+                Level::Word,
+            )))
+        };
+        let rows = Value::Array(Array::from((
+            vec![row(&["1.1.1.1", "GET"]), row(&["2.2.2.2", "PUT"])],
+            Level::Line,
+        )));
+        let first = Selection {
+            items: vec![SelectItem::Index(0)],
+        };
+        let result = SetInput::new(
+            SetOperation::Intersection,
+            Some((first.clone(), first)),
+            input(&["2.2.2.2 POST"]),
+        )
+        .apply(rows)
+        .unwrap();
+        assert_eq!(
+            result,
+            Value::Array(Array::from((vec![row(&["2.2.2.2", "PUT"])], Level::Line)))
+        );
+    }
+}
@@ -2,6 +2,8 @@ use crate::error::Result;
 use crate::interpreter::Transform;
 use crate::value::Value;
 
+use super::group::{Key, extract_key};
+
 pub struct SortDescending;
 
 impl Transform for SortDescending {
@@ -38,6 +40,54 @@ impl Transform for SortAscending {
     }
 }
 
+/// Sorts elements by a computed key, keeping the elements themselves.
+/// Elements with equal keys keep their relative order.
+pub struct SortBy {
+    key: Key,
+    descending: bool,
+}
+
+impl SortBy {
+    pub fn new(key: Key, descending: bool) -> Self {
+        Self { key, descending }
+    }
+}
+
+impl Transform for SortBy {
+    fn apply(&self, value: Value) -> Result<Value> {
+        match value {
+            Value::Array(mut arr) => {
+                let mut origins = arr.take_origins().map(Vec::into_iter);
+                let mut keyed = arr
+                    .elements
+                    .into_iter()
+                    .map(|elem| {
+                        let origin = origins.as_mut().and_then(Iterator::next);
+                        Ok((extract_key(&elem, &self.key)?, elem, origin))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                if self.descending {
+                    keyed.sort_by(|a, b| b.0.cmp(&a.0));
+                } else {
+                    keyed.sort_by(|a, b| a.0.cmp(&b.0));
+                }
+                let (elements, origins): (Vec<_>, Vec<_>) = keyed
+                    .into_iter()
+                    .map(|(_, elem, origin)| (elem, origin))
+                    .unzip();
+                arr.elements = elements;
+                arr.set_origins(origins.into_iter().collect());
+                Ok(Value::Array(arr))
+            }
+            other => Ok(other),
+        }
+    }
+
+    fn requires_full_input(&self) -> bool {
+        true
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -250,4 +300,32 @@ mod tests {
         let result = SortAscending.apply(input).unwrap();
         assert_eq!(result, Value::Number(42.0));
     }
+
+    #[test]
+    fn sort_by_programme_key_is_stable() {
+        use crate::interpreter::Operator;
+        use crate::operators::Count;
+
+        let input = Value::Array(Array::from((
+            vec![text("bb"), text("a"), text("cc"), text("ddd")],
+            Level::Line,
+        )));
+        let key = || Key::Programme(vec![Operator::Transform(Box::new(Count))]);
+        let result = SortBy::new(key(), true).apply(input.deep_copy()).unwrap();
+        assert_eq!(
+            result,
+            Value::Array(Array::from((
+                vec![text("ddd"), text("bb"), text("cc"), text("a")],
+                Level::Line,
+            )))
+        );
+        let result = SortBy::new(key(), false).apply(input).unwrap();
+        assert_eq!(
+            result,
+            Value::Array(Array::from((
+                vec![text("a"), text("bb"), text("cc"), text("ddd")],
+                Level::Line,
+            )))
+        );
+    }
 }
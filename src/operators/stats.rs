@@ -0,0 +1,209 @@
+use crate::ast::Statistic;
+use crate::error::Result;
+use crate::interpreter::Transform;
+use crate::value::{Array, Level, Value};
+
+use super::sum::coerce_scalar;
+
+/// Reduces all numbers in a value to a single statistic.
+///
+/// Numbers are gathered recursively with the coercion rules of `+`: numeric
+/// text is parsed and other text counts as 0. With no numbers at all the
+/// result is empty text.
+pub struct Reduce {
+    statistic: Statistic,
+}
+
+impl Reduce {
+    pub fn new(statistic: Statistic) -> Self {
+        Self { statistic }
+    }
+}
+
+impl Reduce {
+    fn reduce(&self, value: &Value) -> Value {
+        let mut numbers = Vec::new();
+        collect_numbers(value, &mut numbers);
+        match statistic(&self.statistic, &mut numbers) {
+            Some(n) => Value::Number(n),
+            None => Value::Text(String::new()),
+        }
+    }
+}
+
+impl Transform for Reduce {
+    fn apply(&self, value: Value) -> Result<Value> {
+        Ok(self.reduce(&value))
+    }
+
+    fn apply_ref(&self, value: &Value) -> Option<Result<Value>> {
+        Some(Ok(self.reduce(value)))
+    }
+
+    fn requires_full_input(&self) -> bool {
+        true
+    }
+}
+
+/// Reduces all numbers in a value to one row of summary statistics:
+/// count, min, max, mean, p50, p90 and p99.
+pub struct Describe;
+
+impl Transform for Describe {
+    fn apply(&self, value: Value) -> Result<Value> {
+        Ok(describe(&value))
+    }
+
+    fn apply_ref(&self, value: &Value) -> Option<Result<Value>> {
+        Some(Ok(describe(value)))
+    }
+
+    fn requires_full_input(&self) -> bool {
+        true
+    }
+}
+
+fn describe(value: &Value) -> Value {
+    let mut numbers = Vec::new();
+    collect_numbers(value, &mut numbers);
+    let mut row = vec![Value::Number(numbers.len() as f64)];
+    for stat in [
+        Statistic::Min,
+        Statistic::Max,
+        Statistic::Mean,
+        Statistic::Percentile(50.0),
+        Statistic::Percentile(90.0),
+        Statistic::Percentile(99.0),
+    ] {
+        row.push(match statistic(&stat, &mut numbers) {
+            Some(n) => Value::Number(n),
+            None => Value::Text(String::new()),
+        });
+    }
+    Value::Array(Array::from((row, Level::Word)))
+}
+
+fn collect_numbers(value: &Value, out: &mut Vec<f64>) {
+    match value {
+        Value::Array(arr) => arr.elements.iter().for_each(|v| collect_numbers(v, out)),
+        scalar => out.push(coerce_scalar(scalar)),
+    }
+}
+
+/// Compute a statistic, or None if there are no numbers. May sort `numbers`.
+fn statistic(statistic: &Statistic, numbers: &mut [f64]) -> Option<f64> {
+    if numbers.is_empty() {
+        return None;
+    }
+    let len = numbers.len() as f64;
+    let mean = || numbers.iter().sum::<f64>() / len;
+    Some(match statistic {
+        Statistic::Min => numbers.iter().copied().fold(f64::INFINITY, f64::min),
+        Statistic::Max => numbers.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        Statistic::Mean => mean(),
+        Statistic::Median => percentile(numbers, 50.0),
+        Statistic::Percentile(p) => percentile(numbers, *p),
+        Statistic::Variance => variance(numbers, mean()),
+        Statistic::StdDev => variance(numbers, mean()).sqrt(),
+    })
+}
+
+/// Population variance.
+fn variance(numbers: &[f64], mean: f64) -> f64 {
+    numbers.iter().map(|n| (n - mean).powi(2)).sum::<f64>() / numbers.len() as f64
+}
+
+/// The `p`th percentile, interpolating linearly between the closest ranks.
+fn percentile(numbers: &mut [f64], p: f64) -> f64 {
+    numbers.sort_by(f64::total_cmp);
+    let rank = p / 100.0 * (numbers.len() - 1) as f64;
+    let (lo, hi) = (rank.floor() as usize, rank.ceil() as usize);
+    numbers[lo] + (numbers[hi] - numbers[lo]) * (rank - lo as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    fn numbers(values: &[f64]) -> Value {
+        Value::Array(Array::from((
+            values.iter().map(|n| Value::Number(*n)).collect(),
+            Level::Line,
+        )))
+    }
+
+    fn reduce(statistic: Statistic, value: Value) -> Value {
+        Reduce::new(statistic).apply(value).unwrap()
+    }
+
+    #[test]
+    fn min_max_mean() {
+        let input = || numbers(&[3.0, 1.0, 4.0, 1.0, 5.0]);
+        assert_eq!(reduce(Statistic::Min, input()), Value::Number(1.0));
+        assert_eq!(reduce(Statistic::Max, input()), Value::Number(5.0));
+        assert_eq!(reduce(Statistic::Mean, input()), Value::Number(2.8));
+    }
+
+    #[test]
+    fn median_and_percentiles_interpolate() {
+        let input = || numbers(&[4.0, 1.0, 3.0, 2.0]);
+        assert_eq!(reduce(Statistic::Median, input()), Value::Number(2.5));
+        assert_eq!(
+            reduce(Statistic::Percentile(0.0), input()),
+            Value::Number(1.0)
+        );
+        assert_eq!(
+            reduce(Statistic::Percentile(100.0), input()),
+            Value::Number(4.0)
+        );
+        assert_eq!(
+            reduce(Statistic::Percentile(90.0), input()),
+            Value::Number(3.7)
+        );
+    }
+
+    #[test]
+    fn variance_and_stddev() {
+        let input = || numbers(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]);
+        assert_eq!(reduce(Statistic::Variance, input()), Value::Number(4.0));
+        assert_eq!(reduce(Statistic::StdDev, input()), Value::Number(2.0));
+    }
+
+    #[test]
+    fn coerces_like_sum() {
+        let input = Value::Array(Array::from((
+            vec![text("10"), text("abc"), numbers(&[5.0])],
+            Level::Line,
+        )));
+        assert_eq!(reduce(Statistic::Min, input), Value::Number(0.0));
+    }
+
+    #[test]
+    fn empty_is_empty_text() {
+        assert_eq!(reduce(Statistic::Max, numbers(&[])), text(""));
+    }
+
+    #[test]
+    fn describe_row() {
+        let result = Describe.apply(numbers(&[1.0, 2.0, 3.0])).unwrap();
+        assert_eq!(
+            result,
+            Value::Array(Array::from((
+                vec![
+                    Value::Number(3.0),
+                    Value::Number(1.0),
+                    Value::Number(3.0),
+                    Value::Number(2.0),
+                    Value::Number(2.0),
+                    Value::Number(2.8),
+                    Value::Number(2.98),
+                ],
+                Level::Word,
+            )))
+        );
+    }
+}
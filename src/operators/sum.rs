@@ -9,16 +9,39 @@ impl Transform for Sum {
         Ok(Value::Number(sum_recursive(&value)))
     }
 
+    fn apply_ref(&self, value: &Value) -> Option<Result<Value>> {
+        Some(Ok(Value::Number(sum_recursive(value))))
+    }
+
     fn requires_full_input(&self) -> bool {
         true
     }
 }
 
-fn sum_recursive(value: &Value) -> f64 {
+pub fn sum_recursive(value: &Value) -> f64 {
     match value {
         Value::Array(arr) => arr.elements.iter().map(sum_recursive).sum(),
+        scalar => coerce_scalar(scalar),
+    }
+}
+
+/// The number a text or number counts as when summing: its value, or 0 for
+/// text that isn't a number.
+pub fn coerce_scalar(value: &Value) -> f64 {
+    match value {
         Value::Number(n) => *n,
         Value::Text(s) => s.parse::<f64>().unwrap_or(0.0),
+        Value::Array(_) => 0.0,
+    }
+}
+
+/// The number a field stands for, for operators that compute with fields, or
+/// None for arrays and text that isn't one. Surrounding whitespace is ignored.
+pub fn to_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => Some(*n),
+        Value::Text(s) => s.trim().parse().ok(),
+        Value::Array(_) => None,
     }
 }
 
@@ -0,0 +1,402 @@
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::format::{Parsed, StrftimeItems, parse};
+use chrono::{DateTime, Datelike, FixedOffset};
+
+use crate::ast::{Selection, TimeOp};
+use crate::error::{Error, Result};
+use crate::interpreter::{Transform, try_map_elements};
+use crate::value::Value;
+
+use super::select::selection_indices;
+
+/// The formats a time is recognised in without a pattern, besides RFC 3339,
+/// RFC 2822 and numbers. Times without an offset are in UTC.
+const PATTERNS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S%.f%:z",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%d",
+    // Apache, without the brackets
+    "%d/%b/%Y:%H:%M:%S %z",
+    // Syslog, which has no year
+    "%b %e %H:%M:%S",
+];
+
+/// Numbers this large are taken as milliseconds rather than seconds since the
+/// epoch: 10^11 seconds is over 3000 years away, but 10^11 milliseconds is 1973.
+const MILLISECONDS: f64 = 1e11;
+
+/// Parses, truncates or formats the times of each element, or of its selected
+/// fields.
+///
+/// Times are numbers of seconds since the epoch. Truncating and formatting
+/// also take any time that parses, so can be given text directly. Parsing
+/// several selected fields reads them as one time, joined with spaces, which
+/// replaces them all: `k3:5p` parses the two fields an Apache timestamp splits
+/// into.
+pub struct Time {
+    selection: Option<Selection>,
+    op: TimeOp,
+    /// The year of times without one, such as syslog's: the current year
+    year: i32,
+}
+
+impl Time {
+    pub fn new(selection: Option<Selection>, op: TimeOp) -> Self {
+        Self {
+            selection,
+            op,
+            year: current_year(),
+        }
+    }
+
+    fn convert(&self, elem: Value) -> Result<Value> {
+        let Some(selection) = &self.selection else {
+            return self.convert_one(&elem.to_string(), &elem);
+        };
+        let Value::Array(mut arr) = elem else {
+            if selection_indices(selection, 1).is_empty() {
+                return Ok(elem);
+            }
+            return self.convert_one(&elem.to_string(), &elem);
+        };
+        let mut indices: Vec<usize> = Vec::new();
+        for i in selection_indices(selection, arr.len() as i64) {
+            if !indices.contains(&i) {
+                indices.push(i);
+            }
+        }
+        let Some(&first) = indices.iter().min() else {
+            return Ok(Value::Array(arr));
+        };
+        if let TimeOp::Parse(_) = self.op {
+            let text = indices
+                .iter()
+                .map(|&i| arr.elements[i].to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            arr.elements[first] = self.convert_one(&text, &Value::Text(text.clone()))?;
+            let mut i = 0;
+            arr.retain(|_| {
+                let keep = i == first || !indices.contains(&i);
+                i += 1;
+                keep
+            });
+        } else {
+            for i in indices {
+                let field = &arr.elements[i];
+                arr.elements[i] = self.convert_one(&field.to_string(), field)?;
+            }
+        }
+        Ok(Value::Array(arr))
+    }
+
+    /// Convert a single time, given as text or a value.
+    fn convert_one(&self, text: &str, value: &Value) -> Result<Value> {
+        match &self.op {
+            TimeOp::Parse(None) => Ok(Value::Number(self.read(value)?)),
+            TimeOp::Parse(Some(pattern)) => parse_pattern(text.trim(), pattern, self.year)
+                .map(Value::Number)
+                .ok_or_else(|| {
+                    Error::runtime(format!(
+                        "'{}' doesn't match the time pattern '{}'",
+                        text, pattern
+                    ))
+                }),
+            TimeOp::Truncate(bucket) => {
+                let size = f64::from(bucket.count) * f64::from(bucket.unit.seconds());
+                Ok(Value::Number((self.read(value)? / size).floor() * size))
+            }
+            TimeOp::Format { offset, pattern } => {
+                let time = self.read(value)?;
+                let seconds = time.floor();
+                let nanos = ((time - seconds) * 1e9).round().min(999_999_999.0) as u32;
+                let offset = FixedOffset::east_opt(offset.unwrap_or(0)).expect("parsed in range");
+                let datetime = DateTime::from_timestamp(seconds as i64, nanos)
+                    .ok_or_else(|| Error::runtime(format!("time out of range: '{}'", value)))?
+                    .with_timezone(&offset);
+                let mut result = String::new();
+                write!(result, "{}", datetime.format(pattern)).map_err(|_| {
+                    Error::runtime(format!("can't format '{}' with '{}'", value, pattern))
+                })?;
+                Ok(Value::Text(result))
+            }
+        }
+    }
+
+    fn read(&self, value: &Value) -> Result<f64> {
+        read_time(value, self.year)
+            .ok_or_else(|| Error::runtime(format!("not a time: '{}'", value)))
+    }
+}
+
+impl Transform for Time {
+    fn apply(&self, value: Value) -> Result<Value> {
+        match value {
+            Value::Array(mut arr) => {
+                arr.elements = try_map_elements(&mut arr, |elem| self.convert(elem))?;
+                Ok(Value::Array(arr))
+            }
+            other => self.convert(other),
+        }
+    }
+}
+
+/// Replaces each element with the seconds from the time in one field to the
+/// time in another.
+pub struct Duration {
+    from: i64,
+    to: i64,
+    year: i32,
+}
+
+impl Duration {
+    pub fn new(from: i64, to: i64) -> Self {
+        Self {
+            from,
+            to,
+            year: current_year(),
+        }
+    }
+
+    fn duration(&self, elem: &Value) -> Result<Value> {
+        let time = |index: i64| {
+            let field = match elem {
+                Value::Array(arr) => arr.get(index),
+                _ => None,
+            }
+            .ok_or_else(|| Error::runtime(format!("no field {}", index)))?;
+            read_time(field, self.year).ok_or_else(|| {
+                Error::runtime(format!("field {} is not a time: '{}'", index, field))
+            })
+        };
+        Ok(Value::Number(time(self.to)? - time(self.from)?))
+    }
+}
+
+impl Transform for Duration {
+    fn apply(&self, value: Value) -> Result<Value> {
+        match value {
+            Value::Array(mut arr) => {
+                arr.elements = try_map_elements(&mut arr, |elem| self.duration(&elem))?;
+                Ok(Value::Array(arr))
+            }
+            other => self.duration(&other),
+        }
+    }
+}
+
+/// Read a time in any recognised format, as seconds since the epoch.
+fn read_time(value: &Value, year: i32) -> Option<f64> {
+    let text = match value {
+        Value::Number(n) => return Some(from_number(*n)),
+        Value::Text(text) => text.trim(),
+        Value::Array(_) => return None,
+    };
+    if let Some(n) = text.parse::<f64>().ok().filter(|n| n.is_finite()) {
+        return Some(from_number(n));
+    }
+    if let Ok(datetime) =
+        DateTime::parse_from_rfc3339(text).or_else(|_| DateTime::parse_from_rfc2822(text))
+    {
+        return Some(
+            datetime.timestamp() as f64 + f64::from(datetime.timestamp_subsec_nanos()) / 1e9,
+        );
+    }
+    let text = text
+        .strip_prefix('[')
+        .and_then(|text| text.strip_suffix(']'))
+        .unwrap_or(text);
+    PATTERNS
+        .iter()
+        .find_map(|pattern| parse_pattern(text, pattern, year))
+}
+
+fn from_number(n: f64) -> f64 {
+    if n.abs() >= MILLISECONDS {
+        n / 1000.0
+    } else {
+        n
+    }
+}
+
+/// Parse a time with a strftime pattern, as seconds since the epoch. A time
+/// missing its year is in `year`, one missing its time of day is at midnight,
+/// and one missing its offset is in UTC.
+fn parse_pattern(text: &str, pattern: &str, year: i32) -> Option<f64> {
+    let mut parsed = Parsed::new();
+    parse(&mut parsed, text, StrftimeItems::new(pattern)).ok()?;
+    if parsed.timestamp().is_none() {
+        if parsed.year().is_none()
+            && parsed.year_mod_100().is_none()
+            && parsed.isoyear().is_none()
+            && parsed.isoyear_mod_100().is_none()
+        {
+            parsed.set_year(i64::from(year)).ok()?;
+        }
+        if parsed.hour_div_12().is_none() && parsed.hour_mod_12().is_none() {
+            parsed.set_hour(0).ok()?;
+        }
+        if parsed.minute().is_none() {
+            parsed.set_minute(0).ok()?;
+        }
+    }
+    let offset = parsed.offset().unwrap_or(0);
+    let local = parsed.to_naive_datetime_with_offset(offset).ok()?.and_utc();
+    Some(
+        (local.timestamp() - i64::from(offset)) as f64
+            + f64::from(local.timestamp_subsec_nanos()) / 1e9,
+    )
+}
+
+fn current_year() -> i32 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64);
+    DateTime::from_timestamp(now, 0).map_or(1970, |now| now.year())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Bucket, Operator, TimeUnit};
+    use crate::parser::parse_programme;
+    use crate::value::{Array, Level};
+
+    fn row(fields: &[&str]) -> Value {
+        Value::Array(Array::from((
+            fields.iter().map(|s| Value::Text(s.to_string())).collect(),
+            Level::Word,
+        )))
+    }
+
+    /// Run a time operator on a single element.
+    fn run(source: &str, elem: Value) -> Result<Value> {
+        let value = Value::Array(Array::from((vec![elem], Level::Line)));
+        let result = match parse_programme(source).unwrap().operators.remove(0) {
+            Operator::Time { selection, op } => Time::new(selection, op).apply(value),
+            Operator::Duration { from, to } => Duration::new(from, to).apply(value),
+            op => panic!("not a time operator: {:?}", op),
+        };
+        match result? {
+            Value::Array(mut arr) => Ok(arr.elements.remove(0)),
+            other => Ok(other),
+        }
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    #[test]
+    fn parse_common_formats() {
+        for time in [
+            "2000-10-10T20:55:36Z",
+            "2000-10-10T13:55:36-07:00",
+            "2000-10-10 20:55:36",
+            "2000-10-10 13:55:36-07:00",
+            "[10/Oct/2000:13:55:36 -0700]",
+            "Tue, 10 Oct 2000 20:55:36 +0000",
+            "971211336",
+            "971211336000",
+        ] {
+            assert_eq!(
+                run("kp", text(time)).unwrap(),
+                Value::Number(971211336.0),
+                "{}",
+                time
+            );
+        }
+        assert_eq!(
+            run("kp", text("2000-10-10T20:55:36.25Z")).unwrap(),
+            Value::Number(971211336.25)
+        );
+    }
+
+    #[test]
+    fn parse_syslog_in_current_year() {
+        let expected = parse_pattern(
+            &format!("{}-03-01 12:00:00", current_year()),
+            "%Y-%m-%d %H:%M:%S",
+            0,
+        )
+        .unwrap();
+        assert_eq!(
+            run("kp", text("Mar  1 12:00:00")).unwrap(),
+            Value::Number(expected)
+        );
+    }
+
+    #[test]
+    fn parse_with_pattern() {
+        assert_eq!(
+            run(r#"kp"%d.%m.%Y""#, text("02.01.1970")).unwrap(),
+            Value::Number(86400.0)
+        );
+        let err = run(r#"kp"%d.%m.%Y""#, text("1970-01-02")).unwrap_err();
+        assert_eq!(
+            err.message,
+            "'1970-01-02' doesn't match the time pattern '%d.%m.%Y'"
+        );
+    }
+
+    #[test]
+    fn parse_selected_fields_as_one() {
+        let elem = row(&["1.2.3.4", "[10/Oct/2000:13:55:36", "-0700]", "GET"]);
+        assert_eq!(
+            run("k1:3p", elem).unwrap(),
+            Value::Array(Array::from((
+                vec![text("1.2.3.4"), Value::Number(971211336.0), text("GET")],
+                Level::Word
+            )))
+        );
+    }
+
+    #[test]
+    fn truncate_to_bucket() {
+        let time = text("2000-10-10T20:55:36Z");
+        let truncate = |unit, count| {
+            let op = TimeOp::Truncate(Bucket { count, unit });
+            Time::new(None, op).convert(time.deep_copy()).unwrap()
+        };
+        assert_eq!(truncate(TimeUnit::Minute, 1), Value::Number(971211300.0));
+        assert_eq!(truncate(TimeUnit::Minute, 15), Value::Number(971210700.0));
+        assert_eq!(truncate(TimeUnit::Day, 1), Value::Number(971136000.0));
+        // Wider than a u32 of seconds
+        assert_eq!(truncate(TimeUnit::Day, 100_000), Value::Number(0.0));
+    }
+
+    #[test]
+    fn format_with_offset() {
+        assert_eq!(
+            run(r#"kf"%Y-%m-%d %H:%M""#, Value::Number(971211336.0)).unwrap(),
+            text("2000-10-10 20:55")
+        );
+        assert_eq!(
+            run(
+                r#"k0f-07:00"%H:%M:%S %z""#,
+                row(&["2000-10-10T20:55:36Z", "x"])
+            )
+            .unwrap(),
+            row(&["13:55:36 -0700", "x"])
+        );
+    }
+
+    #[test]
+    fn duration_between_fields() {
+        let elem = row(&["2000-10-10T20:55:36Z", "GET", "2000-10-10T21:00:00Z"]);
+        assert_eq!(run("kd0,-1", elem).unwrap(), Value::Number(264.0));
+    }
+
+    #[test]
+    fn not_a_time_error() {
+        let err = run("k/h", text("yesterday")).unwrap_err();
+        assert_eq!(err.message, "not a time: 'yesterday'");
+        let err = run("kd0,1", row(&["0", "GET"])).unwrap_err();
+        assert_eq!(err.message, "field 1 is not a time: 'GET'");
+    }
+}
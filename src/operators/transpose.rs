@@ -0,0 +1,141 @@
+use crate::error::Result;
+use crate::interpreter::Transform;
+use crate::value::{Array, Level, Value};
+
+/// Swaps the rows and columns of an array of arrays.
+///
+/// `[["a", "b"], ["c"]]` → `[["a", "c"], ["b", ""]]`
+///
+/// Short rows are padded with empty text. Non-array rows count as rows of one.
+pub struct Transpose;
+
+impl Transform for Transpose {
+    fn apply(&self, value: Value) -> Result<Value> {
+        match value {
+            Value::Array(arr) => {
+                let width = arr.elements.iter().map(row_len).max().unwrap_or(0);
+                Ok(columns(arr, width))
+            }
+            other => Ok(other),
+        }
+    }
+
+    fn requires_full_input(&self) -> bool {
+        true
+    }
+}
+
+/// Pairs up the elements of parallel arrays, stopping at the shortest.
+///
+/// `[["a", "b", "c"], [1, 2]]` → `[["a", 1], ["b", 2]]`
+pub struct Zip;
+
+impl Transform for Zip {
+    fn apply(&self, value: Value) -> Result<Value> {
+        match value {
+            Value::Array(arr) => {
+                let width = arr.elements.iter().map(row_len).min().unwrap_or(0);
+                Ok(columns(arr, width))
+            }
+            other => Ok(other),
+        }
+    }
+
+    fn requires_full_input(&self) -> bool {
+        true
+    }
+}
+
+fn row_len(row: &Value) -> usize {
+    match row {
+        Value::Array(arr) => arr.len(),
+        _ => 1,
+    }
+}
+
+/// The first `width` columns of `arr`, padding short rows with empty text.
+/// Columns take the level of the rows, so a table of words stays one.
+fn columns(arr: Array, width: usize) -> Value {
+    let row_level = arr
+        .elements
+        .iter()
+        .find_map(|row| match row {
+            Value::Array(row) => Some(row.level),
+            _ => None,
+        })
+        .unwrap_or(Level::Word);
+    let mut columns: Vec<Vec<Value>> = (0..width)
+        .map(|_| Vec::with_capacity(arr.elements.len()))
+        .collect();
+    for row in arr.elements {
+        let mut cells = match row {
+            Value::Array(row) => row.elements.into_iter(),
+            other => vec![other].into_iter(),
+        };
+        for column in &mut columns {
+            column.push(cells.next().unwrap_or_else(|| Value::Text(String::new())));
+        }
+    }
+    let columns = columns
+        .into_iter()
+        .map(|column| Value::Array(Array::from((column, row_level))))
+        .collect();
+    Value::Array(Array::from((columns, arr.level)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    fn row(cells: &[&str]) -> Value {
+        Value::Array(Array::from((
+            cells.iter().map(|s| text(s)).collect(),
+            Level::Word,
+        )))
+    }
+
+    fn table(rows: Vec<Value>) -> Value {
+        Value::Array(Array::from((rows, Level::Line)))
+    }
+
+    #[test]
+    fn transpose_square() {
+        let input = table(vec![row(&["a", "b"]), row(&["c", "d"])]);
+        let result = Transpose.apply(input).unwrap();
+        assert_eq!(result, table(vec![row(&["a", "c"]), row(&["b", "d"])]));
+    }
+
+    #[test]
+    fn transpose_pads_ragged_rows() {
+        let input = table(vec![row(&["a", "b", "c"]), row(&["d"])]);
+        let result = Transpose.apply(input).unwrap();
+        assert_eq!(
+            result,
+            table(vec![row(&["a", "d"]), row(&["b", ""]), row(&["c", ""])])
+        );
+    }
+
+    #[test]
+    fn transpose_twice_is_identity_for_rectangles() {
+        let input = || table(vec![row(&["a", "b", "c"]), row(&["d", "e", "f"])]);
+        let once = Transpose.apply(input()).unwrap();
+        assert_eq!(Transpose.apply(once).unwrap(), input());
+    }
+
+    #[test]
+    fn zip_stops_at_shortest() {
+        let input = table(vec![row(&["a", "b", "c"]), row(&["1", "2"])]);
+        let result = Zip.apply(input).unwrap();
+        assert_eq!(result, table(vec![row(&["a", "1"]), row(&["b", "2"])]));
+    }
+
+    #[test]
+    fn zip_empty_is_empty() {
+        let result = Zip.apply(table(vec![])).unwrap();
+        assert_eq!(result, table(vec![]));
+    }
+}
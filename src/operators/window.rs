@@ -0,0 +1,78 @@
+use crate::error::Result;
+use crate::interpreter::Transform;
+use crate::value::{Array, Level, Value};
+
+/// Replaces an array with its overlapping runs of `size` consecutive elements,
+/// e.g. `[a, b, c]` becomes `[[a, b], [b, c]]` with a size of 2. Each window
+/// is a row of words, so n-grams of words and of lines both print one per line.
+pub struct Window {
+    size: usize,
+}
+
+impl Window {
+    pub fn new(size: usize) -> Self {
+        Self { size }
+    }
+}
+
+impl Transform for Window {
+    fn apply(&self, value: Value) -> Result<Value> {
+        match value {
+            Value::Array(arr) => {
+                let windows = arr
+                    .elements
+                    .windows(self.size)
+                    .map(|window| {
+                        let elements = window.iter().map(Value::deep_copy).collect();
+                        Value::Array(Array::from((elements, Level::Word)))
+                    })
+                    .collect();
+                Ok(Value::Array(Array::from((windows, Level::Line))))
+            }
+            other => Ok(other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(words: &[&str]) -> Value {
+        Value::Array(Array::from((
+            words.iter().map(|s| Value::Text(s.to_string())).collect(),
+            Level::Word,
+        )))
+    }
+
+    #[test]
+    fn window_pairs() {
+        let result = Window::new(2).apply(words(&["a", "b", "c", "d"])).unwrap();
+        assert_eq!(
+            result,
+            Value::Array(Array::from((
+                vec![words(&["a", "b"]), words(&["b", "c"]), words(&["c", "d"])],
+                Level::Line,
+            )))
+        );
+    }
+
+    #[test]
+    fn window_rows_are_words() {
+        let lines = Value::Array(Array::from((
+            vec![Value::Text("a".to_string()), Value::Text("b".to_string())],
+            Level::Line,
+        )));
+        let result = Window::new(2).apply(lines).unwrap();
+        assert_eq!(
+            result,
+            Value::Array(Array::from((vec![words(&["a", "b"])], Level::Line)))
+        );
+    }
+
+    #[test]
+    fn window_larger_than_array_is_empty() {
+        let result = Window::new(3).apply(words(&["a", "b"])).unwrap();
+        assert_eq!(result, Value::Array(Array::from((vec![], Level::Line))));
+    }
+}
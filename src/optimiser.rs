@@ -0,0 +1,577 @@
+//! Optimiser for the t language.
+//!
+//! Applies semantics-preserving rewrites to a parsed programme before it is
+//! compiled:
+//!
+//! - `;` no-ops are dropped.
+//! - Runs of `@` and `^` collapse to their net effect, and navigation at the
+//!   end of the programme is dropped.
+//! - Filters move ahead of `u` and `l` when case mapping can't change whether
+//!   an element matches, so dropped elements are never case-mapped.
+//! - Adjacent filters merge into a single predicate.
+//! - A sort immediately followed by an unkeyed sort is dropped.
+//! - Blocks, fork and conditional branches, and key programmes are optimised
+//!   as programmes in their own right.
+//!
+//! Two rewrites that look tempting are deliberately absent: `sj` is not an
+//! identity because `s` collapses runs of whitespace, and `o` after `d` is
+//! not redundant because `d` breaks count ties by first occurrence rather
+//! than by value.
+
+use regex_syntax::hir::{Class, Hir, HirKind, Look};
+
+use crate::ast::{FilterClause, Key, Operator, Programme};
+
+/// Optimise a programme, returning an equivalent programme.
+pub fn optimise(programme: Programme) -> Programme {
+    let mut operators = programme.operators;
+    loop {
+        let before = operators.clone();
+        operators = drop_noops(operators);
+        operators = collapse_navigation(operators);
+        operators = hoist_filters(operators);
+        operators = merge_filters(operators);
+        operators = drop_redundant_sorts(operators);
+        operators = optimise_nested(operators);
+        if operators == before {
+            return Programme { operators };
+        }
+    }
+}
+
+/// Remove `;` separators.
+fn drop_noops(operators: Vec<Operator>) -> Vec<Operator> {
+    operators
+        .into_iter()
+        .filter(|op| *op != Operator::NoOp)
+        .collect()
+}
+
+/// Replace each run of `@` and `^` with the fewest operators reaching the same depth.
+///
+/// Depth is fully determined by the programme text, so it can be tracked
+/// exactly, including `^` saturating at the root. A run after the last
+/// transform is dropped, as it can't change the output: a run is only emitted
+/// when a transform follows it.
+fn collapse_navigation(operators: Vec<Operator>) -> Vec<Operator> {
+    let mut result = Vec::with_capacity(operators.len());
+    let mut depth = 0usize;
+    let mut target = 0usize;
+    for op in operators {
+        match op {
+            Operator::Descend => target += 1,
+            Operator::Ascend => target = target.saturating_sub(1),
+            other => {
+                if target > depth {
+                    result.extend(std::iter::repeat_n(Operator::Descend, target - depth));
+                } else {
+                    result.extend(std::iter::repeat_n(Operator::Ascend, depth - target));
+                }
+                depth = target;
+                result.push(other);
+            }
+        }
+    }
+    result
+}
+
+/// Swap a filter with a preceding `u` or `l` when the swap can't change its result.
+fn hoist_filters(mut operators: Vec<Operator>) -> Vec<Operator> {
+    for i in 1..operators.len() {
+        let is_case_mapping = matches!(operators[i - 1], Operator::Uppercase | Operator::Lowercase);
+        if is_case_mapping && is_case_invariant_filter(&operators[i]) {
+            operators.swap(i - 1, i);
+        }
+    }
+    operators
+}
+
+/// Merge adjacent filters into a single `FilterAll`.
+fn merge_filters(operators: Vec<Operator>) -> Vec<Operator> {
+    let mut result: Vec<Operator> = Vec::with_capacity(operators.len());
+    for op in operators {
+        let Some(clauses) = filter_clauses(&op) else {
+            result.push(op);
+            continue;
+        };
+        match result.last().and_then(filter_clauses) {
+            Some(mut merged) => {
+                merged.extend(clauses);
+                *result.last_mut().unwrap() = Operator::FilterAll(merged);
+            }
+            None => result.push(op),
+        }
+    }
+    result
+}
+
+/// Drop a sort that is immediately re-sorted by value.
+///
+/// Equal elements can still differ, in their level and in the input lines
+/// they came from, so the order an unkeyed sort leaves them in matters. But
+/// sorts are stable, and elements that are equal have equal keys under any
+/// sort, so the previous sort never reorders them relative to each other:
+/// they come out of the unkeyed sort in their original order either way. A
+/// keyed sort is different: elements with equal keys can differ in value,
+/// and keep the order the previous sort gave them.
+fn drop_redundant_sorts(operators: Vec<Operator>) -> Vec<Operator> {
+    let mut result: Vec<Operator> = Vec::with_capacity(operators.len());
+    for op in operators {
+        let is_sort = |op: &Operator| {
+            matches!(
+                op,
+                Operator::SortAscending
+                    | Operator::SortDescending
+                    | Operator::SortAscendingBy(_)
+                    | Operator::SortDescendingBy(_)
+            )
+        };
+        let is_unkeyed_sort = matches!(op, Operator::SortAscending | Operator::SortDescending);
+        if is_unkeyed_sort && result.last().is_some_and(is_sort) {
+            result.pop();
+        }
+        result.push(op);
+    }
+    result
+}
+
+/// Optimise the body of each block, the branches of each fork and
+/// conditional, and each key programme.
+///
+/// These run in a fresh context, so each starts at depth 0 and its trailing
+/// navigation is dropped like any other programme's.
+fn optimise_nested(operators: Vec<Operator>) -> Vec<Operator> {
+    operators
+        .into_iter()
+        .map(|op| match op {
+            Operator::Block(programme) => Operator::Block(optimise(programme)),
+            Operator::Fork(branches) => {
+                Operator::Fork(branches.into_iter().map(optimise).collect())
+            }
+            Operator::Conditional {
+                selection,
+                pattern,
+                then,
+                otherwise,
+            } => Operator::Conditional {
+                selection,
+                pattern,
+                then: optimise(then),
+                otherwise: otherwise.map(optimise),
+            },
+            Operator::GroupBy(Key::Programme(key)) => {
+                Operator::GroupBy(Key::Programme(optimise(key)))
+            }
+            Operator::GroupAggregate {
+                key: Key::Programme(key),
+                aggregates,
+            } => Operator::GroupAggregate {
+                key: Key::Programme(optimise(key)),
+                aggregates,
+            },
+            Operator::DedupeSelectionWithCounts(Key::Programme(key)) => {
+                Operator::DedupeSelectionWithCounts(Key::Programme(optimise(key)))
+            }
+            Operator::SortDescendingBy(key) => Operator::SortDescendingBy(optimise(key)),
+            Operator::SortAscendingBy(key) => Operator::SortAscendingBy(optimise(key)),
+            op => op,
+        })
+        .collect()
+}
+
+/// Returns the clauses of a filter operator, or None if it isn't a filter.
+fn filter_clauses(op: &Operator) -> Option<Vec<FilterClause>> {
+    match op {
+        Operator::Filter { pattern, negate } => Some(vec![FilterClause {
+            pattern: pattern.clone(),
+            negate: *negate,
+        }]),
+        Operator::FilterAll(clauses) => Some(clauses.clone()),
+        _ => None,
+    }
+}
+
+/// Returns true if `op` is a filter whose every pattern is case invariant.
+fn is_case_invariant_filter(op: &Operator) -> bool {
+    filter_clauses(op).is_some_and(|clauses| {
+        clauses
+            .iter()
+            .all(|clause| is_case_invariant(&clause.pattern))
+    })
+}
+
+/// Returns true if case mapping the input can't change whether `pattern` matches.
+///
+/// Case mapping leaves ASCII punctuation, digits and whitespace alone, and
+/// maps every other character to a non-empty run of characters outside that
+/// set. A pattern that only consumes such ASCII characters, and only asserts
+/// text or line boundaries, therefore sees the same runs of characters
+/// either way.
+fn is_case_invariant(pattern: &str) -> bool {
+    regex_syntax::parse(pattern).is_ok_and(|hir| hir_is_case_invariant(&hir))
+}
+
+fn hir_is_case_invariant(hir: &Hir) -> bool {
+    let uncased = |c: char| c.is_ascii() && !c.is_ascii_alphabetic();
+    match hir.kind() {
+        HirKind::Empty => true,
+        HirKind::Literal(lit) => std::str::from_utf8(&lit.0).is_ok_and(|s| s.chars().all(uncased)),
+        HirKind::Class(Class::Unicode(class)) => class
+            .iter()
+            .all(|range| uncased(range.start()) && uncased(range.end())),
+        HirKind::Class(Class::Bytes(class)) => class
+            .iter()
+            .all(|range| uncased(range.start() as char) && uncased(range.end() as char)),
+        HirKind::Look(look) => matches!(
+            look,
+            Look::Start | Look::End | Look::StartLF | Look::EndLF | Look::StartCRLF | Look::EndCRLF
+        ),
+        HirKind::Repetition(rep) => hir_is_case_invariant(&rep.sub),
+        HirKind::Capture(cap) => hir_is_case_invariant(&cap.sub),
+        HirKind::Concat(subs) | HirKind::Alternation(subs) => {
+            subs.iter().all(hir_is_case_invariant)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Aggregate;
+    use crate::interpreter::{Context, compile, run};
+    use crate::parser::parse_programme;
+    use crate::value::{Array, Level, Value};
+    use proptest::prelude::*;
+
+    fn optimised(source: &str) -> Vec<Operator> {
+        optimise(parse_programme(source).unwrap()).operators
+    }
+
+    fn parsed(source: &str) -> Vec<Operator> {
+        parse_programme(source).unwrap().operators
+    }
+
+    /// Run a programme over lines of input, rendering errors as strings so
+    /// results can be compared.
+    fn execute(operators: &[Operator], lines: &[String]) -> Result<Value, String> {
+        let programme = Programme {
+            operators: operators.to_vec(),
+        };
+        let ops = compile(&programme).map_err(|e| e.to_string())?;
+        let elements = lines.iter().map(|l| Value::Text(l.clone())).collect();
+        let mut ctx = Context::new(Value::Array(Array::from((elements, Level::Line))));
+        run(&ops, &mut ctx).map_err(|e| e.to_string())?;
+        Ok(ctx.into_value())
+    }
+
+    /// Lines drawn from an alphabet that includes characters whose case
+    /// mappings change length, to catch unsound filter hoisting.
+    fn lines() -> impl Strategy<Value = Vec<String>> {
+        prop::collection::vec("[aAbBßİ12 \\-:]{0,8}", 0..8)
+    }
+
+    fn filter(pattern: &str, negate: bool) -> Operator {
+        Operator::Filter {
+            pattern: pattern.to_string(),
+            negate,
+        }
+    }
+
+    fn filters() -> impl Strategy<Value = Operator> {
+        prop::sample::select(vec![
+            filter("a", false),
+            filter("a", true),
+            filter("(?i)b", false),
+            filter("SS", false),
+            filter("^1", false),
+            filter("[0-9]$", true),
+            filter("1 2", false),
+            filter("-", true),
+            filter(".", false),
+            filter("^$", true),
+        ])
+    }
+
+    fn operators() -> impl Strategy<Value = Operator> {
+        leaf_operators().prop_recursive(2, 12, 4, |inner| {
+            let programme =
+                prop::collection::vec(inner, 0..4).prop_map(|operators| Programme { operators });
+            prop_oneof![
+                programme.clone().prop_map(Operator::Block),
+                prop::collection::vec(programme.clone(), 1..3).prop_map(Operator::Fork),
+                (
+                    filters(),
+                    programme.clone(),
+                    proptest::option::of(programme.clone())
+                )
+                    .prop_map(|(filter, then, otherwise)| {
+                        let Operator::Filter { pattern, .. } = filter else {
+                            unreachable!()
+                        };
+                        Operator::Conditional {
+                            selection: None,
+                            pattern,
+                            then,
+                            otherwise,
+                        }
+                    }),
+                programme
+                    .clone()
+                    .prop_map(|key| Operator::GroupBy(Key::Programme(key))),
+                programme.clone().prop_map(|key| Operator::GroupAggregate {
+                    key: Key::Programme(key),
+                    aggregates: vec![Aggregate::Count, Aggregate::Max(None)],
+                }),
+                programme.clone().prop_map(Operator::SortAscendingBy),
+                programme.prop_map(Operator::SortDescendingBy),
+            ]
+        })
+    }
+
+    /// Sorts, including keyed sorts whose keys tie for distinct elements.
+    fn sorts() -> impl Strategy<Value = Operator> {
+        let key = |source: &str| parse_programme(source).unwrap();
+        prop::sample::select(vec![
+            Operator::SortAscending,
+            Operator::SortDescending,
+            Operator::SortAscendingBy(key("l")),
+            Operator::SortDescendingBy(key("#")),
+        ])
+    }
+
+    fn leaf_operators() -> impl Strategy<Value = Operator> {
+        prop_oneof![
+            filters(),
+            prop::sample::select(vec![
+                Operator::Split,
+                Operator::Join,
+                Operator::Descend,
+                Operator::Ascend,
+                Operator::Uppercase,
+                Operator::Lowercase,
+                Operator::Trim,
+                Operator::ToNumber,
+                Operator::DeleteEmpty,
+                Operator::Flatten,
+                Operator::DedupeWithCounts,
+                Operator::Count,
+                Operator::SortAscending,
+                Operator::SortDescending,
+                Operator::NoOp,
+            ]),
+        ]
+    }
+
+    fn assert_equivalent(
+        operators: Vec<Operator>,
+        lines: &[String],
+    ) -> std::result::Result<Vec<Operator>, TestCaseError> {
+        let optimised = optimise(Programme {
+            operators: operators.clone(),
+        })
+        .operators;
+        prop_assert_eq!(
+            execute(&operators, lines),
+            execute(&optimised, lines),
+            "{:?} optimised to {:?}",
+            operators,
+            optimised
+        );
+        Ok(optimised)
+    }
+
+    #[test]
+    fn drops_noops() {
+        assert_eq!(optimised("s;@;0"), parsed("s@0"));
+    }
+
+    #[test]
+    fn collapses_navigation() {
+        assert_eq!(optimised("s@^l"), parsed("sl"));
+        assert_eq!(optimised("s@@^0"), parsed("s@0"));
+        assert_eq!(optimised("s@^^@0"), parsed("s@0"));
+    }
+
+    #[test]
+    fn collapses_saturating_ascend() {
+        // At the root `^` is a no-op, so `^@` is a descend.
+        assert_eq!(optimised("^@0"), parsed("@0"));
+    }
+
+    #[test]
+    fn drops_trailing_navigation() {
+        assert_eq!(optimised("s@0^"), parsed("s@0"));
+        assert_eq!(optimised("s@@"), parsed("s"));
+        assert_eq!(optimised("{s@}"), parsed("{s}"));
+    }
+
+    #[test]
+    fn hoists_case_invariant_filters() {
+        assert_eq!(optimised("l/^[0-9]+ /"), parsed("/^[0-9]+ /l"));
+        assert_eq!(optimised("ul!/-/"), parsed("!/-/ul"));
+    }
+
+    #[test]
+    fn keeps_case_sensitive_filters_in_place() {
+        assert_eq!(optimised("l/error/"), parsed("l/error/"));
+        assert_eq!(optimised("u/(?i)ss/"), parsed("u/(?i)ss/"));
+        assert_eq!(optimised("l/1.2/"), parsed("l/1.2/"));
+        assert_eq!(optimised("l/\\b1/"), parsed("l/\\b1/"));
+    }
+
+    #[test]
+    fn merges_adjacent_filters() {
+        assert_eq!(
+            optimised("/a/!/b/;/c/"),
+            vec![Operator::FilterAll(vec![
+                FilterClause {
+                    pattern: "a".to_string(),
+                    negate: false,
+                },
+                FilterClause {
+                    pattern: "b".to_string(),
+                    negate: true,
+                },
+                FilterClause {
+                    pattern: "c".to_string(),
+                    negate: false,
+                },
+            ])]
+        );
+    }
+
+    #[test]
+    fn does_not_merge_filters_across_depths() {
+        assert_eq!(optimised("/a/@/b/"), parsed("/a/@/b/"));
+    }
+
+    #[test]
+    fn drops_redundant_sorts() {
+        assert_eq!(optimised("oO"), parsed("O"));
+        assert_eq!(optimised("Oo;o"), parsed("o"));
+    }
+
+    #[test]
+    fn drops_sort_before_unkeyed_sort() {
+        assert_eq!(optimised("o{#}O"), parsed("O"));
+    }
+
+    #[test]
+    fn keeps_sort_before_keyed_sort() {
+        assert_eq!(optimised("oO{#}"), parsed("oO{#}"));
+    }
+
+    #[test]
+    fn optimises_key_programmes() {
+        assert_eq!(optimised("g{;l}D{@^0}o{oO}"), parsed("g{l}D{0}o{O}"));
+    }
+
+    #[test]
+    fn keeps_sort_after_dedupe() {
+        assert_eq!(optimised("do"), parsed("do"));
+    }
+
+    #[test]
+    fn optimises_block_bodies() {
+        assert_eq!(optimised("s{;@^0@}"), parsed("s{0}"));
+        assert_eq!(optimised("{oO}"), parsed("{O}"));
+    }
+
+    #[test]
+    fn optimises_fork_branches() {
+        assert_eq!(optimised("(s@;0^|oo)"), parsed("(s@0|o)"));
+    }
+
+    #[test]
+    fn optimises_conditional_branches() {
+        assert_eq!(optimised("?/a/{u;@^}{oO}"), parsed("?/a/{u}{O}"));
+    }
+
+    #[test]
+    fn does_not_merge_filters_across_blocks() {
+        assert_eq!(optimised("/a/{/b/}"), parsed("/a/{/b/}"));
+    }
+
+    #[test]
+    fn keeps_split_join() {
+        assert_eq!(optimised("sj"), parsed("sj"));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_drop_noops(
+            ops in prop::collection::vec(operators(), 0..8),
+            lines in lines(),
+        ) {
+            let optimised = assert_equivalent(ops, &lines)?;
+            prop_assert!(!optimised.contains(&Operator::NoOp));
+        }
+
+        #[test]
+        fn prop_collapse_navigation(
+            ops in prop::collection::vec(
+                prop_oneof![
+                    3 => prop::sample::select(vec![Operator::Descend, Operator::Ascend]),
+                    1 => operators(),
+                ],
+                0..12,
+            ),
+            lines in lines(),
+        ) {
+            let optimised = assert_equivalent(ops, &lines)?;
+            let has_redundant_pair = optimised.windows(2).any(|w| {
+                matches!(
+                    (&w[0], &w[1]),
+                    (Operator::Descend, Operator::Ascend) | (Operator::Ascend, Operator::Descend)
+                )
+            });
+            prop_assert!(!has_redundant_pair);
+        }
+
+        #[test]
+        fn prop_hoist_filters(
+            prefix in prop::collection::vec(operators(), 0..3),
+            case_ops in prop::collection::vec(
+                prop::sample::select(vec![Operator::Uppercase, Operator::Lowercase]),
+                1..3,
+            ),
+            filter in filters(),
+            lines in lines(),
+        ) {
+            let mut ops = prefix;
+            ops.extend(case_ops);
+            ops.push(filter);
+            assert_equivalent(ops, &lines)?;
+        }
+
+        #[test]
+        fn prop_merge_filters(
+            prefix in prop::collection::vec(operators(), 0..3),
+            filters in prop::collection::vec(filters(), 2..5),
+            lines in lines(),
+        ) {
+            let mut ops = prefix;
+            ops.extend(filters);
+            assert_equivalent(ops, &lines)?;
+        }
+
+        #[test]
+        fn prop_drop_redundant_sorts(
+            prefix in prop::collection::vec(operators(), 0..4),
+            sorts in prop::collection::vec(sorts(), 2..4),
+            lines in lines(),
+        ) {
+            let mut ops = prefix;
+            ops.extend(sorts);
+            assert_equivalent(ops, &lines)?;
+        }
+
+        #[test]
+        fn prop_random_programmes(
+            ops in prop::collection::vec(operators(), 0..12),
+            lines in lines(),
+        ) {
+            assert_equivalent(ops, &lines)?;
+        }
+    }
+}
@@ -1,16 +1,200 @@
+use chrono::format::{Item, StrftimeItems};
 use regex::Regex;
 use winnow::ModalResult;
-use winnow::ascii::digit1;
-use winnow::combinator::{alt, cut_err, opt, repeat, separated};
+use winnow::ascii::{digit1, multispace0, multispace1};
+use winnow::combinator::{alt, cut_err, delimited, opt, peek, preceded, repeat, separated};
 use winnow::error::{StrContext, StrContextValue};
 use winnow::prelude::*;
-use winnow::token::{one_of, take_till};
+use winnow::token::{one_of, take_till, take_while};
 
-use crate::ast::{Operator, Programme, SelectItem, Selection, Slice};
+use crate::ast::{
+    Aggregate, BinaryOp, Bucket, Conversion, Expr, FormatPart, FormatSpec, Function, JoinKind, Key,
+    Operator, Programme, Sample, Scan, SelectItem, Selection, SetOperation, Slice, Statistic,
+    TimeOp, TimeUnit, Unmapped,
+};
+use crate::macros::Macros;
+use crate::variables::{Quoting, Variables, has_references};
 
 /// Parse a complete programme (sequence of operators).
+///
+/// Macro invocations are left unexpanded; see [`parse_programme_with_macros`].
 pub fn parse_programme(input: &str) -> std::result::Result<Programme, String> {
-    programme.parse(input).map_err(|e| {
+    let operators = parse_spanned(input)?;
+    Ok(Programme {
+        operators: operators.into_iter().map(|(_, op)| op).collect(),
+    })
+}
+
+/// Parse a programme, expanding `&<name>` macro invocations and substituting
+/// `{$name}` variable references.
+///
+/// Errors inside a macro body point into the body, followed by the
+/// invocation site in `input`.
+pub fn parse_programme_with_macros(
+    input: &str,
+    macros: &Macros,
+    variables: &Variables,
+) -> std::result::Result<Programme, String> {
+    let mut operators = Vec::new();
+    let mut stack = Vec::new();
+    expand(input, macros, variables, &mut stack, &mut operators).map_err(|e| match e {
+        ExpandError::Here(message) => message,
+        ExpandError::InMacro { message, offset } => {
+            format!("{}\n{}", message, render_caret(input, offset))
+        }
+    })?;
+    Ok(Programme { operators })
+}
+
+/// An error found while expanding macros.
+enum ExpandError {
+    /// A fully rendered error in the source being expanded.
+    Here(String),
+    /// A rendered error inside the body of the macro invoked at `offset`.
+    InMacro { message: String, offset: usize },
+}
+
+/// Parse `source` and append its operators to `out`, recursively expanding macros.
+/// `stack` holds the names of the macros currently being expanded.
+fn expand(
+    source: &str,
+    macros: &Macros,
+    variables: &Variables,
+    stack: &mut Vec<String>,
+    out: &mut Vec<Operator>,
+) -> std::result::Result<(), ExpandError> {
+    let operators = parse_spanned(source).map_err(ExpandError::Here)?;
+    expand_spanned(source, operators, macros, variables, stack, out)
+}
+
+/// Append `operators` parsed from `source` to `out`, recursively expanding
+/// macros, including those inside nested programmes.
+fn expand_spanned(
+    source: &str,
+    operators: Vec<(usize, Operator)>,
+    macros: &Macros,
+    variables: &Variables,
+    stack: &mut Vec<String>,
+    out: &mut Vec<Operator>,
+) -> std::result::Result<(), ExpandError> {
+    for (offset, op) in operators {
+        let fail = |message: String| ExpandError::Here(render_error(&message, source, offset));
+        let op = substitute(op, variables)
+            .map_err(|message| fail(format!("parse error: {}", message)))?;
+        let name = match op {
+            Operator::Macro(name) => name,
+            Operator::Block(_)
+            | Operator::Fork(_)
+            | Operator::Conditional { .. }
+            | Operator::GroupBy(Key::Programme(_))
+            | Operator::GroupAggregate {
+                key: Key::Programme(_),
+                ..
+            }
+            | Operator::DedupeSelectionWithCounts(Key::Programme(_))
+            | Operator::SortDescendingBy(_)
+            | Operator::SortAscendingBy(_) => {
+                let mut bodies = Vec::new();
+                for spanned in bodies_spanned(source, offset, &op) {
+                    let mut body = Vec::new();
+                    expand_spanned(source, spanned, macros, variables, stack, &mut body)?;
+                    bodies.push(Programme { operators: body });
+                }
+                out.push(with_bodies(op, bodies));
+                continue;
+            }
+            op => {
+                out.push(op);
+                continue;
+            }
+        };
+        if stack.contains(&name) {
+            let cycle: Vec<String> = stack
+                .iter()
+                .skip_while(|n| **n != name)
+                .chain(std::iter::once(&name))
+                .map(|n| format!("&{}", n))
+                .collect();
+            return Err(fail(format!(
+                "parse error: macro cycle: {}",
+                cycle.join(" -> ")
+            )));
+        }
+        let Some(body) = macros.get(&name) else {
+            return Err(fail(format!("parse error: unknown macro &{}", name)));
+        };
+        stack.push(name.clone());
+        let result = expand(body, macros, variables, stack, out);
+        stack.pop();
+        result.map_err(|e| {
+            let message = match e {
+                ExpandError::Here(message) => message,
+                // Point at the invocation inside this macro's body as well
+                ExpandError::InMacro {
+                    message,
+                    offset: inner,
+                } => format!("{}\n{}", message, render_caret(body, inner)),
+            };
+            // Name the macro on the message line, leaving the caret lines intact
+            let (first, rest) = message.split_once('\n').unwrap_or((&message, ""));
+            let message = format!("{} (in macro &{})\n{}", first, name, rest);
+            ExpandError::InMacro { message, offset }
+        })?;
+    }
+    Ok(())
+}
+
+/// Substitute the variable references in the regexes, replacements and
+/// delimiters of `op`, but not of its nested programmes.
+fn substitute(op: Operator, variables: &Variables) -> std::result::Result<Operator, String> {
+    let regex = |pattern: String| variables.substitute(&pattern, Quoting::Regex);
+    let literal = |text: String| variables.substitute(&text, Quoting::Literal);
+    Ok(match op {
+        Operator::SplitDelim(delim) => Operator::SplitDelim(literal(delim)?),
+        Operator::JoinDelim(delim) => Operator::JoinDelim(literal(delim)?),
+        Operator::Replace {
+            selection,
+            pattern,
+            replacement,
+        } => Operator::Replace {
+            selection,
+            pattern: regex(pattern)?,
+            replacement: variables.substitute(&replacement, Quoting::Replacement)?,
+        },
+        Operator::Filter { pattern, negate } => Operator::Filter {
+            pattern: regex(pattern)?,
+            negate,
+        },
+        Operator::Match { pattern } => Operator::Match {
+            pattern: regex(pattern)?,
+        },
+        Operator::Conditional {
+            selection,
+            pattern,
+            then,
+            otherwise,
+        } => Operator::Conditional {
+            selection,
+            pattern: regex(pattern)?,
+            then,
+            otherwise,
+        },
+        Operator::Map {
+            input,
+            selection,
+            unmapped: Unmapped::Default(default),
+        } => Operator::Map {
+            input,
+            selection,
+            unmapped: Unmapped::Default(literal(default)?),
+        },
+        op => op,
+    })
+}
+
+/// Parse a programme into operators paired with their byte offsets in `input`.
+fn parse_spanned(input: &str) -> std::result::Result<Vec<(usize, Operator)>, String> {
+    spanned_operators(input.len()).parse(input).map_err(|e| {
         let mut offset = e.offset();
         let message = if let Some(ctx) = e.inner().context().next() {
             match ctx {
@@ -38,32 +222,179 @@ pub fn parse_programme(input: &str) -> std::result::Result<Programme, String> {
         } else {
             "parse error: unexpected character".to_string()
         };
-        format!("{}\n  {}\n  {}^", message, input, " ".repeat(offset))
+        render_error(&message, input, offset)
     })
 }
 
-/// Parser for the full programme.
-fn programme(input: &mut &str) -> ModalResult<Programme> {
-    let operators = repeat(0.., operator).parse_next(input)?;
-    Ok(Programme { operators })
+/// Parser for a run of operators, paired with their byte offsets in a source
+/// `total` bytes long. Stops before anything that isn't an operator.
+fn spanned_operators(total: usize) -> impl FnMut(&mut &str) -> ModalResult<Vec<(usize, Operator)>> {
+    move |i: &mut &str| {
+        ignored.parse_next(i)?;
+        let mut operators = Vec::new();
+        loop {
+            let offset = total - i.len();
+            match opt(operator).parse_next(i)? {
+                Some(op) => operators.push((offset, op)),
+                None => return Ok(operators),
+            }
+            ignored.parse_next(i)?;
+        }
+    }
+}
+
+/// The operators of each nested programme of `op`, which starts at `offset`
+/// in `source`, paired with their offsets. `op` must have been parsed from there.
+fn bodies_spanned(source: &str, offset: usize, op: &Operator) -> Vec<Vec<(usize, Operator)>> {
+    let mut rest = &source[offset..];
+    let body = |rest: &mut &str| {
+        spanned_operators(source.len())
+            .parse_next(rest)
+            .expect("parsed before")
+    };
+    let mut bodies = Vec::new();
+    match op {
+        Operator::Block(_) => {
+            rest = &rest[1..];
+            bodies.push(body(&mut rest));
+        }
+        Operator::Fork(_) => {
+            rest = &rest[1..];
+            loop {
+                bodies.push(body(&mut rest));
+                match rest.strip_prefix('|') {
+                    Some(next) => rest = next,
+                    None => break,
+                }
+            }
+        }
+        Operator::GroupBy(Key::Programme(_))
+        | Operator::GroupAggregate {
+            key: Key::Programme(_),
+            ..
+        }
+        | Operator::DedupeSelectionWithCounts(Key::Programme(_))
+        | Operator::SortDescendingBy(_)
+        | Operator::SortAscendingBy(_) => {
+            // A one-letter operator followed by the braced key
+            rest = &rest[2..];
+            bodies.push(body(&mut rest));
+        }
+        Operator::Conditional { .. } => {
+            conditional_header
+                .parse_next(&mut rest)
+                .expect("parsed before");
+            while let Some(next) = rest.strip_prefix('{') {
+                rest = next;
+                bodies.push(body(&mut rest));
+                rest = &rest[1..];
+            }
+        }
+        _ => {}
+    }
+    bodies
+}
+
+/// Replace the nested programmes of `op` with `bodies`, in source order.
+fn with_bodies(op: Operator, bodies: Vec<Programme>) -> Operator {
+    let mut bodies = bodies.into_iter();
+    match op {
+        Operator::Block(_) => Operator::Block(bodies.next().expect("block has a body")),
+        Operator::Fork(_) => Operator::Fork(bodies.collect()),
+        Operator::Conditional {
+            selection, pattern, ..
+        } => Operator::Conditional {
+            selection,
+            pattern,
+            then: bodies.next().expect("conditional has a then-branch"),
+            otherwise: bodies.next(),
+        },
+        Operator::GroupBy(Key::Programme(_)) => {
+            Operator::GroupBy(Key::Programme(bodies.next().expect("key has a body")))
+        }
+        Operator::GroupAggregate {
+            key: Key::Programme(_),
+            aggregates,
+        } => Operator::GroupAggregate {
+            key: Key::Programme(bodies.next().expect("key has a body")),
+            aggregates,
+        },
+        Operator::DedupeSelectionWithCounts(Key::Programme(_)) => {
+            Operator::DedupeSelectionWithCounts(Key::Programme(
+                bodies.next().expect("key has a body"),
+            ))
+        }
+        Operator::SortDescendingBy(_) => {
+            Operator::SortDescendingBy(bodies.next().expect("key has a body"))
+        }
+        Operator::SortAscendingBy(_) => {
+            Operator::SortAscendingBy(bodies.next().expect("key has a body"))
+        }
+        op => op,
+    }
+}
+
+/// Render an error message followed by the source line with a caret at `offset`.
+fn render_error(message: &str, input: &str, offset: usize) -> String {
+    if input.contains('\n') {
+        // Multi-line programmes (from files) show only the offending line
+        let line_number = input[..offset].matches('\n').count() + 1;
+        format!(
+            "{} (at line {})\n{}",
+            message,
+            line_number,
+            render_caret(input, offset)
+        )
+    } else {
+        format!("{}\n{}", message, render_caret(input, offset))
+    }
+}
+
+/// Render the line of `input` containing `offset`, with a caret beneath it.
+fn render_caret(input: &str, offset: usize) -> String {
+    let line_start = input[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = input[offset..]
+        .find('\n')
+        .map_or(input.len(), |i| offset + i);
+    format!(
+        "  {}\n  {}^",
+        &input[line_start..line_end],
+        " ".repeat(offset - line_start)
+    )
+}
+
+/// Parser for insignificant whitespace and `--` comments between operators.
+fn ignored(input: &mut &str) -> ModalResult<()> {
+    repeat(0.., alt((multispace1.void(), comment))).parse_next(input)
+}
+
+/// Parser for a line comment: `--` to the end of the line.
+fn comment(input: &mut &str) -> ModalResult<()> {
+    ("--", take_till(0.., '\n')).void().parse_next(input)
 }
 
 /// Parser for a single operator.
 fn operator(input: &mut &str) -> ModalResult<Operator> {
     alt((
+        sort_by_op,
         simple_op,
-        split_delim_op,
-        join_delim_op,
-        lowercase_selected_op,
-        uppercase_selected_op,
-        to_number_selected_op,
-        trim_selected_op,
+        alt((split_delim_op, join_delim_op, format_op)),
+        selected_op,
         partition_op,
         replace_op,
         match_op,
-        filter_op,
+        alt((command_op, filter_op, arithmetic_op, time_op, chart_op)),
         group_by_op,
         dedupe_selection_op,
+        statistic_op,
+        scan_op,
+        window_op,
+        sample_op,
+        input_op,
+        macro_op,
+        block_op,
+        fork_op,
+        conditional_op,
         selection_op,
     ))
     .parse_next(input)
@@ -72,7 +403,8 @@ fn operator(input: &mut &str) -> ModalResult<Operator> {
 /// Parser for simple single-character operators.
 fn simple_op(input: &mut &str) -> ModalResult<Operator> {
     one_of((
-        's', 'j', '@', '^', 'u', 'l', 't', 'n', 'x', 'f', 'd', '+', '#', 'c', 'o', 'O', ';',
+        's', 'j', '@', '^', 'u', 'l', 't', 'n', 'x', 'f', 'e', 'v', 'Z', 'z', 'd', '+', '#', 'c',
+        'o', 'O', ';',
     ))
     .map(|c| match c {
         's' => Operator::Split,
@@ -85,6 +417,10 @@ fn simple_op(input: &mut &str) -> ModalResult<Operator> {
         'n' => Operator::ToNumber,
         'x' => Operator::DeleteEmpty,
         'f' => Operator::Flatten,
+        'e' => Operator::Enumerate,
+        'v' => Operator::Reverse,
+        'Z' => Operator::Transpose,
+        'z' => Operator::Zip,
         'd' => Operator::DedupeWithCounts,
         '+' => Operator::Sum,
         '#' => Operator::Count,
@@ -97,6 +433,119 @@ fn simple_op(input: &mut &str) -> ModalResult<Operator> {
     .parse_next(input)
 }
 
+/// Parser for chart operators: `H`, or `h`, `h%`, `h+` or `h%+`
+fn chart_op(input: &mut &str) -> ModalResult<Operator> {
+    alt((
+        'H'.value(Operator::Sparkline),
+        preceded('h', (opt('%'), opt('+'))).map(|(percent, cumulative)| Operator::Histogram {
+            percent: percent.is_some(),
+            cumulative: cumulative.is_some(),
+        }),
+    ))
+    .parse_next(input)
+}
+
+/// Parser for statistical reducers: `%min`, `%p99`, `%describe`, ...
+fn statistic_op(input: &mut &str) -> ModalResult<Operator> {
+    '%'.parse_next(input)?;
+    cut_err(alt((
+        "describe".value(Operator::Describe),
+        "min".value(Operator::Statistic(Statistic::Min)),
+        "max".value(Operator::Statistic(Statistic::Max)),
+        "mean".value(Operator::Statistic(Statistic::Mean)),
+        "median".value(Operator::Statistic(Statistic::Median)),
+        "stddev".value(Operator::Statistic(Statistic::StdDev)),
+        "var".value(Operator::Statistic(Statistic::Variance)),
+        preceded(
+            'p',
+            cut_err(percentile).context(StrContext::Expected(StrContextValue::Description(
+                "percentile from 0 to 100",
+            ))),
+        )
+        .map(|p| Operator::Statistic(Statistic::Percentile(p))),
+    )))
+    .context(StrContext::Expected(StrContextValue::Description(
+        "statistic (min, max, mean, median, p<n>, stddev, var or describe)",
+    )))
+    .parse_next(input)
+}
+
+/// Parser for running computations: `\+`, `\-`, `\/` and `\m<n>`
+fn scan_op(input: &mut &str) -> ModalResult<Operator> {
+    '\\'.parse_next(input)?;
+    cut_err(alt((
+        '+'.value(Scan::Sum),
+        '-'.value(Scan::Delta),
+        '/'.value(Scan::Rate),
+        preceded(
+            'm',
+            cut_err(size).context(StrContext::Expected(StrContextValue::Description(
+                "window size",
+            ))),
+        )
+        .map(Scan::MovingAverage),
+    )))
+    .context(StrContext::Expected(StrContextValue::Description(
+        "'+', '-', '/' or 'm<n>'",
+    )))
+    .map(Operator::Scan)
+    .parse_next(input)
+}
+
+/// Parser for sliding windows: `w<n>`
+fn window_op(input: &mut &str) -> ModalResult<Operator> {
+    'w'.parse_next(input)?;
+    cut_err(size)
+        .context(StrContext::Expected(StrContextValue::Description(
+            "window size",
+        )))
+        .map(Operator::Window)
+        .parse_next(input)
+}
+
+/// Parser for shuffling and sampling: `~`, `~<n>` or `~<fraction>`
+fn sample_op(input: &mut &str) -> ModalResult<Operator> {
+    '~'.parse_next(input)?;
+    opt(alt((
+        preceded(
+            peek((digit1, '.')),
+            cut_err(fraction).context(StrContext::Expected(StrContextValue::Description(
+                "fraction from 0 to 1",
+            ))),
+        )
+        .map(Sample::Fraction),
+        digit1.try_map(str::parse::<usize>).map(Sample::Count),
+    )))
+    .map(|sample| sample.map_or(Operator::Shuffle, Operator::Sample))
+    .parse_next(input)
+}
+
+/// Parser for a fraction: a number from 0 to 1, e.g. `0.01`.
+fn fraction(input: &mut &str) -> ModalResult<f64> {
+    (digit1, '.', digit1)
+        .take()
+        .try_map(str::parse::<f64>)
+        .verify(|p| *p <= 1.0)
+        .parse_next(input)
+}
+
+/// Parser for a size: a positive integer.
+fn size(input: &mut &str) -> ModalResult<usize> {
+    digit1
+        .try_map(str::parse::<usize>)
+        .verify(|n| *n > 0)
+        .parse_next(input)
+}
+
+/// Parser for a percentile: a number from 0 to 100, e.g. `99` or `99.9`.
+fn percentile(input: &mut &str) -> ModalResult<f64> {
+    (digit1, opt(('.', digit1)))
+        .take()
+        .try_map(str::parse::<f64>)
+        .verify(|p| *p <= 100.0)
+        .parse_next(input)
+}
+
 /// Parser for split delimiter operator: `S<char>` or `S"<delim>"`
 fn split_delim_op(input: &mut &str) -> ModalResult<Operator> {
     'S'.parse_next(input)?;
@@ -119,6 +568,153 @@ fn join_delim_op(input: &mut &str) -> ModalResult<Operator> {
     Ok(Operator::JoinDelim(delim))
 }
 
+/// Parser for format operator: `F"<template>"`
+///
+/// The template is a quoted string, so takes the same escapes, and is parsed
+/// as a template once unescaped.
+fn format_op(input: &mut &str) -> ModalResult<Operator> {
+    'F'.parse_next(input)?;
+    let start = *input;
+    let template = cut_err(quoted_string)
+        .context(StrContext::Expected(StrContextValue::Description(
+            "\"<template>\"",
+        )))
+        .parse_next(input)?;
+    match format_template.parse(template.as_str()) {
+        Ok(parts) => Ok(Operator::Format(parts)),
+        Err(e) => {
+            let expected = match e.inner().context().next() {
+                Some(StrContext::Expected(StrContextValue::Description(desc))) => desc,
+                _ => "placeholder",
+            };
+            // Point at the error if the template has no escapes, so its
+            // offsets are those of the source; otherwise at the template
+            let raw_len = start.len() - input.len();
+            *input = if raw_len == template.len() + 2 {
+                &start[1 + e.offset()..]
+            } else {
+                start
+            };
+            cut_err(winnow::combinator::fail)
+                .context(StrContext::Label(Box::leak(
+                    format!("{} in template", expected).into_boxed_str(),
+                )))
+                .parse_next(input)
+        }
+    }
+}
+
+/// Parser for an unescaped `F` template: literal text, `{{` and `}}` for
+/// braces, and placeholders.
+fn format_template(input: &mut &str) -> ModalResult<Vec<FormatPart>> {
+    let mut parts = Vec::new();
+    let push_literal = |parts: &mut Vec<FormatPart>, text: &str| match parts.last_mut() {
+        Some(FormatPart::Literal(literal)) => literal.push_str(text),
+        _ => parts.push(FormatPart::Literal(text.to_string())),
+    };
+    while !input.is_empty() {
+        if input.starts_with("{{") {
+            "{{".parse_next(input)?;
+            push_literal(&mut parts, "{");
+        } else if input.starts_with("}}") {
+            "}}".parse_next(input)?;
+            push_literal(&mut parts, "}");
+        } else if input.starts_with('{') {
+            parts.push(placeholder.parse_next(input)?);
+        } else if input.starts_with('}') {
+            return cut_err(winnow::combinator::fail)
+                .context(StrContext::Expected(StrContextValue::Description(
+                    "'}}' for a literal '}'",
+                )))
+                .parse_next(input);
+        } else {
+            let text = take_till(1.., ('{', '}')).parse_next(input)?;
+            push_literal(&mut parts, text);
+        }
+    }
+    Ok(parts)
+}
+
+/// Parser for a placeholder of a template: `{[<selection>][|<separator>][%<spec>]}`
+fn placeholder(input: &mut &str) -> ModalResult<FormatPart> {
+    '{'.parse_next(input)?;
+    let selection = opt(selection).parse_next(input)?;
+    let separator = opt(preceded('|', take_till(0.., ('%', '}')))).parse_next(input)?;
+    let spec = opt(format_spec).parse_next(input)?;
+    cut_err('}')
+        .context(StrContext::Expected(StrContextValue::Description(
+            "'}' closing the placeholder",
+        )))
+        .parse_next(input)?;
+    Ok(FormatPart::Field {
+        selection,
+        separator: separator.map(str::to_string),
+        spec,
+    })
+}
+
+/// The largest width or precision a format spec may give.
+const MAX_FORMAT_SIZE: usize = 1000;
+
+/// Parser for a printf-style spec: `%[-+0][<width>][.<precision>]<conversion>`
+fn format_spec(input: &mut &str) -> ModalResult<FormatSpec> {
+    '%'.parse_next(input)?;
+    let flags = take_while(0.., ['-', '+', '0']).parse_next(input)?;
+    let width = opt(preceded(
+        peek(digit1),
+        cut_err(format_size).context(StrContext::Expected(StrContextValue::Description(
+            "<width> of at most 1000",
+        ))),
+    ))
+    .parse_next(input)?;
+    let precision = opt(preceded(
+        '.',
+        cut_err(format_size).context(StrContext::Expected(StrContextValue::Description(
+            "<precision> of at most 1000",
+        ))),
+    ))
+    .parse_next(input)?;
+    let letter = cut_err(one_of(|c| {
+        Conversion::ALL.iter().any(|conv| conv.letter() == c)
+    }))
+    .context(StrContext::Expected(StrContextValue::Description(
+        "conversion (s, d, f, e, x)",
+    )))
+    .parse_next(input)?;
+    let conversion = Conversion::ALL
+        .into_iter()
+        .find(|conv| conv.letter() == letter)
+        .expect("the letter is of a conversion");
+    Ok(FormatSpec {
+        left: flags.contains('-'),
+        sign: flags.contains('+'),
+        zero: flags.contains('0'),
+        width,
+        precision,
+        conversion,
+    })
+}
+
+/// Parser for the width or precision of a format spec.
+fn format_size(input: &mut &str) -> ModalResult<usize> {
+    digit1
+        .try_map(str::parse)
+        .verify(|size| *size <= MAX_FORMAT_SIZE)
+        .parse_next(input)
+}
+
+/// Parser for the element-wise operators applied to a selection: `L`, `U`, `N`
+/// and `T`.
+fn selected_op(input: &mut &str) -> ModalResult<Operator> {
+    alt((
+        lowercase_selected_op,
+        uppercase_selected_op,
+        to_number_selected_op,
+        trim_selected_op,
+    ))
+    .parse_next(input)
+}
+
 /// Parser for lowercase selected operator: `L<selection>`
 fn lowercase_selected_op(input: &mut &str) -> ModalResult<Operator> {
     'L'.parse_next(input)?;
@@ -371,29 +967,33 @@ fn validate_regex<'a>(
     pattern: &str,
     pattern_len: usize,
 ) -> impl FnMut(&mut &'a str) -> ModalResult<()> + use<'a> {
-    let err_info = Regex::new(pattern).err().map(|e| {
-        let err_str = e.to_string();
-        let lines: Vec<&str> = err_str.lines().collect();
-
-        // Extract error offset from caret line (line 2, after 4-space indent)
-        let offset_in_pattern = lines
-            .get(2)
-            .and_then(|caret_line| caret_line.find('^'))
-            .map(|pos| pos.saturating_sub(4))
-            .unwrap_or(0);
-
-        // Extract just the core error message (last line starting with "error:")
-        let msg = lines
-            .iter()
-            .find(|line| line.starts_with("error:"))
-            .and_then(|line| line.strip_prefix("error: "))
-            .unwrap_or("invalid pattern");
-
-        // Encode the backtrack amount in the message for parse_programme to extract
-        // Format: "invalid regex@BACKTRACK: message"
-        let backtrack = pattern_len.saturating_sub(offset_in_pattern);
-        format!("invalid regex@{}: {}", backtrack, msg)
-    });
+    // A pattern with variable references is checked once they are substituted
+    let err_info = (!has_references(pattern))
+        .then(|| Regex::new(pattern).err())
+        .flatten()
+        .map(|e| {
+            let err_str = e.to_string();
+            let lines: Vec<&str> = err_str.lines().collect();
+
+            // Extract error offset from caret line (line 2, after 4-space indent)
+            let offset_in_pattern = lines
+                .get(2)
+                .and_then(|caret_line| caret_line.find('^'))
+                .map(|pos| pos.saturating_sub(4))
+                .unwrap_or(0);
+
+            // Extract just the core error message (last line starting with "error:")
+            let msg = lines
+                .iter()
+                .find(|line| line.starts_with("error:"))
+                .and_then(|line| line.strip_prefix("error: "))
+                .unwrap_or("invalid pattern");
+
+            // Encode the backtrack amount in the message for parse_programme to extract
+            // Format: "invalid regex@BACKTRACK: message"
+            let backtrack = pattern_len.saturating_sub(offset_in_pattern);
+            format!("invalid regex@{}: {}", backtrack, msg)
+        });
     move |input: &mut &'a str| {
         if let Some(ref msg) = err_info {
             cut_err(winnow::combinator::fail)
@@ -437,117 +1037,752 @@ fn filter_op(input: &mut &str) -> ModalResult<Operator> {
     Ok(Operator::Filter { pattern, negate })
 }
 
-/// Parser for group by operator: `g<selection>`
-fn group_by_op(input: &mut &str) -> ModalResult<Operator> {
-    'g'.parse_next(input)?;
-    let sel = cut_err(selection)
-        .context(StrContext::Expected(StrContextValue::Description(
-            "<selection>",
+/// Parser for time operators: `kd<from>,<to>`, or `k[<selection>]` and then
+/// `p["<pattern>"]`, `/<bucket>` or `f[<offset>]"<pattern>"`
+fn time_op(input: &mut &str) -> ModalResult<Operator> {
+    'k'.parse_next(input)?;
+    let duration = preceded(
+        'd',
+        cut_err((index, ',', index)).context(StrContext::Expected(StrContextValue::Description(
+            "<from>,<to>",
+        ))),
+    )
+    .map(|(from, _, to)| Operator::Duration { from, to });
+    let time = (
+        opt(selection),
+        cut_err(alt((
+            preceded('p', opt(time_pattern)).map(TimeOp::Parse),
+            preceded(
+                '/',
+                cut_err(bucket).context(StrContext::Expected(StrContextValue::Description(
+                    "bucket (<n>s, <n>m, <n>h or <n>d)",
+                ))),
+            )
+            .map(TimeOp::Truncate),
+            preceded(
+                'f',
+                (
+                    opt(utc_offset),
+                    cut_err(time_pattern).context(StrContext::Expected(
+                        StrContextValue::Description("\"<pattern>\""),
+                    )),
+                ),
+            )
+            .map(|(offset, pattern)| TimeOp::Format { offset, pattern }),
         )))
-        .parse_next(input)?;
-    Ok(Operator::GroupBy(sel))
-}
-
-/// Parser for dedupe by selection with counts: `D<selection>`
-fn dedupe_selection_op(input: &mut &str) -> ModalResult<Operator> {
-    'D'.parse_next(input)?;
-    let sel = cut_err(selection)
         .context(StrContext::Expected(StrContextValue::Description(
-            "<selection>",
-        )))
-        .parse_next(input)?;
-    Ok(Operator::DedupeSelectionWithCounts(sel))
+            "time operation (p, /<bucket>, f\"<pattern>\" or d<from>,<to>)",
+        ))),
+    )
+        .map(|(selection, op)| Operator::Time { selection, op });
+    alt((duration, time)).parse_next(input)
 }
 
-/// Parser for selection operator (indices, slices, multi-select).
-fn selection_op(input: &mut &str) -> ModalResult<Operator> {
-    selection.map(Operator::Selection).parse_next(input)
+/// Parser for a strftime pattern as a quoted string, e.g. `"%Y-%m-%d"`.
+fn time_pattern(input: &mut &str) -> ModalResult<String> {
+    let start = *input;
+    let pattern = quoted_string(input)?;
+    if StrftimeItems::new(&pattern).any(|item| item == Item::Error) {
+        *input = start;
+        return cut_err(winnow::combinator::fail)
+            .context(StrContext::Expected(StrContextValue::Description(
+                "valid strftime pattern",
+            )))
+            .parse_next(input);
+    }
+    Ok(pattern)
 }
 
-/// Parser for a selection (comma-separated list of select items).
-fn selection(input: &mut &str) -> ModalResult<Selection> {
-    separated(1.., select_item, ',')
-        .map(|items| Selection { items })
-        .parse_next(input)
+/// Parser for a bucket: `[<count>]<unit>`, e.g. `m` or `15m`.
+fn bucket(input: &mut &str) -> ModalResult<Bucket> {
+    let count = opt(digit1.try_map(str::parse::<u32>).verify(|n| *n > 0)).parse_next(input)?;
+    let letter =
+        one_of(|c| TimeUnit::ALL.iter().any(|unit| unit.letter() == c)).parse_next(input)?;
+    let unit = TimeUnit::ALL
+        .into_iter()
+        .find(|unit| unit.letter() == letter)
+        .expect("the letter is of a unit");
+    Ok(Bucket {
+        count: count.unwrap_or(1),
+        unit,
+    })
 }
 
-/// Parser for a single select item (either a slice or an index).
-fn select_item(input: &mut &str) -> ModalResult<SelectItem> {
-    alt((slice_item, index_item)).parse_next(input)
+/// Parser for a fixed UTC offset, `+HH:MM` or `-HH:MM`, in seconds.
+fn utc_offset(input: &mut &str) -> ModalResult<i32> {
+    let sign = one_of(['+', '-']).parse_next(input)?;
+    let two_digits = || take_while(2, |c: char| c.is_ascii_digit()).try_map(str::parse::<i32>);
+    let (hours, _, minutes) = cut_err((
+        two_digits().verify(|h| *h < 24),
+        ':',
+        two_digits().verify(|m| *m < 60),
+    ))
+    .context(StrContext::Expected(StrContextValue::Description(
+        "offset (+HH:MM or -HH:MM)",
+    )))
+    .parse_next(input)?;
+    let seconds = (hours * 60 + minutes) * 60;
+    Ok(if sign == '-' { -seconds } else { seconds })
 }
 
-/// Parser for a slice (must contain at least one ':').
-fn slice_item(input: &mut &str) -> ModalResult<SelectItem> {
-    let start = opt(index).parse_next(input)?;
-    ':'.parse_next(input)?;
-    let end = opt(index).parse_next(input)?;
-    let step = opt((':'.value(()), index).map(|(_, i)| i)).parse_next(input)?;
+/// Parser for arithmetic operator: `={<expression>}`, or `=<expression>`
+/// without spaces
+fn arithmetic_op(input: &mut &str) -> ModalResult<Operator> {
+    '='.parse_next(input)?;
+    let braced = opt('{').parse_next(input)?.is_some();
+    let expr = cut_err(|i: &mut &str| {
+        if braced {
+            delimited(multispace0, |i: &mut &str| expr(i, true), multispace0).parse_next(i)
+        } else {
+            expr(i, false)
+        }
+    })
+    .context(StrContext::Expected(StrContextValue::Description(
+        "<expression>",
+    )))
+    .parse_next(input)?;
+    if braced {
+        cut_err('}')
+            .context(StrContext::Expected(StrContextValue::Description(
+                "operator or closing '}'",
+            )))
+            .parse_next(input)?;
+    }
+    Ok(Operator::Arithmetic(expr))
+}
 
-    Ok(SelectItem::Slice(Slice { start, end, step }))
+/// Parser for an arithmetic expression: a sum of terms. With `spaced`, as
+/// inside braces, spaces may separate tokens and an operator must be followed
+/// by an operand; without, the expression ends before anything that doesn't
+/// continue it, so `=$1+` is followed by `+`.
+fn expr(input: &mut &str, spaced: bool) -> ModalResult<Expr> {
+    binary_chain(input, spaced, additive_op, term)
 }
 
-/// Parser for a single index (returns as SelectItem).
-fn index_item(input: &mut &str) -> ModalResult<SelectItem> {
-    index.map(SelectItem::Index).parse_next(input)
+/// Parser for a product of factors.
+fn term(input: &mut &str, spaced: bool) -> ModalResult<Expr> {
+    binary_chain(input, spaced, multiplicative_op, factor)
 }
 
-/// Parser for an integer index (possibly negative).
-fn index(input: &mut &str) -> ModalResult<i64> {
-    (opt('-'), digit1)
-        .map(|(neg, digits): (Option<char>, &str)| {
-            let value: i64 = digits.parse().expect("digit1 guarantees valid digits");
-            if neg.is_some() { -value } else { value }
-        })
-        .parse_next(input)
+fn additive_op(input: &mut &str) -> ModalResult<BinaryOp> {
+    alt(('+'.value(BinaryOp::Add), '-'.value(BinaryOp::Subtract))).parse_next(input)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::ast::{Operator, SelectItem, Selection, Slice};
+fn multiplicative_op(input: &mut &str) -> ModalResult<BinaryOp> {
+    alt((
+        // Not the first `*` of a power
+        ('*', peek(winnow::combinator::not('*'))).value(BinaryOp::Multiply),
+        '/'.value(BinaryOp::Divide),
+        '%'.value(BinaryOp::Remainder),
+    ))
+    .parse_next(input)
+}
 
-    #[test]
-    fn empty_programme() {
-        let result = parse_programme("").unwrap();
-        assert_eq!(result.operators, vec![]);
+/// Parse `operand (op operand)*`, combining left to right.
+fn binary_chain(
+    input: &mut &str,
+    spaced: bool,
+    op: fn(&mut &str) -> ModalResult<BinaryOp>,
+    operand: fn(&mut &str, bool) -> ModalResult<Expr>,
+) -> ModalResult<Expr> {
+    let mut left = operand(input, spaced)?;
+    loop {
+        let checkpoint = *input;
+        if spaced {
+            multispace0.parse_next(input)?;
+        }
+        let Some(op) = opt(op).parse_next(input)? else {
+            *input = checkpoint;
+            return Ok(left);
+        };
+        let right = if spaced {
+            cut_err(preceded(multispace0, |i: &mut &str| operand(i, spaced)))
+                .context(StrContext::Expected(StrContextValue::Description(
+                    "<operand>",
+                )))
+                .parse_next(input)?
+        } else {
+            match opt(|i: &mut &str| operand(i, spaced)).parse_next(input)? {
+                Some(right) => right,
+                None => {
+                    *input = checkpoint;
+                    return Ok(left);
+                }
+            }
+        };
+        left = Expr::Binary(op, Box::new(left), Box::new(right));
     }
+}
 
-    #[test]
-    fn simple_operators() {
-        let result = parse_programme("sjul").unwrap();
-        assert_eq!(
-            result.operators,
-            vec![
-                Operator::Split,
-                Operator::Join,
-                Operator::Uppercase,
-                Operator::Lowercase,
-            ]
-        );
+/// Parser for a factor: a negation or a power.
+fn factor(input: &mut &str, spaced: bool) -> ModalResult<Expr> {
+    if opt('-').parse_next(input)?.is_some() {
+        if spaced {
+            multispace0.parse_next(input)?;
+        }
+        return factor(input, spaced).map(|operand| Expr::Negate(Box::new(operand)));
     }
-
-    #[test]
-    fn descend_ascend() {
-        let result = parse_programme("@^").unwrap();
-        assert_eq!(result.operators, vec![Operator::Descend, Operator::Ascend,]);
+    let base = atom(input)?;
+    let checkpoint = *input;
+    if spaced {
+        multispace0.parse_next(input)?;
     }
+    if opt("**").parse_next(input)?.is_none() {
+        *input = checkpoint;
+        return Ok(base);
+    }
+    // `**` is right-associative and its exponent may be negated
+    let exponent = if spaced {
+        cut_err(preceded(multispace0, |i: &mut &str| factor(i, spaced)))
+            .context(StrContext::Expected(StrContextValue::Description(
+                "<operand>",
+            )))
+            .parse_next(input)?
+    } else {
+        match opt(|i: &mut &str| factor(i, spaced)).parse_next(input)? {
+            Some(exponent) => exponent,
+            None => {
+                *input = checkpoint;
+                return Ok(base);
+            }
+        }
+    };
+    Ok(Expr::Binary(
+        BinaryOp::Power,
+        Box::new(base),
+        Box::new(exponent),
+    ))
+}
 
-    #[test]
-    fn single_index() {
-        let result = parse_programme("0").unwrap();
-        assert_eq!(
-            result.operators,
-            vec![Operator::Selection(Selection {
-                items: vec![SelectItem::Index(0)]
-            })]
-        );
+/// Parser for an atom: a number, a field, a function call or a parenthesised
+/// expression.
+fn atom(input: &mut &str) -> ModalResult<Expr> {
+    if opt('(').parse_next(input)?.is_some() {
+        let inner = cut_err(delimited(
+            multispace0,
+            |i: &mut &str| expr(i, true),
+            multispace0,
+        ))
+        .context(StrContext::Expected(StrContextValue::Description(
+            "<expression>",
+        )))
+        .parse_next(input)?;
+        cut_err(')')
+            .context(StrContext::Expected(StrContextValue::Description(
+                "operator or closing ')'",
+            )))
+            .parse_next(input)?;
+        return Ok(inner);
     }
+    if opt('$').parse_next(input)?.is_some() {
+        return opt(index).map(Expr::Field).parse_next(input);
+    }
+    if let Some(number) = opt((digit1, opt(('.', digit1)))
+        .take()
+        .try_map(str::parse::<f64>))
+    .parse_next(input)?
+    {
+        return Ok(Expr::Number(number));
+    }
+    call(input)
+}
 
-    #[test]
-    fn negative_index() {
-        let result = parse_programme("-1").unwrap();
-        assert_eq!(
-            result.operators,
+/// Parser for a function call: `<name>(<expr>, ...)`. Spaces are allowed
+/// within the parentheses, as they can't be confused with what follows.
+fn call(input: &mut &str) -> ModalResult<Expr> {
+    let start = *input;
+    let name = (
+        take_while(1.., |c: char| c.is_ascii_lowercase()),
+        take_while(0.., |c: char| c.is_ascii_alphanumeric()),
+        peek('('),
+    )
+        .take()
+        .parse_next(input)?;
+    let Some(function) = Function::ALL.into_iter().find(|f| f.name() == name) else {
+        *input = start;
+        return cut_err(winnow::combinator::fail)
+            .context(StrContext::Expected(StrContextValue::Description(
+                "function (abs, ceil, floor, round, sqrt, exp, log, log2 or log10)",
+            )))
+            .parse_next(input);
+    };
+    '('.parse_next(input)?;
+    let args: Vec<Expr> = cut_err(separated(
+        1..,
+        delimited(multispace0, |i: &mut &str| expr(i, true), multispace0),
+        ',',
+    ))
+    .context(StrContext::Expected(StrContextValue::Description(
+        "<expression>",
+    )))
+    .parse_next(input)?;
+    if !function.arity().contains(&args.len()) {
+        let expected = match function.arity().end() {
+            1 => format!("one argument to {}", function.name()),
+            _ => format!("one or two arguments to {}", function.name()),
+        };
+        return cut_err(winnow::combinator::fail)
+            .context(StrContext::Label(Box::leak(expected.into_boxed_str())))
+            .parse_next(input);
+    }
+    cut_err(')')
+        .context(StrContext::Expected(StrContextValue::Description(
+            "',' or closing ')'",
+        )))
+        .parse_next(input)?;
+    Ok(Expr::Call(function, args))
+}
+
+/// Parser for shell command operator: `!{<command>}`, or `!|{<command>}` to
+/// run the command once for all elements
+fn command_op(input: &mut &str) -> ModalResult<Operator> {
+    let (_, batched, _) = ('!', opt('|'), '{').parse_next(input)?;
+    let command = cut_err(command_text)
+        .context(StrContext::Expected(StrContextValue::Description(
+            "<command>",
+        )))
+        .parse_next(input)?;
+    cut_err('}')
+        .context(StrContext::Expected(StrContextValue::Description(
+            "closing '}'",
+        )))
+        .parse_next(input)?;
+    Ok(Operator::Command {
+        command: command.to_string(),
+        batched: batched.is_some(),
+    })
+}
+
+/// Parser for the text of a shell command: anything with balanced braces, up
+/// to the `}` closing the operator.
+fn command_text<'a>(input: &mut &'a str) -> ModalResult<&'a str> {
+    let mut depth = 0;
+    let end = input
+        .char_indices()
+        .find(|&(_, c)| {
+            match c {
+                '{' => depth += 1,
+                '}' if depth == 0 => return true,
+                '}' => depth -= 1,
+                _ => {}
+            }
+            false
+        })
+        .map_or(input.len(), |(i, _)| i);
+    if end == 0 {
+        return winnow::combinator::fail.parse_next(input);
+    }
+    let (command, rest) = input.split_at(end);
+    *input = rest;
+    Ok(command)
+}
+
+/// Parser for group by operator: `g<selection>` or `g{<programme>}`,
+/// optionally followed by aggregates: `g<key>{<aggregate>, ...}`
+fn group_by_op(input: &mut &str) -> ModalResult<Operator> {
+    'g'.parse_next(input)?;
+    let key = cut_err(key)
+        .context(StrContext::Expected(StrContextValue::Description(
+            "<selection> or '{'",
+        )))
+        .parse_next(input)?;
+    match opt(aggregates).parse_next(input)? {
+        Some(aggregates) => Ok(Operator::GroupAggregate { key, aggregates }),
+        None => Ok(Operator::GroupBy(key)),
+    }
+}
+
+/// Parser for a braced, comma-separated list of aggregates: `{#, +9, max 3}`
+///
+/// Backtracks unless the braces hold a lone aggregate or open with an
+/// aggregate and a comma, so `g0{j}` is a group followed by a block.
+fn aggregates(input: &mut &str) -> ModalResult<Vec<Aggregate>> {
+    '{'.parse_next(input)?;
+    let mut aggregates = vec![delimited(ignored, aggregate, ignored).parse_next(input)?];
+    if opt(',').parse_next(input)?.is_none() {
+        '}'.parse_next(input)?;
+        return Ok(aggregates);
+    }
+    loop {
+        let aggregate = cut_err(delimited(ignored, aggregate, ignored))
+            .context(StrContext::Expected(StrContextValue::Description(
+                "aggregate (#, +, min, max or mean)",
+            )))
+            .parse_next(input)?;
+        aggregates.push(aggregate);
+        if opt(',').parse_next(input)?.is_none() {
+            break;
+        }
+    }
+    cut_err('}')
+        .context(StrContext::Expected(StrContextValue::Description(
+            "',' or closing '}'",
+        )))
+        .parse_next(input)?;
+    Ok(aggregates)
+}
+
+/// Parser for a single aggregate, with an optional field index.
+fn aggregate(input: &mut &str) -> ModalResult<Aggregate> {
+    if opt('#').parse_next(input)?.is_some() {
+        return Ok(Aggregate::Count);
+    }
+    let make: fn(Option<i64>) -> Aggregate = alt((
+        "+".value(Aggregate::Sum as fn(_) -> _),
+        "min".value(Aggregate::Min as fn(_) -> _),
+        "max".value(Aggregate::Max as fn(_) -> _),
+        "mean".value(Aggregate::Mean as fn(_) -> _),
+    ))
+    .parse_next(input)?;
+    let field = opt(preceded(ignored, index)).parse_next(input)?;
+    Ok(make(field))
+}
+
+/// Parser for dedupe by key with counts: `D<selection>` or `D{<programme>}`
+fn dedupe_selection_op(input: &mut &str) -> ModalResult<Operator> {
+    'D'.parse_next(input)?;
+    let key = cut_err(key)
+        .context(StrContext::Expected(StrContextValue::Description(
+            "<selection> or '{'",
+        )))
+        .parse_next(input)?;
+    Ok(Operator::DedupeSelectionWithCounts(key))
+}
+
+/// Parser for a key: a selection, or a programme computing the key.
+fn key(input: &mut &str) -> ModalResult<Key> {
+    alt((
+        block_body.map(Key::Programme),
+        selection.map(Key::Selection),
+    ))
+    .parse_next(input)
+}
+
+/// Parser for sort by key: `o{<programme>}` or `O{<programme>}`
+fn sort_by_op(input: &mut &str) -> ModalResult<Operator> {
+    let descending = one_of(('o', 'O')).parse_next(input)? == 'o';
+    let key = block_body.parse_next(input)?;
+    Ok(if descending {
+        Operator::SortDescendingBy(key)
+    } else {
+        Operator::SortAscendingBy(key)
+    })
+}
+
+/// Parser for a block: `{<programme>}`
+fn block_op(input: &mut &str) -> ModalResult<Operator> {
+    block_body.map(Operator::Block).parse_next(input)
+}
+
+/// Parser for a braced programme: `{<programme>}`
+fn block_body(input: &mut &str) -> ModalResult<Programme> {
+    '{'.parse_next(input)?;
+    let operators = spanned_operators(input.len()).parse_next(input)?;
+    cut_err('}')
+        .context(StrContext::Expected(StrContextValue::Description(
+            "closing '}'",
+        )))
+        .parse_next(input)?;
+    Ok(Programme {
+        operators: operators.into_iter().map(|(_, op)| op).collect(),
+    })
+}
+
+/// Parser for a conditional: `?[<selection>]/<regex>/{<then>}[{<else>}]`
+fn conditional_op(input: &mut &str) -> ModalResult<Operator> {
+    let (selection, pattern) = conditional_header.parse_next(input)?;
+    let then = cut_err(block_body)
+        .context(StrContext::Expected(StrContextValue::Description("'{'")))
+        .parse_next(input)?;
+    let otherwise = opt(block_body).parse_next(input)?;
+    Ok(Operator::Conditional {
+        selection,
+        pattern,
+        then,
+        otherwise,
+    })
+}
+
+/// Parser for the predicate of a conditional: `?[<selection>]/<regex>/`
+fn conditional_header(input: &mut &str) -> ModalResult<(Option<Selection>, String)> {
+    '?'.parse_next(input)?;
+    let sel = opt(selection).parse_next(input)?;
+    cut_err('/')
+        .context(StrContext::Expected(StrContextValue::Description("'/'")))
+        .parse_next(input)?;
+    let before = input.len();
+    let pattern = cut_err(|i: &mut &str| slash_delimited_pattern(i, true))
+        .context(StrContext::Expected(StrContextValue::Description(
+            "<pattern>",
+        )))
+        .parse_next(input)?;
+    let pattern_len = before - input.len();
+    validate_regex(&pattern, pattern_len).parse_next(input)?;
+    cut_err('/')
+        .context(StrContext::Expected(StrContextValue::Description(
+            "closing '/'",
+        )))
+        .parse_next(input)?;
+    Ok((sel, pattern))
+}
+
+/// Parser for a fork: `(<programme>|<programme>...)`
+fn fork_op(input: &mut &str) -> ModalResult<Operator> {
+    '('.parse_next(input)?;
+    let branches: Vec<Vec<(usize, Operator)>> =
+        separated(1.., spanned_operators(input.len()), '|').parse_next(input)?;
+    cut_err(')')
+        .context(StrContext::Expected(StrContextValue::Description(
+            "'|' or closing ')'",
+        )))
+        .parse_next(input)?;
+    Ok(Operator::Fork(
+        branches
+            .into_iter()
+            .map(|branch| Programme {
+                operators: branch.into_iter().map(|(_, op)| op).collect(),
+            })
+            .collect(),
+    ))
+}
+
+/// Parser for macro invocation: `&<name>`
+fn macro_op(input: &mut &str) -> ModalResult<Operator> {
+    '&'.parse_next(input)?;
+    let name = cut_err(macro_name)
+        .context(StrContext::Expected(StrContextValue::Description(
+            "<macro name>",
+        )))
+        .parse_next(input)?;
+    Ok(Operator::Macro(name.to_string()))
+}
+
+/// Parser for operations with a named input: joins such as `*users:0=2`,
+/// `*?users:0` or `*!users:0`, where `⋈` may be written for `*`, set
+/// operations such as `*-users` or `*&users:0`, and pattern list filters such
+/// as `*/noise` or `*!~/noise:1`.
+fn input_op(input: &mut &str) -> ModalResult<Operator> {
+    let join = alt(('*', '⋈')).parse_next(input)? == '⋈';
+    if !join && let Some(filter) = opt(pattern_filter).parse_next(input)? {
+        return Ok(filter);
+    }
+    if !join && let Some(map) = opt(map).parse_next(input)? {
+        return Ok(map);
+    }
+    let set_operation = if join {
+        None
+    } else {
+        opt(alt((
+            '|'.value(SetOperation::Union),
+            '&'.value(SetOperation::Intersection),
+            '-'.value(SetOperation::Difference),
+            '^'.value(SetOperation::SymmetricDifference),
+        )))
+        .parse_next(input)?
+    };
+    let kind = match set_operation {
+        Some(_) => JoinKind::Inner,
+        None => opt(alt(('?'.value(JoinKind::Left), '!'.value(JoinKind::Anti))))
+            .map(|kind| kind.unwrap_or(JoinKind::Inner))
+            .parse_next(input)?,
+    };
+    let name = cut_err(macro_name)
+        .context(StrContext::Expected(StrContextValue::Description(
+            "<input name>",
+        )))
+        .parse_next(input)?
+        .to_string();
+    if let Some(operation) = set_operation {
+        let key = opt(preceded(':', cut_err(input_key))).parse_next(input)?;
+        return Ok(Operator::SetInput {
+            input: name,
+            operation,
+            key,
+        });
+    }
+    let (left, right) = cut_err(preceded(':', input_key))
+        .context(StrContext::Expected(StrContextValue::Description(
+            "':' and key <selection>",
+        )))
+        .parse_next(input)?;
+    Ok(Operator::JoinInput {
+        input: name,
+        kind,
+        left,
+        right,
+    })
+}
+
+/// Parser for the rest of a pattern list filter after the `*`: `[!][~]/<input>[:<selection>]`
+fn pattern_filter(input: &mut &str) -> ModalResult<Operator> {
+    let (negate, regex, _) = (opt('!'), opt('~'), '/').parse_next(input)?;
+    let name = cut_err(macro_name)
+        .context(StrContext::Expected(StrContextValue::Description(
+            "<input name>",
+        )))
+        .parse_next(input)?;
+    let selection = opt(preceded(
+        ':',
+        cut_err(selection).context(StrContext::Expected(StrContextValue::Description(
+            "<selection>",
+        ))),
+    ))
+    .parse_next(input)?;
+    Ok(Operator::PatternFilter {
+        input: name.to_string(),
+        regex: regex.is_some(),
+        negate: negate.is_some(),
+        selection,
+    })
+}
+
+/// Parser for the rest of a dictionary lookup after the `*`:
+/// `=[!]<input>[:<selection>]["<default>"]`, with no default when dropping
+fn map(input: &mut &str) -> ModalResult<Operator> {
+    let (_, drop) = ('=', opt('!')).parse_next(input)?;
+    let name = cut_err(macro_name)
+        .context(StrContext::Expected(StrContextValue::Description(
+            "<input name>",
+        )))
+        .parse_next(input)?;
+    let selection = opt(preceded(
+        ':',
+        cut_err(selection).context(StrContext::Expected(StrContextValue::Description(
+            "<selection>",
+        ))),
+    ))
+    .parse_next(input)?;
+    let unmapped = if drop.is_some() {
+        Unmapped::Drop
+    } else {
+        opt(quoted_string)
+            .parse_next(input)?
+            .map_or(Unmapped::Keep, Unmapped::Default)
+    };
+    Ok(Operator::Map {
+        input: name.to_string(),
+        selection,
+        unmapped,
+    })
+}
+
+/// Parser for the keys of an operation with a named input: `<selection>`, or
+/// `<selection>=<selection>` where the input's key differs.
+fn input_key(input: &mut &str) -> ModalResult<(Selection, Selection)> {
+    let left = selection
+        .context(StrContext::Expected(StrContextValue::Description(
+            "key <selection>",
+        )))
+        .parse_next(input)?;
+    let right = opt(preceded(
+        '=',
+        cut_err(selection).context(StrContext::Expected(StrContextValue::Description(
+            "<selection>",
+        ))),
+    ))
+    .parse_next(input)?;
+    Ok((left.clone(), right.unwrap_or(left)))
+}
+
+/// Parser for a macro name: a letter or `_`, then letters, digits or `_`.
+pub fn macro_name<'a>(input: &mut &'a str) -> ModalResult<&'a str> {
+    (
+        one_of(|c: char| c.is_ascii_alphabetic() || c == '_'),
+        take_while(0.., |c: char| c.is_ascii_alphanumeric() || c == '_'),
+    )
+        .take()
+        .parse_next(input)
+}
+
+/// Parser for selection operator (indices, slices, multi-select).
+fn selection_op(input: &mut &str) -> ModalResult<Operator> {
+    selection.map(Operator::Selection).parse_next(input)
+}
+
+/// Parser for a selection (comma-separated list of select items).
+fn selection(input: &mut &str) -> ModalResult<Selection> {
+    separated(1.., select_item, ',')
+        .map(|items| Selection { items })
+        .parse_next(input)
+}
+
+/// Parser for a single select item (either a slice or an index).
+fn select_item(input: &mut &str) -> ModalResult<SelectItem> {
+    alt((slice_item, index_item)).parse_next(input)
+}
+
+/// Parser for a slice (must contain at least one ':').
+fn slice_item(input: &mut &str) -> ModalResult<SelectItem> {
+    let start = opt(index).parse_next(input)?;
+    ':'.parse_next(input)?;
+    let end = opt(index).parse_next(input)?;
+    let step = opt((':'.value(()), index).map(|(_, i)| i)).parse_next(input)?;
+
+    Ok(SelectItem::Slice(Slice { start, end, step }))
+}
+
+/// Parser for a single index (returns as SelectItem).
+fn index_item(input: &mut &str) -> ModalResult<SelectItem> {
+    index.map(SelectItem::Index).parse_next(input)
+}
+
+/// Parser for an integer index (possibly negative).
+fn index(input: &mut &str) -> ModalResult<i64> {
+    (opt('-'), digit1)
+        .map(|(neg, digits): (Option<char>, &str)| {
+            let value: i64 = digits.parse().expect("digit1 guarantees valid digits");
+            if neg.is_some() { -value } else { value }
+        })
+        .parse_next(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Operator, SelectItem, Selection, Slice};
+
+    #[test]
+    fn empty_programme() {
+        let result = parse_programme("").unwrap();
+        assert_eq!(result.operators, vec![]);
+    }
+
+    #[test]
+    fn simple_operators() {
+        let result = parse_programme("sjul").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![
+                Operator::Split,
+                Operator::Join,
+                Operator::Uppercase,
+                Operator::Lowercase,
+            ]
+        );
+    }
+
+    #[test]
+    fn descend_ascend() {
+        let result = parse_programme("@^").unwrap();
+        assert_eq!(result.operators, vec![Operator::Descend, Operator::Ascend,]);
+    }
+
+    #[test]
+    fn single_index() {
+        let result = parse_programme("0").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![Operator::Selection(Selection {
+                items: vec![SelectItem::Index(0)]
+            })]
+        );
+    }
+
+    #[test]
+    fn negative_index() {
+        let result = parse_programme("-1").unwrap();
+        assert_eq!(
+            result.operators,
             vec![Operator::Selection(Selection {
                 items: vec![SelectItem::Index(-1)]
             })]
@@ -807,9 +2042,9 @@ mod tests {
         let result = parse_programme("g0").unwrap();
         assert_eq!(
             result.operators,
-            vec![Operator::GroupBy(Selection {
+            vec![Operator::GroupBy(Key::Selection(Selection {
                 items: vec![SelectItem::Index(0)]
-            })]
+            }))]
         );
     }
 
@@ -818,9 +2053,9 @@ mod tests {
         let result = parse_programme("g-1").unwrap();
         assert_eq!(
             result.operators,
-            vec![Operator::GroupBy(Selection {
+            vec![Operator::GroupBy(Key::Selection(Selection {
                 items: vec![SelectItem::Index(-1)]
-            })]
+            }))]
         );
     }
 
@@ -829,9 +2064,9 @@ mod tests {
         let result = parse_programme("g0,2").unwrap();
         assert_eq!(
             result.operators,
-            vec![Operator::GroupBy(Selection {
+            vec![Operator::GroupBy(Key::Selection(Selection {
                 items: vec![SelectItem::Index(0), SelectItem::Index(2)]
-            })]
+            }))]
         );
     }
 
@@ -840,13 +2075,13 @@ mod tests {
         let result = parse_programme("g0:3").unwrap();
         assert_eq!(
             result.operators,
-            vec![Operator::GroupBy(Selection {
+            vec![Operator::GroupBy(Key::Selection(Selection {
                 items: vec![SelectItem::Slice(Slice {
                     start: Some(0),
                     end: Some(3),
                     step: None,
                 })]
-            })]
+            }))]
         );
     }
 
@@ -857,9 +2092,9 @@ mod tests {
             result.operators,
             vec![
                 Operator::Split,
-                Operator::GroupBy(Selection {
+                Operator::GroupBy(Key::Selection(Selection {
                     items: vec![SelectItem::Index(0)]
-                }),
+                })),
                 Operator::SortDescending,
             ]
         );
@@ -870,7 +2105,7 @@ mod tests {
         let result = parse_programme("sg");
         assert_eq!(
             result,
-            Err("parse error: expected <selection>\n  sg\n    ^".to_string())
+            Err("parse error: expected <selection> or '{'\n  sg\n    ^".to_string())
         );
     }
 
@@ -1444,9 +2679,11 @@ mod tests {
         let result = parse_programme("D0").unwrap();
         assert_eq!(
             result.operators,
-            vec![Operator::DedupeSelectionWithCounts(Selection {
-                items: vec![SelectItem::Index(0)]
-            })]
+            vec![Operator::DedupeSelectionWithCounts(Key::Selection(
+                Selection {
+                    items: vec![SelectItem::Index(0)]
+                }
+            ))]
         );
     }
 
@@ -1457,9 +2694,9 @@ mod tests {
             result.operators,
             vec![
                 Operator::Split,
-                Operator::DedupeSelectionWithCounts(Selection {
+                Operator::DedupeSelectionWithCounts(Key::Selection(Selection {
                     items: vec![SelectItem::Index(0)]
-                }),
+                })),
                 Operator::SortAscending
             ]
         );
@@ -1487,15 +2724,36 @@ mod tests {
     }
 
     #[test]
-    fn partition_single_index() {
-        let result = parse_programme("p2").unwrap();
+    fn charts() {
+        let result = parse_programme("dh%+ h+ +H").unwrap();
         assert_eq!(
             result.operators,
-            vec![Operator::Partition(Selection {
-                items: vec![SelectItem::Index(2)]
-            })]
-        );
-    }
+            vec![
+                Operator::DedupeWithCounts,
+                Operator::Histogram {
+                    percent: true,
+                    cumulative: true,
+                },
+                Operator::Histogram {
+                    percent: false,
+                    cumulative: true,
+                },
+                Operator::Sum,
+                Operator::Sparkline,
+            ]
+        );
+    }
+
+    #[test]
+    fn partition_single_index() {
+        let result = parse_programme("p2").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![Operator::Partition(Selection {
+                items: vec![SelectItem::Index(2)]
+            })]
+        );
+    }
 
     #[test]
     fn partition_multiple_indices() {
@@ -1572,4 +2830,912 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn whitespace_between_operators() {
+        let result = parse_programme(" s @ 0 ^\n\td ").unwrap();
+        assert_eq!(result, parse_programme("s@0^d").unwrap());
+    }
+
+    #[test]
+    fn whitespace_separates_selections() {
+        let result = parse_programme("0 1").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![
+                Operator::Selection(Selection {
+                    items: vec![SelectItem::Index(0)]
+                }),
+                Operator::Selection(Selection {
+                    items: vec![SelectItem::Index(1)]
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn whitespace_inside_operator_is_significant() {
+        let result = parse_programme("S J\" \"").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![
+                Operator::SplitDelim(" ".to_string()),
+                Operator::JoinDelim(" ".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn line_comments() {
+        let source = "-- top words\ns f -- flatten\nl\n--\nd o :20 -- done";
+        let result = parse_programme(source).unwrap();
+        assert_eq!(result, parse_programme("sfldo:20").unwrap());
+    }
+
+    #[test]
+    fn comment_after_open_slice() {
+        let result = parse_programme("1:--rest").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![Operator::Selection(Selection {
+                items: vec![SelectItem::Slice(Slice {
+                    start: Some(1),
+                    end: None,
+                    step: None,
+                })]
+            })]
+        );
+    }
+
+    #[test]
+    fn multi_line_error_shows_line() {
+        let result = parse_programme("s\n  @0\n  g\n");
+        assert_eq!(
+            result,
+            Err("parse error: expected <selection> or '{' (at line 3)\n    g\n     ^".to_string())
+        );
+    }
+
+    #[test]
+    fn macro_left_unexpanded() {
+        let result = parse_programme("s&ips").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![Operator::Split, Operator::Macro("ips".to_string())]
+        );
+    }
+
+    #[test]
+    fn macro_missing_name_error() {
+        let result = parse_programme("s&");
+        assert_eq!(
+            result,
+            Err("parse error: expected <macro name>\n  s&\n    ^".to_string())
+        );
+    }
+
+    #[test]
+    fn macro_expansion() {
+        let macros = Macros::parse("first = @0^\ntop = &first do :10").unwrap();
+        let result = parse_programme_with_macros("s&top", &macros, &Variables::default()).unwrap();
+        assert_eq!(result, parse_programme("s@0^do:10").unwrap());
+    }
+
+    #[test]
+    fn variable_substitution() {
+        let mut variables = Variables::default();
+        variables.insert("path", "/a.b");
+        variables.insert("alt", "GET|PUT");
+        let result = parse_programme_with_macros(
+            r#"/{$path}/;!/{$!alt}/;r/x/{$path}$0/;J"{$path}";?/{$path}/{/{$alt}/}"#,
+            &Macros::default(),
+            &variables,
+        )
+        .unwrap();
+        let filter = |pattern: &str, negate| Operator::Filter {
+            pattern: pattern.to_string(),
+            negate,
+        };
+        assert_eq!(
+            result.operators,
+            vec![
+                filter(r"/a\.b", false),
+                Operator::NoOp,
+                filter("GET|PUT", true),
+                Operator::NoOp,
+                Operator::Replace {
+                    selection: None,
+                    pattern: "x".to_string(),
+                    replacement: "/a.b$0".to_string(),
+                },
+                Operator::NoOp,
+                Operator::JoinDelim("/a.b".to_string()),
+                Operator::NoOp,
+                Operator::Conditional {
+                    selection: None,
+                    pattern: r"/a\.b".to_string(),
+                    then: Programme {
+                        operators: vec![filter(r"GET\|PUT", false)],
+                    },
+                    otherwise: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn variable_unknown_error() {
+        let macros = Macros::parse("ips = m/{$ip}/").unwrap();
+        let result = parse_programme_with_macros("s&ips", &macros, &Variables::default());
+        assert_eq!(
+            result,
+            Err(
+                "parse error: unknown variable 'ip' (set one with --arg ip=<value>) (in macro &ips)\n  m/{$ip}/\n  ^\n  s&ips\n   ^"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn macro_unknown_error() {
+        let macros = Macros::default();
+        let result = parse_programme_with_macros("s&nope", &macros, &Variables::default());
+        assert_eq!(
+            result,
+            Err("parse error: unknown macro &nope\n  s&nope\n   ^".to_string())
+        );
+    }
+
+    #[test]
+    fn macro_error_points_into_body() {
+        let macros = Macros::parse("ips = m/\\d+").unwrap();
+        let result = parse_programme_with_macros("s&ips", &macros, &Variables::default());
+        assert_eq!(
+            result,
+            Err(
+                "parse error: expected closing '/' (in macro &ips)\n  m/\\d+\n       ^\n  s&ips\n   ^"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn macro_nested_error_points_at_each_invocation() {
+        let macros = Macros::parse("a = s&b\nb = g").unwrap();
+        let result = parse_programme_with_macros("l&a", &macros, &Variables::default());
+        assert_eq!(
+            result,
+            Err(
+                "parse error: expected <selection> or '{' (in macro &b) (in macro &a)\n  g\n   ^\n  s&b\n   ^\n  l&a\n   ^"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn macro_cycle_error() {
+        let macros = Macros::parse("a = s&b\nb = &a").unwrap();
+        let result = parse_programme_with_macros("&a", &macros, &Variables::default());
+        assert_eq!(
+            result,
+            Err(
+                "parse error: macro cycle: &a -> &b -> &a (in macro &b) (in macro &a)\n  &a\n  ^\n  s&b\n   ^\n  &a\n  ^"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn macro_self_reference_error() {
+        let macros = Macros::parse("a = s&a").unwrap();
+        let result = parse_programme_with_macros("&a", &macros, &Variables::default());
+        assert!(
+            result
+                .unwrap_err()
+                .starts_with("parse error: macro cycle: &a -> &a")
+        );
+    }
+
+    #[test]
+    fn block() {
+        let result = parse_programme("s{@u^j}0").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![
+                Operator::Split,
+                Operator::Block(Programme {
+                    operators: vec![
+                        Operator::Descend,
+                        Operator::Uppercase,
+                        Operator::Ascend,
+                        Operator::Join,
+                    ]
+                }),
+                Operator::Selection(Selection {
+                    items: vec![SelectItem::Index(0)]
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_blocks_with_whitespace() {
+        let result = parse_programme("{ s { 0 } -- first\n}").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![Operator::Block(Programme {
+                operators: vec![
+                    Operator::Split,
+                    Operator::Block(Programme {
+                        operators: vec![Operator::Selection(Selection {
+                            items: vec![SelectItem::Index(0)]
+                        })]
+                    }),
+                ]
+            })]
+        );
+    }
+
+    #[test]
+    fn empty_block() {
+        let result = parse_programme("{}").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![Operator::Block(Programme { operators: vec![] })]
+        );
+    }
+
+    #[test]
+    fn unclosed_block_error() {
+        let result = parse_programme("s{@u");
+        assert_eq!(
+            result,
+            Err("parse error: expected closing '}'\n  s{@u\n      ^".to_string())
+        );
+    }
+
+    #[test]
+    fn unopened_block_error() {
+        assert!(parse_programme("s}").is_err());
+    }
+
+    #[test]
+    fn macro_inside_block() {
+        let macros = Macros::parse("first = s0").unwrap();
+        let result =
+            parse_programme_with_macros("{&first}", &macros, &Variables::default()).unwrap();
+        assert_eq!(result, parse_programme("{s0}").unwrap());
+    }
+
+    #[test]
+    fn macro_inside_block_error_points_at_invocation() {
+        let macros = Macros::default();
+        let result = parse_programme_with_macros("s{u&nope}", &macros, &Variables::default());
+        assert_eq!(
+            result,
+            Err("parse error: unknown macro &nope\n  s{u&nope}\n     ^".to_string())
+        );
+    }
+
+    #[test]
+    fn fork() {
+        let result = parse_programme("(#|s@0^ d#)").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![Operator::Fork(vec![
+                Programme {
+                    operators: vec![Operator::Count]
+                },
+                Programme {
+                    operators: vec![
+                        Operator::Split,
+                        Operator::Descend,
+                        Operator::Selection(Selection {
+                            items: vec![SelectItem::Index(0)]
+                        }),
+                        Operator::Ascend,
+                        Operator::DedupeWithCounts,
+                        Operator::Count,
+                    ]
+                },
+            ])]
+        );
+    }
+
+    #[test]
+    fn fork_empty_branches() {
+        let result = parse_programme("(|)").unwrap();
+        let empty = Programme { operators: vec![] };
+        assert_eq!(
+            result.operators,
+            vec![Operator::Fork(vec![empty.clone(), empty])]
+        );
+    }
+
+    #[test]
+    fn unclosed_fork_error() {
+        let result = parse_programme("(#|s");
+        assert_eq!(
+            result,
+            Err("parse error: expected '|' or closing ')'\n  (#|s\n      ^".to_string())
+        );
+    }
+
+    #[test]
+    fn macro_inside_fork() {
+        let macros = Macros::parse("first = s@0^").unwrap();
+        let result =
+            parse_programme_with_macros("(#|{&first}|&first)", &macros, &Variables::default())
+                .unwrap();
+        assert_eq!(result, parse_programme("(#|{s@0^}|s@0^)").unwrap());
+    }
+
+    #[test]
+    fn conditional() {
+        let result = parse_programme("?/ERROR/{u}").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![Operator::Conditional {
+                selection: None,
+                pattern: "ERROR".to_string(),
+                then: Programme {
+                    operators: vec![Operator::Uppercase]
+                },
+                otherwise: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn conditional_with_selection_and_else() {
+        let result = parse_programme("?1/GET/{ r/a/b/ }{l}").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![Operator::Conditional {
+                selection: Some(Selection {
+                    items: vec![SelectItem::Index(1)]
+                }),
+                pattern: "GET".to_string(),
+                then: Programme {
+                    operators: vec![Operator::Replace {
+                        selection: None,
+                        pattern: "a".to_string(),
+                        replacement: "b".to_string(),
+                    }]
+                },
+                otherwise: Some(Programme {
+                    operators: vec![Operator::Lowercase]
+                }),
+            }]
+        );
+    }
+
+    #[test]
+    fn conditional_missing_then_error() {
+        let result = parse_programme("?/a/u");
+        assert_eq!(
+            result,
+            Err("parse error: expected '{'\n  ?/a/u\n      ^".to_string())
+        );
+    }
+
+    #[test]
+    fn conditional_invalid_regex_error() {
+        let result = parse_programme("?/(/{u}");
+        assert!(result.unwrap_err().contains("invalid regex"));
+    }
+
+    #[test]
+    fn macro_inside_conditional() {
+        let macros = Macros::parse("up = u").unwrap();
+        let result =
+            parse_programme_with_macros("?0/a/{&up}{&up;l}", &macros, &Variables::default())
+                .unwrap();
+        assert_eq!(result, parse_programme("?0/a/{u}{u;l}").unwrap());
+    }
+
+    #[test]
+    fn keyed_group_and_dedupe() {
+        let result = parse_programme("g{l}D{1}").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![
+                Operator::GroupBy(Key::Programme(Programme {
+                    operators: vec![Operator::Lowercase]
+                })),
+                Operator::DedupeSelectionWithCounts(Key::Programme(Programme {
+                    operators: vec![Operator::Selection(Selection {
+                        items: vec![SelectItem::Index(1)]
+                    })]
+                })),
+            ]
+        );
+    }
+
+    #[test]
+    fn keyed_sorts() {
+        let result = parse_programme("o{#}O{l}").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![
+                Operator::SortDescendingBy(Programme {
+                    operators: vec![Operator::Count]
+                }),
+                Operator::SortAscendingBy(Programme {
+                    operators: vec![Operator::Lowercase]
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn macro_inside_key() {
+        let macros = Macros::parse("low = l").unwrap();
+        let result =
+            parse_programme_with_macros("g{&low}o{&low}", &macros, &Variables::default()).unwrap();
+        assert_eq!(result, parse_programme("g{l}o{l}").unwrap());
+    }
+
+    #[test]
+    fn group_aggregates() {
+        let result = parse_programme("g0{#, +9, max 3,mean}").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![Operator::GroupAggregate {
+                key: Key::Selection(Selection {
+                    items: vec![SelectItem::Index(0)]
+                }),
+                aggregates: vec![
+                    Aggregate::Count,
+                    Aggregate::Sum(Some(9)),
+                    Aggregate::Max(Some(3)),
+                    Aggregate::Mean(None),
+                ],
+            }]
+        );
+    }
+
+    #[test]
+    fn group_aggregates_with_programme_key() {
+        let macros = Macros::parse("low = l").unwrap();
+        let result =
+            parse_programme_with_macros("g{&low}{#}", &macros, &Variables::default()).unwrap();
+        assert_eq!(
+            result.operators,
+            vec![Operator::GroupAggregate {
+                key: Key::Programme(Programme {
+                    operators: vec![Operator::Lowercase]
+                }),
+                aggregates: vec![Aggregate::Count],
+            }]
+        );
+    }
+
+    #[test]
+    fn group_followed_by_block() {
+        let result = parse_programme("g0{j}").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![
+                Operator::GroupBy(Key::Selection(Selection {
+                    items: vec![SelectItem::Index(0)]
+                })),
+                Operator::Block(Programme {
+                    operators: vec![Operator::Join]
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn group_aggregates_unknown_aggregate_error() {
+        let result = parse_programme("g0{#,median 2}");
+        assert_eq!(
+            result,
+            Err(
+                "parse error: expected aggregate (#, +, min, max or mean)\n  g0{#,median 2}\n       ^"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn group_aggregates_unclosed_error() {
+        let result = parse_programme("g0{#, +1");
+        assert_eq!(
+            result,
+            Err("parse error: expected ',' or closing '}'\n  g0{#, +1\n          ^".to_string())
+        );
+    }
+
+    #[test]
+    fn statistics() {
+        let result = parse_programme("%median%p99.9%describe").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![
+                Operator::Statistic(Statistic::Median),
+                Operator::Statistic(Statistic::Percentile(99.9)),
+                Operator::Describe,
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_statistic_error() {
+        let result = parse_programme("%mode");
+        assert_eq!(
+            result,
+            Err(
+                "parse error: expected statistic (min, max, mean, median, p<n>, stddev, var or describe)\n  %mode\n   ^"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn percentile_out_of_range_error() {
+        let result = parse_programme("%p101");
+        assert_eq!(
+            result,
+            Err("parse error: expected percentile from 0 to 100\n  %p101\n    ^".to_string())
+        );
+    }
+
+    #[test]
+    fn scans_and_windows() {
+        let result = parse_programme("\\+\\-\\/\\m5w3").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![
+                Operator::Scan(Scan::Sum),
+                Operator::Scan(Scan::Delta),
+                Operator::Scan(Scan::Rate),
+                Operator::Scan(Scan::MovingAverage(5)),
+                Operator::Window(3),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_scan_error() {
+        let result = parse_programme("\\*");
+        assert_eq!(
+            result,
+            Err("parse error: expected '+', '-', '/' or 'm<n>'\n  \\*\n   ^".to_string())
+        );
+    }
+
+    #[test]
+    fn zero_window_error() {
+        let result = parse_programme("w0");
+        assert_eq!(
+            result,
+            Err("parse error: expected window size\n  w0\n   ^".to_string())
+        );
+    }
+
+    #[test]
+    fn transpose_and_zip() {
+        let result = parse_programme("sZz").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![Operator::Split, Operator::Transpose, Operator::Zip]
+        );
+    }
+
+    #[test]
+    fn reverse_shuffle_and_sample() {
+        let result = parse_programme("v~;~5~0.25").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![
+                Operator::Reverse,
+                Operator::Shuffle,
+                Operator::NoOp,
+                Operator::Sample(Sample::Count(5)),
+                Operator::Sample(Sample::Fraction(0.25)),
+            ]
+        );
+    }
+
+    #[test]
+    fn sample_fraction_out_of_range_error() {
+        let result = parse_programme("~1.5");
+        assert_eq!(
+            result,
+            Err("parse error: expected fraction from 0 to 1\n  ~1.5\n   ^".to_string())
+        );
+    }
+
+    #[test]
+    fn join_input() {
+        let key = |i| Selection {
+            items: vec![SelectItem::Index(i)],
+        };
+        let result = parse_programme("*users:3=1*?users:0⋈!t2:1").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![
+                Operator::JoinInput {
+                    input: "users".to_string(),
+                    kind: JoinKind::Inner,
+                    left: key(3),
+                    right: key(1),
+                },
+                Operator::JoinInput {
+                    input: "users".to_string(),
+                    kind: JoinKind::Left,
+                    left: key(0),
+                    right: key(0),
+                },
+                Operator::JoinInput {
+                    input: "t2".to_string(),
+                    kind: JoinKind::Anti,
+                    left: key(1),
+                    right: key(1),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn set_input() {
+        let key = |i| Selection {
+            items: vec![SelectItem::Index(i)],
+        };
+        let result = parse_programme("*|a*&a*-a:0*^a:0=1").unwrap();
+        let set = |operation, key| Operator::SetInput {
+            input: "a".to_string(),
+            operation,
+            key,
+        };
+        assert_eq!(
+            result.operators,
+            vec![
+                set(SetOperation::Union, None),
+                set(SetOperation::Intersection, None),
+                set(SetOperation::Difference, Some((key(0), key(0)))),
+                set(SetOperation::SymmetricDifference, Some((key(0), key(1)))),
+            ]
+        );
+    }
+
+    #[test]
+    fn pattern_filter() {
+        let filter = |regex, negate, selection| Operator::PatternFilter {
+            input: "noise".to_string(),
+            regex,
+            negate,
+            selection,
+        };
+        let first = Selection {
+            items: vec![SelectItem::Index(0)],
+        };
+        let result = parse_programme("*/noise*!/noise:0*~/noise*!~/noise").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![
+                filter(false, false, None),
+                filter(false, true, Some(first)),
+                filter(true, false, None),
+                filter(true, true, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn map() {
+        let map = |selection, unmapped| Operator::Map {
+            input: "codes".to_string(),
+            selection,
+            unmapped,
+        };
+        let second = Selection {
+            items: vec![SelectItem::Index(1)],
+        };
+        let result = parse_programme(r#"*=codes;*=codes:1"-"*=!codes"#).unwrap();
+        assert_eq!(
+            result.operators,
+            vec![
+                map(None, Unmapped::Keep),
+                Operator::NoOp,
+                map(Some(second), Unmapped::Default("-".to_string())),
+                map(None, Unmapped::Drop),
+            ]
+        );
+    }
+
+    #[test]
+    fn arithmetic_unbraced_ends_before_other_operators() {
+        let result = parse_programme("=$2-$1+o").unwrap();
+        let field = |i| Box::new(Expr::Field(Some(i)));
+        assert_eq!(
+            result.operators,
+            vec![
+                Operator::Arithmetic(Expr::Binary(BinaryOp::Subtract, field(2), field(1))),
+                Operator::Sum,
+                Operator::SortDescending,
+            ]
+        );
+    }
+
+    #[test]
+    fn arithmetic_missing_operand_error() {
+        let result = parse_programme("={$1 * }");
+        assert_eq!(
+            result,
+            Err("parse error: expected <operand>\n  ={$1 * }\n         ^".to_string())
+        );
+    }
+
+    #[test]
+    fn format() {
+        use crate::ast::{Conversion, FormatPart, FormatSpec};
+        let result = parse_programme(r#"F"{{{-1}}}\t{1:|, %-08.2f}""#).unwrap();
+        assert_eq!(
+            result.operators,
+            vec![Operator::Format(vec![
+                FormatPart::Literal("{".to_string()),
+                FormatPart::Field {
+                    selection: Some(Selection {
+                        items: vec![SelectItem::Index(-1)],
+                    }),
+                    separator: None,
+                    spec: None,
+                },
+                FormatPart::Literal("}\t".to_string()),
+                FormatPart::Field {
+                    selection: Some(Selection {
+                        items: vec![SelectItem::Slice(Slice {
+                            start: Some(1),
+                            end: None,
+                            step: None,
+                        })],
+                    }),
+                    separator: Some(", ".to_string()),
+                    spec: Some(FormatSpec {
+                        left: true,
+                        sign: false,
+                        zero: true,
+                        width: Some(8),
+                        precision: Some(2),
+                        conversion: Conversion::Fixed,
+                    }),
+                },
+            ])]
+        );
+    }
+
+    #[test]
+    fn time() {
+        use crate::ast::{Bucket, TimeOp, TimeUnit};
+        let result = parse_programme(r#"k3:5p k/15m k0f-07:30"%H:%M" kd0,-1"#).unwrap();
+        assert_eq!(
+            result.operators,
+            vec![
+                Operator::Time {
+                    selection: Some(Selection {
+                        items: vec![SelectItem::Slice(Slice {
+                            start: Some(3),
+                            end: Some(5),
+                            step: None,
+                        })],
+                    }),
+                    op: TimeOp::Parse(None),
+                },
+                Operator::Time {
+                    selection: None,
+                    op: TimeOp::Truncate(Bucket {
+                        count: 15,
+                        unit: TimeUnit::Minute,
+                    }),
+                },
+                Operator::Time {
+                    selection: Some(Selection {
+                        items: vec![SelectItem::Index(0)],
+                    }),
+                    op: TimeOp::Format {
+                        offset: Some(-27000),
+                        pattern: "%H:%M".to_string(),
+                    },
+                },
+                Operator::Duration { from: 0, to: -1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn time_bad_pattern_error() {
+        let result = parse_programme(r#"kp"%Y-%Q""#);
+        assert_eq!(
+            result,
+            Err("parse error: expected valid strftime pattern\n  kp\"%Y-%Q\"\n    ^".to_string())
+        );
+    }
+
+    #[test]
+    fn format_bad_conversion_error() {
+        let result = parse_programme(r#"F"{0%5q}""#);
+        assert_eq!(
+            result,
+            Err(
+                "parse error: expected conversion (s, d, f, e, x) in template\n  F\"{0%5q}\"\n        ^"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn format_width_too_large_error() {
+        assert!(parse_programme(r#"F"{0%1000d}""#).is_ok());
+        let result = parse_programme(r#"F"{0%18446744073709551615d}""#);
+        assert_eq!(
+            result,
+            Err("parse error: expected <width> of at most 1000 in template\n  F\"{0%18446744073709551615d}\"\n       ^".to_string())
+        );
+    }
+
+    #[test]
+    fn format_precision_too_large_error() {
+        assert!(parse_programme(r#"F"{0%.1000f}""#).is_ok());
+        let result = parse_programme(r#"F"{0%.18446744073709551615f}""#);
+        assert_eq!(
+            result,
+            Err("parse error: expected <precision> of at most 1000 in template\n  F\"{0%.18446744073709551615f}\"\n        ^".to_string())
+        );
+    }
+
+    #[test]
+    fn command() {
+        let result = parse_programme(r#"!{base64 -d}!|{awk '{print $1}'}"#).unwrap();
+        assert_eq!(
+            result.operators,
+            vec![
+                Operator::Command {
+                    command: "base64 -d".to_string(),
+                    batched: false,
+                },
+                Operator::Command {
+                    command: "awk '{print $1}'".to_string(),
+                    batched: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn command_unclosed_error() {
+        let result = parse_programme("!{awk '{print}'");
+        assert_eq!(
+            result,
+            Err(
+                "parse error: expected closing '}'\n  !{awk '{print}'\n                 ^"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn join_input_missing_key_error() {
+        let result = parse_programme("*users");
+        assert_eq!(
+            result,
+            Err("parse error: expected ':' and key <selection>\n  *users\n        ^".to_string())
+        );
+    }
+
+    #[test]
+    fn enumerate() {
+        let result = parse_programme("e0").unwrap();
+        assert_eq!(
+            result.operators,
+            vec![
+                Operator::Enumerate,
+                Operator::Selection(Selection {
+                    items: vec![SelectItem::Index(0)]
+                })
+            ]
+        );
+    }
 }
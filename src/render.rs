@@ -0,0 +1,380 @@
+//! Structured output formats, given with `--output <format>`.
+//!
+//! Each format is a [`Renderer`] of the final value of a programme. The table
+//! formats read the value as rows: an array of arrays is a row per element
+//! with a cell per field, an array of anything else a row of one cell per
+//! element, and anything else a single cell.
+
+use std::io::{self, Write};
+
+use crate::value::Value;
+
+/// Writes a value in an output format.
+pub trait Renderer {
+    fn render(&self, value: &Value, out: &mut dyn Write) -> io::Result<()>;
+}
+
+/// An output format.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Format {
+    /// Comma-separated values, quoted where needed
+    Csv,
+    /// Tab-separated values, quoted where needed
+    Tsv,
+    /// A line of JSON per element
+    Ndjson,
+    /// A Markdown table
+    Markdown,
+    /// An HTML table
+    Html,
+    /// A YAML sequence
+    Yaml,
+}
+
+impl Format {
+    /// The renderer of the format. With `header`, the table formats that
+    /// distinguish a header take the first row as theirs.
+    pub fn renderer(self, header: bool) -> Box<dyn Renderer> {
+        match self {
+            Format::Csv => Box::new(Delimited { delimiter: b',' }),
+            Format::Tsv => Box::new(Delimited { delimiter: b'\t' }),
+            Format::Ndjson => Box::new(Ndjson),
+            Format::Markdown => Box::new(Markdown { header }),
+            Format::Html => Box::new(Html { header }),
+            Format::Yaml => Box::new(Yaml),
+        }
+    }
+}
+
+/// The rows of a value, as the text of their cells.
+fn rows(value: &Value) -> Vec<Vec<String>> {
+    match value {
+        Value::Array(arr) => arr
+            .elements
+            .iter()
+            .map(|elem| match elem {
+                Value::Array(row) => row.elements.iter().map(Value::to_string).collect(),
+                other => vec![other.to_string()],
+            })
+            .collect(),
+        other => vec![vec![other.to_string()]],
+    }
+}
+
+/// Split off the header row, if there is one, and pad every row to the width
+/// of the widest.
+fn table(value: &Value, header: bool) -> (Vec<String>, Vec<Vec<String>>) {
+    let mut rows = rows(value);
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    for row in &mut rows {
+        row.resize(columns, String::new());
+    }
+    let head = if header && !rows.is_empty() {
+        rows.remove(0)
+    } else {
+        vec![String::new(); columns]
+    };
+    (head, rows)
+}
+
+/// CSV or TSV.
+struct Delimited {
+    delimiter: u8,
+}
+
+impl Renderer for Delimited {
+    fn render(&self, value: &Value, out: &mut dyn Write) -> io::Result<()> {
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(self.delimiter)
+            .flexible(true)
+            .from_writer(out);
+        for row in rows(value) {
+            writer.write_record(&row)?;
+        }
+        writer.flush()
+    }
+}
+
+struct Ndjson;
+
+impl Renderer for Ndjson {
+    fn render(&self, value: &Value, out: &mut dyn Write) -> io::Result<()> {
+        let elements = match value {
+            Value::Array(arr) => arr.elements.iter().collect(),
+            other => vec![other],
+        };
+        for elem in elements {
+            serde_json::to_writer(&mut *out, elem)?;
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+/// A Markdown table, its columns aligned. Markdown tables must have a header,
+/// so without one it is left empty.
+struct Markdown {
+    header: bool,
+}
+
+impl Renderer for Markdown {
+    fn render(&self, value: &Value, out: &mut dyn Write) -> io::Result<()> {
+        let escape = |cell: &str| {
+            cell.replace('\\', "\\\\")
+                .replace('|', "\\|")
+                .replace("\r\n", "<br>")
+                .replace('\n', "<br>")
+        };
+        let (head, rows) = table(value, self.header);
+        if head.is_empty() {
+            return Ok(());
+        }
+        let head: Vec<String> = head.iter().map(|cell| escape(cell)).collect();
+        let rows: Vec<Vec<String>> = rows
+            .iter()
+            .map(|row| row.iter().map(|cell| escape(cell)).collect())
+            .collect();
+        let widths: Vec<usize> = (0..head.len())
+            .map(|i| {
+                rows.iter()
+                    .chain([&head])
+                    .map(|row| row[i].chars().count())
+                    .max()
+                    .unwrap_or(0)
+                    .max(3)
+            })
+            .collect();
+        let write_row = |out: &mut dyn Write, row: &[String]| {
+            write!(out, "|")?;
+            for (cell, width) in row.iter().zip(&widths) {
+                let padding = width - cell.chars().count();
+                write!(out, " {}{} |", cell, " ".repeat(padding))?;
+            }
+            writeln!(out)
+        };
+        write_row(out, &head)?;
+        let rule: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+        write_row(out, &rule)?;
+        for row in &rows {
+            write_row(out, row)?;
+        }
+        Ok(())
+    }
+}
+
+/// An HTML table, with a `<thead>` if there is a header.
+struct Html {
+    header: bool,
+}
+
+impl Renderer for Html {
+    fn render(&self, value: &Value, out: &mut dyn Write) -> io::Result<()> {
+        let escape = |cell: &str| {
+            cell.replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+                .replace('"', "&quot;")
+                .replace('\'', "&#39;")
+        };
+        let write_row = |out: &mut dyn Write, row: &[String], tag: &str| {
+            write!(out, "    <tr>")?;
+            for cell in row {
+                write!(out, "<{}>{}</{}>", tag, escape(cell), tag)?;
+            }
+            writeln!(out, "</tr>")
+        };
+        let (head, rows) = table(value, self.header);
+        writeln!(out, "<table>")?;
+        if self.header && !head.is_empty() {
+            writeln!(out, "  <thead>")?;
+            write_row(out, &head, "th")?;
+            writeln!(out, "  </thead>")?;
+        }
+        writeln!(out, "  <tbody>")?;
+        for row in &rows {
+            write_row(out, row, "td")?;
+        }
+        writeln!(out, "  </tbody>")?;
+        writeln!(out, "</table>")
+    }
+}
+
+/// YAML: arrays as block sequences, nested as deep as the value is.
+struct Yaml;
+
+impl Renderer for Yaml {
+    fn render(&self, value: &Value, out: &mut dyn Write) -> io::Result<()> {
+        for line in yaml_lines(value) {
+            writeln!(out, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+fn yaml_lines(value: &Value) -> Vec<String> {
+    match value {
+        Value::Array(arr) if arr.elements.is_empty() => vec!["[]".to_string()],
+        Value::Array(arr) => {
+            let mut lines = Vec::new();
+            for elem in &arr.elements {
+                for (i, line) in yaml_lines(elem).into_iter().enumerate() {
+                    let indent = if i == 0 { "- " } else { "  " };
+                    lines.push(format!("{}{}", indent, line));
+                }
+            }
+            lines
+        }
+        Value::Number(n) if n.is_nan() => vec![".nan".to_string()],
+        Value::Number(n) if n.is_infinite() => {
+            vec![if *n > 0.0 { ".inf" } else { "-.inf" }.to_string()]
+        }
+        Value::Number(n) => vec![n.to_string()],
+        Value::Text(s) => vec![yaml_string(s)],
+    }
+}
+
+/// A string as a YAML scalar: plain if it would read back as the same
+/// string, and otherwise double-quoted.
+///
+/// Strings starting with a digit are always quoted, as YAML reads many of them,
+/// such as `0x1F`, `1_000` and `2001-12-14`, as numbers or dates. Strings
+/// starting with `+`, `-` or `.` aren't plain either.
+fn yaml_string(s: &str) -> String {
+    const RESERVED: &[&str] = &[
+        "true", "false", "yes", "no", "on", "off", "y", "n", "null", "~",
+    ];
+    let plain = s.starts_with(|c: char| c.is_alphabetic() || c == '_' || c == '/')
+        && !s.ends_with(' ')
+        && s.chars()
+            .all(|c| c.is_alphanumeric() || " _-./@()+".contains(c))
+        && s.parse::<f64>().is_err()
+        && !RESERVED.contains(&s.to_lowercase().as_str());
+    if plain {
+        s.to_string()
+    } else {
+        serde_json::to_string(s).expect("strings serialise")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::{Array, Level};
+
+    fn table_value(rows: &[&[&str]]) -> Value {
+        Value::Array(Array::from((
+            rows.iter()
+                .map(|row| {
+                    Value::Array(Array::from((
+                        row.iter().map(|s| Value::Text(s.to_string())).collect(),
+                        Level::Word,
+                    )))
+                })
+                .collect(),
+            Level::Line,
+        )))
+    }
+
+    fn render(format: Format, header: bool, value: &Value) -> String {
+        let mut out = Vec::new();
+        format.renderer(header).render(value, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn csv_and_tsv_quoting() {
+        let value = table_value(&[&["a,b", "say \"hi\""], &["tab\there", "x"]]);
+        assert_eq!(
+            render(Format::Csv, false, &value),
+            "\"a,b\",\"say \"\"hi\"\"\"\ntab\there,x\n"
+        );
+        assert_eq!(
+            render(Format::Tsv, false, &value),
+            "a,b\t\"say \"\"hi\"\"\"\n\"tab\there\"\tx\n"
+        );
+    }
+
+    #[test]
+    fn ndjson() {
+        let value = Value::Array(Array::from((
+            vec![
+                Value::Text("a\"b".to_string()),
+                Value::Number(2.5),
+                table_value(&[&["x", "y"]]),
+            ],
+            Level::Line,
+        )));
+        assert_eq!(
+            render(Format::Ndjson, false, &value),
+            "\"a\\\"b\"\n2.5\n[[\"x\",\"y\"]]\n"
+        );
+    }
+
+    #[test]
+    fn markdown_with_header() {
+        let value = table_value(&[&["name", "shell"], &["root", "/bin/a|b"], &["x"]]);
+        assert_eq!(
+            render(Format::Markdown, true, &value),
+            "| name | shell     |\n\
+             | ---- | --------- |\n\
+             | root | /bin/a\\|b |\n\
+             | x    |           |\n"
+        );
+    }
+
+    #[test]
+    fn markdown_without_header() {
+        let value = table_value(&[&["a", "b"]]);
+        assert_eq!(
+            render(Format::Markdown, false, &value),
+            "|     |     |\n| --- | --- |\n| a   | b   |\n"
+        );
+    }
+
+    #[test]
+    fn html_escaping() {
+        let value = table_value(&[&["<b>", "x"], &["A&B", "'q'"]]);
+        assert_eq!(
+            render(Format::Html, true, &value),
+            "<table>\n  <thead>\n    <tr><th>&lt;b&gt;</th><th>x</th></tr>\n  </thead>\n  \
+             <tbody>\n    <tr><td>A&amp;B</td><td>&#39;q&#39;</td></tr>\n  </tbody>\n</table>\n"
+        );
+    }
+
+    #[test]
+    fn yaml_nesting_and_quoting() {
+        let value = Value::Array(Array::from((
+            vec![
+                table_value(&[&["GET", "true"], &["a: b"]]),
+                Value::Number(3.0),
+                Value::Text("12".to_string()),
+                Value::Array(Array::new(Level::Word)),
+            ],
+            Level::Line,
+        )));
+        assert_eq!(
+            render(Format::Yaml, false, &value),
+            "- - - GET\n    - \"true\"\n  - - \"a: b\"\n- 3\n- \"12\"\n- []\n"
+        );
+    }
+
+    #[test]
+    fn yaml_quotes_strings_read_as_numbers_or_dates() {
+        let strings = [
+            "0x1F",
+            "1_000",
+            "2001-12-14",
+            "0b101",
+            "1:20",
+            "+1",
+            "-1",
+            ".5",
+            ".inf",
+        ];
+        for s in strings {
+            assert_eq!(yaml_string(s), format!("\"{}\"", s));
+        }
+        assert_eq!(yaml_string("x1_000"), "x1_000");
+        assert_eq!(yaml_string("_0x1F"), "_0x1F");
+    }
+}
@@ -12,6 +12,7 @@ use std::fmt;
 use std::fs;
 use std::io::{self, BufRead, BufReader, Read};
 use std::path::Path;
+use std::sync::Arc;
 
 use serde::Serialize;
 use serde::ser::{SerializeSeq, Serializer};
@@ -193,12 +194,36 @@ impl Ord for Value {
 }
 
 /// An array with semantic level.
-#[derive(Debug, PartialEq, Eq)]
+///
+/// Arrays read from input also remember where each line came from. These
+/// origins are hidden: they don't affect equality or output, and are only
+/// trusted while there is one per element. Operators that drop or reorder
+/// elements without changing their number must carry the origins along
+/// (see [`Array::retain`] and [`Array::take_origins`]) or they are lost.
+#[derive(Debug)]
 pub struct Array {
     pub level: Level,
     pub elements: Vec<Value>,
+    origins: Option<Vec<Origin>>,
+}
+
+/// Where an input line came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    /// The file the line was read from, or None for stdin.
+    pub file: Option<Arc<str>>,
+    /// The 1-based line number within the file.
+    pub line: usize,
+}
+
+impl PartialEq for Array {
+    fn eq(&self, other: &Self) -> bool {
+        self.level == other.level && self.elements == other.elements
+    }
 }
 
+impl Eq for Array {}
+
 impl Array {
     /// Create an explicit deep copy of this array.
     ///
@@ -208,6 +233,7 @@ impl Array {
         Self {
             level: self.level,
             elements: self.elements.iter().map(|v| v.deep_copy()).collect(),
+            origins: self.origins().map(<[Origin]>::to_vec),
         }
     }
 
@@ -221,6 +247,9 @@ impl Array {
                 .take(limit)
                 .map(|v| v.deep_copy())
                 .collect(),
+            origins: self
+                .origins()
+                .map(|origins| origins.iter().take(limit).cloned().collect()),
         }
     }
 
@@ -229,6 +258,7 @@ impl Array {
         Self {
             level,
             elements: Vec::new(),
+            origins: None,
         }
     }
 
@@ -240,15 +270,55 @@ impl Array {
         Self::from_reader(stdin.lock(), level)
     }
 
+    /// Read the lines of stdin one at a time, each with its line number.
+    pub fn stdin_lines() -> impl Iterator<Item = io::Result<(Value, Origin)>> {
+        io::stdin().lock().lines().enumerate().map(|(i, line)| {
+            line.map(|line| {
+                (
+                    Value::Text(line),
+                    Origin {
+                        file: None,
+                        line: i + 1,
+                    },
+                )
+            })
+        })
+    }
+
+    /// Read the lines of files one at a time, each with its file and line
+    /// number, as `from_files` would load them at `Level::Line`.
+    pub fn file_lines(
+        paths: &[impl AsRef<Path>],
+    ) -> impl Iterator<Item = io::Result<(Value, Origin)>> {
+        paths.iter().flat_map(|path| {
+            let name: Arc<str> = path.as_ref().to_string_lossy().into();
+            let lines: Box<dyn Iterator<Item = io::Result<String>>> = match fs::File::open(path) {
+                Ok(file) => Box::new(BufReader::new(file).lines()),
+                Err(e) => Box::new(std::iter::once(Err(e))),
+            };
+            lines.enumerate().map(move |(i, line)| {
+                let origin = Origin {
+                    file: Some(name.clone()),
+                    line: i + 1,
+                };
+                line.map(|line| (Value::Text(line), origin))
+            })
+        })
+    }
+
     /// Load input from files.
     /// - `Level::File`: each file as one Text element
     /// - `Level::Line`: all files split into lines, concatenated
+    ///
+    /// Lines remember their file and line number.
     pub fn from_files(paths: &[impl AsRef<Path>], level: Level) -> io::Result<Self> {
         let mut elements = Vec::new();
+        let mut origins = Vec::new();
 
         for path in paths {
             let file = fs::File::open(path)?;
             let reader = BufReader::new(file);
+            let name: Arc<str> = path.as_ref().to_string_lossy().into();
 
             match level {
                 Level::File => {
@@ -263,19 +333,29 @@ impl Array {
                     elements.push(Value::Text(contents));
                 }
                 _ => {
-                    for line in reader.lines() {
+                    for (i, line) in reader.lines().enumerate() {
                         elements.push(Value::Text(line?));
+                        origins.push(Origin {
+                            file: Some(name.clone()),
+                            line: i + 1,
+                        });
                     }
                 }
             }
         }
 
-        Ok(Self { level, elements })
+        let origins = (level != Level::File).then_some(origins);
+        Ok(Self {
+            level,
+            elements,
+            origins,
+        })
     }
 
     /// Load from a reader.
     fn from_reader<R: BufRead>(reader: R, level: Level) -> io::Result<Self> {
         let mut elements = Vec::new();
+        let mut origins = None;
 
         match level {
             Level::File => {
@@ -294,10 +374,16 @@ impl Array {
                 for line in reader.lines() {
                     elements.push(Value::Text(line?));
                 }
+                let lines = (1..=elements.len()).map(|line| Origin { file: None, line });
+                origins = Some(lines.collect());
             }
         }
 
-        Ok(Self { level, elements })
+        Ok(Self {
+            level,
+            elements,
+            origins,
+        })
     }
 
     /// Get element by index. Negative indices count from end.
@@ -323,13 +409,86 @@ impl Array {
 
     /// Sort the array in place.
     pub fn sort(&mut self, descending: bool) {
-        if descending {
+        if let Some(origins) = self.take_origins() {
+            let mut paired: Vec<_> = self.elements.drain(..).zip(origins).collect();
+            if descending {
+                paired.sort_by(|a, b| b.0.cmp(&a.0));
+            } else {
+                paired.sort_by(|a, b| a.0.cmp(&b.0));
+            }
+            let (elements, origins) = paired.into_iter().unzip();
+            self.elements = elements;
+            self.origins = Some(origins);
+        } else if descending {
             self.elements.sort_by(|a, b| b.cmp(a));
         } else {
             self.elements.sort();
         }
     }
 
+    /// Reverse the order of the elements, along with their origins.
+    pub fn reverse(&mut self) {
+        if let Some(origins) = self.origins.as_mut() {
+            origins.reverse();
+        }
+        self.elements.reverse();
+    }
+
+    /// Keep only the elements for which `keep` returns true, along with their origins.
+    pub fn retain(&mut self, mut keep: impl FnMut(&Value) -> bool) {
+        match self.take_origins() {
+            Some(origins) => {
+                let (elements, origins) = self
+                    .elements
+                    .drain(..)
+                    .zip(origins)
+                    .filter(|(elem, _)| keep(elem))
+                    .unzip();
+                self.elements = elements;
+                self.origins = Some(origins);
+            }
+            None => self.elements.retain(keep),
+        }
+    }
+
+    /// Replace each element with the result of `f`, dropping those for which
+    /// it returns None, along with their origins.
+    pub fn filter_map(&mut self, mut f: impl FnMut(Value) -> Option<Value>) {
+        match self.take_origins() {
+            Some(origins) => {
+                let (elements, origins) = self
+                    .elements
+                    .drain(..)
+                    .zip(origins)
+                    .filter_map(|(elem, origin)| Some((f(elem)?, origin)))
+                    .unzip();
+                self.elements = elements;
+                self.origins = Some(origins);
+            }
+            None => self.elements = self.elements.drain(..).filter_map(f).collect(),
+        }
+    }
+
+    /// Where each element came from, if the array was read from input and
+    /// still has one origin per element.
+    pub fn origins(&self) -> Option<&[Origin]> {
+        self.origins
+            .as_deref()
+            .filter(|origins| origins.len() == self.elements.len())
+    }
+
+    /// Remove the origins, to be restored with [`Array::set_origins`] once the
+    /// elements have been rearranged to match.
+    pub fn take_origins(&mut self) -> Option<Vec<Origin>> {
+        let origins = self.origins.take()?;
+        (origins.len() == self.elements.len()).then_some(origins)
+    }
+
+    /// Set the origins of the elements, one per element.
+    pub fn set_origins(&mut self, origins: Option<Vec<Origin>>) {
+        self.origins = origins;
+    }
+
     /// Returns an iterator over the elements.
     pub fn iter(&self) -> impl Iterator<Item = &Value> {
         self.elements.iter()
@@ -338,7 +497,11 @@ impl Array {
 
 impl From<(Vec<Value>, Level)> for Array {
     fn from((elements, level): (Vec<Value>, Level)) -> Self {
-        Self { level, elements }
+        Self {
+            level,
+            elements,
+            origins: None,
+        }
     }
 }
 
@@ -651,4 +814,28 @@ mod tests {
         std::fs::remove_file(&path1).unwrap();
         std::fs::remove_file(&path2).unwrap();
     }
+
+    #[test]
+    fn from_reader_records_line_origins() {
+        let arr = Array::from_reader(io::Cursor::new("a\nb\n"), Level::Line).unwrap();
+        let lines: Vec<_> = arr.origins().unwrap().iter().map(|o| o.line).collect();
+        assert_eq!(lines, vec![1, 2]);
+        assert!(arr.origins().unwrap()[0].file.is_none());
+    }
+
+    #[test]
+    fn origins_follow_retain_and_sort() {
+        let mut arr = Array::from_reader(io::Cursor::new("c\n\na\nb\n"), Level::Line).unwrap();
+        arr.retain(|v| v != &Value::Text(String::new()));
+        arr.sort(false);
+        let lines: Vec<_> = arr.origins().unwrap().iter().map(|o| o.line).collect();
+        assert_eq!(lines, vec![3, 4, 1]);
+    }
+
+    #[test]
+    fn origins_are_dropped_when_lengths_differ() {
+        let mut arr = Array::from_reader(io::Cursor::new("a\nb\n"), Level::Line).unwrap();
+        arr.elements.pop();
+        assert!(arr.origins().is_none());
+    }
 }
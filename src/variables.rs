@@ -0,0 +1,221 @@
+//! Programme variables, given with `--arg <name>=<value>`.
+//!
+//! Regexes, replacements and quoted strings refer to a variable as `{$name}`,
+//! and to an environment variable as `{$env.NAME}`. References are substituted
+//! at parse time. `{$name}` inserts the value as literal text, quoted so that
+//! a regex matches it exactly and a replacement inserts it as is; `{$!name}`
+//! inserts it raw, so a regex reads it as regex syntax.
+
+use std::collections::HashMap;
+
+use winnow::Parser;
+
+use crate::parser::macro_name;
+
+/// A set of variables.
+#[derive(Debug, Clone, Default)]
+pub struct Variables {
+    values: HashMap<String, String>,
+}
+
+/// A `{$name}` reference to a variable.
+#[derive(Debug, Clone, PartialEq)]
+struct Reference<'a> {
+    name: &'a str,
+    /// Whether the reference is to an environment variable: `{$env.NAME}`
+    env: bool,
+    /// Whether to insert the value unquoted: `{$!name}`
+    raw: bool,
+}
+
+/// How the value of a reference is quoted where it is inserted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quoting {
+    /// In a regex, escaping regex syntax
+    Regex,
+    /// In a replacement, escaping `$`
+    Replacement,
+    /// In a quoted string, as is
+    Literal,
+}
+
+impl Variables {
+    /// Load variables from `<name>=<value>` specs.
+    pub fn load(specs: &[String]) -> Result<Self, String> {
+        let mut variables = Self::default();
+        for spec in specs {
+            let Some((name, value)) = spec.split_once('=') else {
+                return Err(format!("{}: expected <name>=<value>", spec));
+            };
+            if macro_name.parse(name).is_err() {
+                return Err(format!("invalid variable name '{}'", name));
+            }
+            if variables.values.contains_key(name) {
+                return Err(format!("duplicate variable '{}'", name));
+            }
+            variables.insert(name, value);
+        }
+        Ok(variables)
+    }
+
+    /// Set a variable, replacing any of that name.
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.values.insert(name.to_string(), value.to_string());
+    }
+
+    /// Get the value of a variable.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    /// Substitute the references in `text`, quoting each value for where it
+    /// is inserted. Errors name the first reference that has no value.
+    pub fn substitute(&self, text: &str, quoting: Quoting) -> Result<String, String> {
+        replace_references(text, |reference| {
+            let value = if reference.env {
+                std::env::var(reference.name)
+                    .map_err(|_| format!("environment variable {} is not set", reference.name))?
+            } else {
+                self.get(reference.name)
+                    .ok_or_else(|| {
+                        format!(
+                            "unknown variable '{}' (set one with --arg {}=<value>)",
+                            reference.name, reference.name
+                        )
+                    })?
+                    .to_string()
+            };
+            Ok(match quoting {
+                _ if reference.raw => value,
+                Quoting::Regex => regex::escape(&value),
+                Quoting::Replacement => value.replace('$', "$$"),
+                Quoting::Literal => value,
+            })
+        })
+    }
+}
+
+/// Whether `text` contains any references.
+pub fn has_references(text: &str) -> bool {
+    replace_references(text, |_| Err(String::new())).is_err()
+}
+
+/// Replace each reference in `text` with the result of `f`, stopping at the
+/// first error. Anything else, including a `{$` not starting a well-formed
+/// reference, is left as it is.
+fn replace_references(
+    text: &str,
+    mut f: impl FnMut(Reference) -> Result<String, String>,
+) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{$") {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match reference(rest) {
+            Some((reference, len)) => {
+                result.push_str(&f(reference)?);
+                rest = &rest[len..];
+            }
+            None => {
+                result.push_str("{$");
+                rest = &rest[2..];
+            }
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// The reference at the start of `text`, and its length.
+fn reference(text: &str) -> Option<(Reference<'_>, usize)> {
+    let body = text.strip_prefix("{$")?;
+    let (raw, body) = match body.strip_prefix('!') {
+        Some(body) => (true, body),
+        None => (false, body),
+    };
+    let (env, body) = match body.strip_prefix("env.") {
+        Some(body) => (true, body),
+        None => (false, body),
+    };
+    let end = body.find('}')?;
+    let name = &body[..end];
+    if macro_name.parse(name).is_err() {
+        return None;
+    }
+    let len = text.len() - body.len() + end + 1;
+    Some((Reference { name, env, raw }, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables() -> Variables {
+        let mut variables = Variables::default();
+        variables.insert("ip", "1.2.3.4");
+        variables.insert("price", "$5");
+        variables
+    }
+
+    #[test]
+    fn substitute_quoted() {
+        let variables = variables();
+        assert_eq!(
+            variables.substitute("^{$ip}/", Quoting::Regex).unwrap(),
+            r"^1\.2\.3\.4/"
+        );
+        assert_eq!(
+            variables
+                .substitute("cost {$price}", Quoting::Replacement)
+                .unwrap(),
+            "cost $$5"
+        );
+        assert_eq!(
+            variables.substitute("{$price}", Quoting::Literal).unwrap(),
+            "$5"
+        );
+    }
+
+    #[test]
+    fn substitute_raw() {
+        assert_eq!(
+            variables().substitute("^{$!ip}$", Quoting::Regex).unwrap(),
+            "^1.2.3.4$"
+        );
+    }
+
+    #[test]
+    fn malformed_references_are_left_alone() {
+        assert_eq!(
+            variables()
+                .substitute("{$} {$1} {$ip {$$}", Quoting::Regex)
+                .unwrap(),
+            "{$} {$1} {$ip {$$}"
+        );
+        assert!(!has_references("a{$}b"));
+        assert!(has_references("a{$!env.HOME}b"));
+    }
+
+    #[test]
+    fn unknown_variable_error() {
+        assert_eq!(
+            variables()
+                .substitute("{$host}", Quoting::Regex)
+                .unwrap_err(),
+            "unknown variable 'host' (set one with --arg host=<value>)"
+        );
+    }
+
+    #[test]
+    fn load_errors() {
+        let load = |spec: &str| Variables::load(&[spec.to_string()]).unwrap_err();
+        assert_eq!(load("ip"), "ip: expected <name>=<value>");
+        assert_eq!(load("1ip=x"), "invalid variable name '1ip'");
+        let specs = ["ip=a".to_string(), "ip=b".to_string()];
+        assert_eq!(
+            Variables::load(&specs).unwrap_err(),
+            "duplicate variable 'ip'"
+        );
+    }
+}